@@ -32,6 +32,8 @@ lines = f.readlines()
 setup_start = 0
 setup_end = 0
 send_start = 0
+send_end = 0
+messages = 0
 
 for i in range(len(lines)):
     if " - " in lines[i]:
@@ -41,8 +43,11 @@ for i in range(len(lines)):
         if info[1] == "Trigger send\n":
             setup_end = int(lines[i-1].split(" - ")[0])
             send_start = int(info[0])
-
-send_end = int(lines[-1].split(" - ")[0])
+        # The send ends with the last delivery, whichever the protocol.
+        if " delivered : " in info[1]:
+            send_end = int(info[0])
+        if info[1].startswith("Summary : "):
+            messages = int(info[1].split(", ")[2].split(" ")[0])
 
 result = ""
 
@@ -54,4 +59,5 @@ else:
 with open(f"results_{id}.log", "w") as result_file:
     result_file.write(result)
     result_file.write(f"Setup time : {setup_end-setup_start} seconds\n")
-    result_file.write(f"Send time : {send_end-send_start} seconds")
+    result_file.write(f"Send time : {send_end-send_start} seconds\n")
+    result_file.write(f"Messages : {messages}")
@@ -1,21 +1,43 @@
+import argparse
 import subprocess
 import time
 
-for i in range(100):
-    print(f"{i}:")
-    rendezvous = subprocess.Popen(["cargo", "run", "--bin", "rendezvous"], stdin=subprocess.PIPE)
-    time.sleep(10)
-    f = open(f"output_{i}.log", "w")
-    sbr = subprocess.Popen(["cargo", "run"], stdin=subprocess.PIPE, stdout=f)
-    time.sleep(100)
-    print("Send trigger")
-    try:
-        sbr.communicate(input="send\n".encode(), timeout=10)
-    except subprocess.TimeoutExpired:
-        print("timeout")
-    time.sleep(120)
-    print("Will stop")
-    sbr.kill()
-    rendezvous.kill()
-    subprocess.run(["sh", "run_clean.sh", f"{i}"])
-    subprocess.run(["python", "parse_output.py", "-n", "100", "-i", f"{i}"])
+parser = argparse.ArgumentParser(description="Run the Broadcast several times and check the deliveries.")
+parser.add_argument("-p", type=str, default="sbr", choices=["sbr", "bracha"], help="Protocol to run.")
+parser.add_argument("-r", type=int, default=100, help="Number of runs.")
+parser.add_argument("-n", type=int, default=100, help="Size of the system, N.")
+
+args = parser.parse_args()
+
+# The protocol is selected in the configuration, the previous one is restored at the end.
+config = open("broadcast.config").read()
+lines = [line for line in config.split("\n") if not line.startswith("protocol=")]
+open("broadcast.config", "w").write("\n".join(lines).rstrip("\n") + f"\nprotocol={args.p}\n")
+
+try:
+    for i in range(args.r):
+        run = f"{args.p}_{i}"
+        print(f"{run}:")
+        rendezvous = subprocess.Popen(["cargo", "run", "--bin", "rendezvous"], stdin=subprocess.PIPE)
+        time.sleep(10)
+        f = open(f"output_{run}.log", "w")
+        sbr = subprocess.Popen(["cargo", "run"], stdin=subprocess.PIPE, stdout=f)
+        # Wait for every node to have its subscriptions acknowledged.
+        while "System ready" not in open(f"output_{run}.log").read():
+            time.sleep(1)
+        print("Send trigger")
+        sbr.stdin.write("send\n".encode())
+        sbr.stdin.flush()
+        time.sleep(120)
+        print("Will stop")
+        # SIGTERM lets the nodes print their summary, with the messages they received.
+        sbr.terminate()
+        try:
+            sbr.wait(timeout=30)
+        except subprocess.TimeoutExpired:
+            sbr.kill()
+        rendezvous.kill()
+        subprocess.run(["sh", "run_clean.sh", run])
+        subprocess.run(["python", "parse_output.py", "-n", str(args.n), "-i", run])
+finally:
+    open("broadcast.config", "w").write(config)
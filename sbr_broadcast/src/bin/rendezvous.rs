@@ -1,5 +1,229 @@
-use std::{fs, io};
+#[macro_use]
+#[path = "../my_macros.rs"]
+mod my_macros;
+// The server only uses the allowlist and the registry, the Nodes use the rest of these modules.
+#[allow(dead_code)]
+#[path = "../membership.rs"]
+mod membership;
+#[allow(dead_code)]
+#[path = "../registry.rs"]
+mod registry;
+
+use membership::Allowlist;
+use registry::{Registrations, Registry, Request, Response};
+use std::collections::HashSet;
+use std::fs;
+use std::process;
+use std::sync::Arc;
+use talk::crypto::primitives::sign::Signature;
+use talk::crypto::{Identity, KeyCard};
 use talk::link::rendezvous::{Server, ServerSettings};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Mutex;
+
+/// Registry of the server and the KeyCards it admits.
+struct Gate {
+    registry: Registry,
+    /// The Identities allowed to register, if restricted.
+    allowlist: Option<Allowlist>,
+    /// The Identities already refused.
+    rejected: HashSet<Identity>,
+    /// The file in which the registrations are persisted.
+    state_file: String,
+}
+
+impl Gate {
+    /// Whether a KeyCard may be registered, logging it the first time it is refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard in which the KeyCard is registered.
+    /// * `keycard` - The KeyCard.
+    ///
+    fn authorized(&mut self, shard: u32, keycard: &KeyCard) -> bool {
+        match &self.allowlist {
+            Some(allowlist) if !allowlist.identities.contains(&keycard.identity()) => {
+                if self.rejected.insert(keycard.identity()) {
+                    println!(
+                        "ERROR : shard {} : rejected unauthorized KeyCard {:?}",
+                        shard,
+                        keycard.identity()
+                    );
+                }
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Register a KeyCard, refusing it before it takes a slot of its shard if it is not allowed or its
+    /// KeyChain did not sign the registration. A new registration is persisted right away, whether its
+    /// shard is full or still filling.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard in which the KeyCard is registered.
+    /// * `keycard` - The KeyCard.
+    /// * `signature` - The signature of the registration by the KeyChain of the KeyCard.
+    ///
+    fn register(&mut self, shard: u32, keycard: KeyCard, signature: &Signature) -> Response {
+        if !self.authorized(shard, &keycard) {
+            return Response::Error {
+                message: format!("{:?} is not allowed to register", keycard.identity()),
+            };
+        }
+        let registered = self.registry.registrations.get(&shard).map(|k| k.len());
+        let response = self.registry.register(shard, keycard, signature);
+        if self.registry.registrations.get(&shard).map(|k| k.len()) != registered {
+            store_registrations(&self.state_file, &self.registry.registrations);
+        }
+        response
+    }
+
+    /// Register again a KeyCard persisted by a previous run, whose registration was signed then, if it
+    /// is still allowed.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard in which the KeyCard is registered.
+    /// * `keycard` - The KeyCard.
+    ///
+    fn restore(&mut self, shard: u32, keycard: KeyCard) -> Response {
+        if !self.authorized(shard, &keycard) {
+            return Response::Error {
+                message: format!("{:?} is not allowed to register", keycard.identity()),
+            };
+        }
+        self.registry.admit(shard, keycard)
+    }
+}
+
+/// Load the registrations persisted by a previous run of the server.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the registrations are persisted.
+///
+fn load_registrations(path: &str) -> Registrations {
+    match fs::read_to_string(path) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(registrations) => registrations,
+            Err(e) => {
+                println!("ERROR : parse {} : {}", path, e);
+                Registrations::new()
+            }
+        },
+        Err(_) => Registrations::new(),
+    }
+}
+
+/// Persist the registrations of the shards.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the registrations are persisted.
+/// * `registrations` - The registrations to persist.
+///
+fn store_registrations(path: &str, registrations: &Registrations) {
+    let content = serde_json::to_string_pretty(registrations).unwrap();
+    if let Err(e) = fs::write(path, content) {
+        println!("ERROR : write {} : {}", path, e);
+    }
+}
+
+/// Serve the registry, on which the Nodes register their KeyCard and get the KeyCards of their shard.
+///
+/// # Arguments
+///
+/// * `addr` - The address of the server.
+/// * `port` - The port of the registry.
+/// * `gate` - The registry and the KeyCards it admits.
+///
+async fn serve_registry(addr: String, port: u16, gate: Arc<Mutex<Gate>>) {
+    let listener = match TcpListener::bind((addr.as_str(), port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("ERROR : registry bind {}:{} : {}", addr, port, e);
+            process::exit(1);
+        }
+    };
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_request(stream, gate.clone()));
+            }
+            Err(e) => {
+                println!("ERROR : registry accept : {}", e);
+            }
+        }
+    }
+}
+
+async fn serve_request(stream: TcpStream, gate: Arc<Mutex<Gate>>) {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Register {
+                shard,
+                keycard,
+                signature,
+            }) => gate.lock().await.register(shard, keycard, &signature),
+            Ok(Request::Shard { shard }) => gate.lock().await.registry.shard(shard),
+            Err(e) => Response::Error {
+                message: format!("Invalid request : {}", e),
+            },
+        };
+        let mut output = serde_json::to_string(&response).unwrap();
+        output.push('\n');
+        if write_half.write_all(output.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Print the status of every shard, full or still filling, with the KeyCards registered in it.
+/// Unauthorized KeyCards are refused before they register, and never listed.
+///
+/// # Arguments
+///
+/// * `gate` - The registry and the KeyCards it admits.
+///
+async fn print_shards(gate: &Arc<Mutex<Gate>>) {
+    let locked_gate = gate.lock().await;
+    let registry = &locked_gate.registry;
+    for (shard, size) in registry.sizes.iter().enumerate() {
+        let keycards = registry
+            .registrations
+            .get(&(shard as u32))
+            .cloned()
+            .unwrap_or_default();
+        let state = if keycards.len() >= *size {
+            "full"
+        } else {
+            "not full"
+        };
+        println!(
+            "Shard {} : {}, {}/{} keycards",
+            shard,
+            state,
+            keycards.len(),
+            size
+        );
+        for keycard in keycards.iter() {
+            println!("    {:?}", keycard.identity());
+        }
+    }
+    if locked_gate.allowlist.is_some() {
+        println!(
+            "{} unauthorized KeyCards rejected",
+            locked_gate.rejected.len()
+        );
+    }
+    drop(locked_gate);
+}
 
 #[tokio::main]
 async fn main() {
@@ -9,7 +233,11 @@ async fn main() {
     // Default values, if not specified in config file.
     let mut addr: String = String::from("127.0.0.1");
     let mut port = 4446;
+    let mut registry_port = 4447;
     let mut n: usize = 1;
+    let mut shard_sizes: Vec<usize> = Vec::new();
+    let mut state_file: String = String::from("rendezvous.json");
+    let mut allowlist_file: String = String::new();
     for line in lines {
         let mut elems = line.split("=");
         match elems.next().unwrap() {
@@ -19,43 +247,112 @@ async fn main() {
             "port" => {
                 port = elems.next().unwrap().parse().unwrap();
             }
+            "registry_port" => {
+                registry_port = elems.next().unwrap().parse().unwrap();
+            }
             "N" => {
                 n = elems.next().unwrap().parse().unwrap();
             }
-            "spawn" => {}
-            "G" => {}
-            "E" => {}
-            "E_thr" => {}
-            "R" => {}
-            "R_thr" => {}
-            "D" => {}
-            "D_thr" => {}
-            "" => {}
-            _ => {
-                println!("Unknown configuration : {}", line);
+            "shards" => {
+                shard_sizes = elems
+                    .next()
+                    .unwrap()
+                    .split(",")
+                    .map(|size| size.trim().parse().unwrap())
+                    .collect();
+            }
+            "rendezvous_state" => {
+                state_file = elems.next().unwrap().to_string();
             }
+            "allowlist_file" => {
+                allowlist_file = elems.next().unwrap().to_string();
+            }
+            // The other keys configure the Nodes and are ignored here.
+            _ => {}
         }
     }
+    // Without explicit shards, a single shard holds the N nodes.
+    if shard_sizes.is_empty() {
+        shard_sizes = vec![n];
+    }
+    // Without an allowlist, every KeyCard is accepted.
+    let allowlist = if allowlist_file.is_empty() {
+        None
+    } else {
+        match Allowlist::load(&allowlist_file) {
+            Ok(allowlist) => {
+                println!("Allowlist : {} identities", allowlist.identities.len());
+                Some(allowlist)
+            }
+            Err(e) => {
+                println!("ERROR : allowlist : {}", e);
+                process::exit(1);
+            }
+        }
+    };
     println!("Start Rendezvous server...");
-    // Start rendez-vous server
-    let _server = Server::new(
+    // Start rendez-vous server, through which the Nodes find the addresses of each other.
+    let server = Server::new(
         (addr.clone(), port),
         ServerSettings {
-            shard_sizes: vec![n],
+            shard_sizes: shard_sizes.clone(),
         },
     )
     .await
     .unwrap();
 
+    // Register again the KeyCards of a previous run.
+    let mut gate = Gate {
+        registry: Registry::new(shard_sizes.clone()),
+        allowlist,
+        rejected: HashSet::new(),
+        state_file: state_file.clone(),
+    };
+    let persisted = load_registrations(&state_file);
+    for (shard, keycards) in persisted.iter() {
+        if *shard as usize >= shard_sizes.len() {
+            println!("ERROR : persisted shard {} is not configured", shard);
+            continue;
+        }
+        for keycard in keycards.iter() {
+            if let Response::Error { message } = gate.restore(*shard, keycard.clone()) {
+                println!("ERROR : restore shard {} : {}", shard, message);
+            }
+        }
+        println!("Restored shard {} : {} keycards", shard, keycards.len());
+    }
+    let gate = Arc::new(Mutex::new(gate));
+    // Registrations go through the registry, so that unauthorized KeyCards never take a slot.
+    tokio::spawn(serve_registry(addr.clone(), registry_port, gate.clone()));
+
+    // SIGINT and SIGTERM stop the server like the exit command.
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
     loop {
-        let mut input: String = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        match input.as_str() {
-            "exit\n" => {
+        tokio::select! {
+            line = lines.next_line(), if stdin_open => match line {
+                Ok(Some(input)) => match input.as_str() {
+                    "status" => {
+                        print_shards(&gate).await;
+                    }
+                    "exit" => {
+                        break;
+                    }
+                    _ => {}
+                },
+                _ => {
+                    stdin_open = false;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => {
+                break;
+            }
+            _ = terminate.recv() => {
                 break;
             }
-            _ => {}
         }
     }
+    drop(server);
     println!("Rendezvous server stopped!");
 }
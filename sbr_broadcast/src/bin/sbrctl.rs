@@ -0,0 +1,217 @@
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+
+const USAGE: &str = "Usage : sbrctl [--socket <path>] [--topic <topic>] <command>
+
+Commands :
+    broadcast <source> <payload>  Broadcast the payload from the given source and print its instance
+    transfer <source> <to> <amount>
+                                  Transfer the amount from the account of the source to the account
+                                  of a node index or an identity
+    balance <node> [<account>]    Print the balances of every account known to the given node, or of
+                                  the given account
+    put <source> <key> <value>    Write the value of the key in the replicated store from the source
+    get <node> <key>              Print the value of the key in the store of the given node
+    log <node>                    Print the log of every source in the store of the given node
+    subscribe <node> <topic>...   Deliver only the given topics to the application of the node
+    membership <node>             Print the current epoch of the given node and its members
+    epoch [--join <shard>] [--leave <member>]...
+                                  Start a new epoch, with the nodes registered in the shard and
+                                  without the leaving members, given by node index or identity
+    list                          List the nodes and their phase
+    dump <node>                   Dump the state of the given node
+    stop <node>                   Stop the given node
+    shutdown                      Stop every node and the Broadcast
+
+The source is a node index, or --identity <identity>. The payload is the text given, --file <path> to
+read it from a file, or - to read it from stdin. Transfers need the nodes to run with transfers=true,
+and the store commands with store=true. --topic selects the topic of broadcast, put, get, log and dump,
+0 by default. Transfers are always broadcast in topic 0.";
+
+fn usage() -> ! {
+    println!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_node(arg: Option<&String>) -> usize {
+    match arg.map(|a| a.parse()) {
+        Some(Ok(node)) => node,
+        _ => usage(),
+    }
+}
+
+fn parse_amount(arg: Option<&String>) -> u64 {
+    match arg.map(|a| a.parse()) {
+        Some(Ok(amount)) => amount,
+        _ => usage(),
+    }
+}
+
+/// Read the payload from the remaining arguments : the text itself, `--file <path>` or `-` for stdin.
+fn read_payload(args: &[String]) -> String {
+    match args {
+        [] => usage(),
+        [flag, path] if flag == "--file" => match fs::read_to_string(path) {
+            Ok(payload) => payload,
+            Err(e) => {
+                println!("ERROR : read {} : {}", path, e);
+                process::exit(1);
+            }
+        },
+        [dash] if dash == "-" => {
+            let mut payload = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut payload) {
+                println!("ERROR : read stdin : {}", e);
+                process::exit(1);
+            }
+            payload
+        }
+        _ => args.join(" "),
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut socket = String::from("sbr.sock");
+    if args.len() >= 2 && args[0] == "--socket" {
+        socket = args[1].clone();
+        args.drain(0..2);
+    }
+    let mut topic = None;
+    if args.len() >= 2 && args[0] == "--topic" {
+        topic = match args[1].parse::<u32>() {
+            Ok(topic) => Some(topic),
+            _ => usage(),
+        };
+        args.drain(0..2);
+    }
+    let mut request: Value = match args.get(0).map(|a| a.as_str()) {
+        Some("broadcast") => {
+            if args.len() < 3 {
+                usage();
+            }
+            if args[1] == "--identity" {
+                json!({
+                    "command": "broadcast",
+                    "identity": args[2],
+                    "payload": read_payload(&args[3..]),
+                })
+            } else {
+                json!({
+                    "command": "broadcast",
+                    "node": parse_node(args.get(1)),
+                    "payload": read_payload(&args[2..]),
+                })
+            }
+        }
+        Some("transfer") => {
+            if args.len() == 5 && args[1] == "--identity" {
+                json!({
+                    "command": "transfer",
+                    "identity": args[2],
+                    "to": args[3],
+                    "amount": parse_amount(args.get(4)),
+                })
+            } else if args.len() == 4 {
+                json!({
+                    "command": "transfer",
+                    "node": parse_node(args.get(1)),
+                    "to": args[2],
+                    "amount": parse_amount(args.get(3)),
+                })
+            } else {
+                usage()
+            }
+        }
+        Some("balance") => json!({
+            "command": "balance",
+            "node": parse_node(args.get(1)),
+            "account": args.get(2),
+        }),
+        Some("put") => {
+            if args.len() >= 5 && args[1] == "--identity" {
+                json!({
+                    "command": "put",
+                    "identity": args[2],
+                    "key": args[3],
+                    "value": args[4..].join(" "),
+                })
+            } else if args.len() >= 4 {
+                json!({
+                    "command": "put",
+                    "node": parse_node(args.get(1)),
+                    "key": args[2],
+                    "value": args[3..].join(" "),
+                })
+            } else {
+                usage()
+            }
+        }
+        Some("get") if args.len() == 3 => json!({
+            "command": "get",
+            "node": parse_node(args.get(1)),
+            "key": args[2],
+        }),
+        Some("log") => json!({ "command": "log", "node": parse_node(args.get(1)) }),
+        Some("subscribe") if args.len() >= 2 => {
+            let topics: Vec<u32> = args[2..]
+                .iter()
+                .map(|topic| match topic.parse() {
+                    Ok(topic) => topic,
+                    _ => usage(),
+                })
+                .collect();
+            json!({
+                "command": "subscribe",
+                "node": parse_node(args.get(1)),
+                "topics": topics,
+            })
+        }
+        Some("membership") => json!({ "command": "membership", "node": parse_node(args.get(1)) }),
+        Some("epoch") => {
+            let mut join_shard: Option<u32> = None;
+            let mut leave: Vec<String> = Vec::new();
+            for option in args[1..].chunks(2) {
+                match option {
+                    [flag, shard] if flag == "--join" => {
+                        join_shard = match shard.parse() {
+                            Ok(shard) => Some(shard),
+                            _ => usage(),
+                        };
+                    }
+                    [flag, member] if flag == "--leave" => leave.push(member.clone()),
+                    _ => usage(),
+                }
+            }
+            json!({ "command": "epoch", "join_shard": join_shard, "leave": leave })
+        }
+        Some("list") => json!({ "command": "list" }),
+        Some("dump") => json!({ "command": "dump", "node": parse_node(args.get(1)) }),
+        Some("stop") => json!({ "command": "stop", "node": parse_node(args.get(1)) }),
+        Some("shutdown") => json!({ "command": "shutdown" }),
+        _ => usage(),
+    };
+    if let Some(topic) = topic {
+        request["topic"] = json!(topic);
+    }
+
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("ERROR : connect to {} : {}", socket, e);
+            process::exit(1);
+        }
+    };
+    writeln!(stream, "{}", request).unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    let response: Value = serde_json::from_str(&line).unwrap_or(Value::String(line));
+    println!("{}", serde_json::to_string_pretty(&response).unwrap());
+    if response["status"] == "error" {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,344 @@
+use crate::journal::{record, Journal, Record};
+use crate::layer::BroadcastLayer;
+use crate::message::{Message, SignedMessage};
+use crate::message_headers::{BrachaEcho, BrachaReady, BrachaSend};
+use crate::push::Pusher;
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::sync::Arc;
+use talk::crypto::{Identity, KeyChain};
+use talk::unicast::Sender;
+use tokio::sync::Mutex;
+
+/// Quorums of Bracha's reliable broadcast, in a system of n Nodes of which at most f = (n - 1) / 3
+/// are Byzantine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quorums {
+    /// Echoes needed to send a Ready : more than (n + f) / 2, 2f + 1 when n = 3f + 1.
+    pub echo: usize,
+    /// Readies needed to send a Ready without enough Echoes : f + 1.
+    pub ready: usize,
+    /// Readies needed to deliver : 2f + 1.
+    pub delivery: usize,
+}
+
+impl Quorums {
+    /// Quorums of a system of the given size, the Node itself included.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The size of the system.
+    ///
+    pub fn new(n: usize) -> Self {
+        let f = n.saturating_sub(1) / 3;
+        Quorums {
+            echo: (n + f) / 2 + 1,
+            ready: f + 1,
+            delivery: 2 * f + 1,
+        }
+    }
+}
+
+/// What a Node has to do after recording a Bracha Message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    Echo(String),
+    Ready(String),
+    Deliver(String),
+}
+
+/// State of a Bracha instance. Only the first Echo and Ready of every peer is counted, and the Node
+/// counts its own Echo and Ready as if it received them.
+#[derive(Clone, Debug, Default)]
+pub struct BrachaState {
+    pub echoed: bool,
+    pub readied: bool,
+    pub delivered: bool,
+    pub echoes: HashMap<Identity, String>,
+    pub readies: HashMap<Identity, String>,
+}
+
+impl BrachaState {
+    /// Record the Send of the source. Only the first Send is echoed.
+    ///
+    /// # Arguments
+    ///
+    /// * `own` - The Identity of the Node.
+    /// * `content` - The content sent by the source.
+    /// * `quorums` - The quorums of the system.
+    ///
+    pub fn on_send(&mut self, own: Identity, content: &str, quorums: Quorums) -> Vec<Step> {
+        if self.echoed {
+            return Vec::new();
+        }
+        self.echoed = true;
+        self.echoes.entry(own).or_insert(content.to_string());
+        let mut steps = vec![Step::Echo(content.to_string())];
+        steps.extend(self.progress(own, quorums));
+        steps
+    }
+
+    /// Record the Echo of a peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `own` - The Identity of the Node.
+    /// * `from` - The Identity of the peer.
+    /// * `content` - The content echoed.
+    /// * `quorums` - The quorums of the system.
+    ///
+    pub fn on_echo(
+        &mut self,
+        own: Identity,
+        from: Identity,
+        content: &str,
+        quorums: Quorums,
+    ) -> Vec<Step> {
+        self.echoes.entry(from).or_insert(content.to_string());
+        self.progress(own, quorums)
+    }
+
+    /// Record the Ready of a peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `own` - The Identity of the Node.
+    /// * `from` - The Identity of the peer.
+    /// * `content` - The content the peer is ready for.
+    /// * `quorums` - The quorums of the system.
+    ///
+    pub fn on_ready(
+        &mut self,
+        own: Identity,
+        from: Identity,
+        content: &str,
+        quorums: Quorums,
+    ) -> Vec<Step> {
+        self.readies.entry(from).or_insert(content.to_string());
+        self.progress(own, quorums)
+    }
+
+    fn progress(&mut self, own: Identity, quorums: Quorums) -> Vec<Step> {
+        let mut steps = Vec::new();
+        if !self.readied {
+            let content = reaching(&self.echoes, quorums.echo)
+                .or_else(|| reaching(&self.readies, quorums.ready));
+            if let Some(content) = content {
+                self.readied = true;
+                self.readies.entry(own).or_insert(content.clone());
+                steps.push(Step::Ready(content));
+            }
+        }
+        if !self.delivered {
+            if let Some(content) = reaching(&self.readies, quorums.delivery) {
+                self.delivered = true;
+                steps.push(Step::Deliver(content));
+            }
+        }
+        steps
+    }
+}
+
+/// The content received from at least quorum peers, if any.
+///
+/// # Arguments
+///
+/// * `replies` - The content received from every peer.
+/// * `quorum` - The number of peers needed.
+///
+fn reaching(replies: &HashMap<Identity, String>, quorum: usize) -> Option<String> {
+    let mut occurrences: HashMap<&String, usize> = HashMap::new();
+    for content in replies.values() {
+        let count = occurrences.entry(content).or_insert(0);
+        *count += 1;
+        if *count >= quorum {
+            return Some(content.clone());
+        }
+    }
+    None
+}
+
+/// Bracha's reliable broadcast of an instance : every Message is sent to every other member, and a
+/// content is delivered once enough members are ready for it.
+pub struct BrachaLayer {
+    pub keychain: KeyChain,
+    pub node_sender: Sender<SignedMessage>,
+    pub pusher: Pusher,
+    /// Every other member of the system.
+    pub peers: Vec<Identity>,
+    pub quorums: Quorums,
+    pub state: Arc<Mutex<BrachaState>>,
+    pub journal: Option<Arc<Journal>>,
+    /// The application, to which the Message is delivered.
+    pub application: Arc<dyn BroadcastLayer>,
+}
+
+impl BrachaLayer {
+    /// Broadcast a content in a new instance : send it to every peer, and handle the Send locally.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The Message to broadcast, with its instance, topic and epoch.
+    ///
+    pub async fn broadcast(&self, message: Message) {
+        let msg = message.with_type(10);
+        let send = self.send_all(msg.clone());
+        let local = self.deliver(msg, self.keychain.keycard().identity());
+        tokio::join!(send, local);
+    }
+
+    /// Deliver a Bracha Message, Send, Echo or Ready, and take the resulting steps. The delivery is
+    /// written, and the Echo and Ready journaled, before any Message is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The verified Message received.
+    /// * `from` - The Identity of the Node which sent the Message.
+    ///
+    pub async fn deliver(&self, message: Message, from: Identity) {
+        let own = self.keychain.keycard().identity();
+        let quorums = self.quorums;
+        let mut locked_state = self.state.lock().await;
+        let steps = match message.message_type {
+            10 => locked_state.on_send(own, &message.content, quorums),
+            11 => locked_state.on_echo(own, from, &message.content, quorums),
+            12 => locked_state.on_ready(own, from, &message.content, quorums),
+            _ => Vec::new(),
+        };
+        drop(locked_state);
+
+        let mut sends = Vec::new();
+        for step in steps.into_iter() {
+            match step {
+                Step::Echo(content) => {
+                    let mut echo = message.with_type(11);
+                    echo.content = content;
+                    if echo.instance.is_some() {
+                        let message = echo.clone();
+                        if !record(&self.journal, Record::Echo { message }).await {
+                            continue;
+                        }
+                    }
+                    sends.push(echo);
+                }
+                Step::Ready(content) => {
+                    let mut ready = message.with_type(12);
+                    ready.content = content;
+                    if ready.instance.is_some() {
+                        let message = ready.clone();
+                        if !record(&self.journal, Record::Ready { message }).await {
+                            continue;
+                        }
+                    }
+                    sends.push(ready);
+                }
+                Step::Deliver(content) => {
+                    let mut msg = message.with_type(12);
+                    msg.content = content;
+                    self.application.deliver(msg).await;
+                }
+            }
+        }
+        join_all(sends.into_iter().map(|msg| self.send_all(msg))).await;
+    }
+
+    /// Sign a Bracha Message and push it to every peer until they acknowledge it, or until the push is
+    /// abandoned.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Message to send.
+    ///
+    async fn send_all(&self, msg: Message) {
+        let keychain = &self.keychain;
+        let (kind, signature) = match msg.message_type {
+            10 => ("bracha_send", keychain.sign(&BrachaSend(msg.clone()))),
+            11 => ("bracha_echo", keychain.sign(&BrachaEcho(msg.clone()))),
+            _ => ("bracha_ready", keychain.sign(&BrachaReady(msg.clone()))),
+        };
+        let signed_msg = SignedMessage::new(msg, signature.unwrap());
+        let (node_sender, peers) = (self.node_sender.clone(), self.peers.clone());
+        self.pusher
+            .best_effort(kind, node_sender, peers, signed_msg)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identities(count: usize) -> Vec<Identity> {
+        (0..count)
+            .map(|_| KeyChain::random().keycard().identity())
+            .collect()
+    }
+
+    #[test]
+    fn quorums_of_3f_plus_1() {
+        assert_eq!(
+            Quorums::new(4),
+            Quorums {
+                echo: 3,
+                ready: 2,
+                delivery: 3
+            }
+        );
+        assert_eq!(
+            Quorums::new(100),
+            Quorums {
+                echo: 67,
+                ready: 34,
+                delivery: 67
+            }
+        );
+    }
+
+    #[test]
+    fn only_the_first_send_is_echoed() {
+        let ids = identities(1);
+        let quorums = Quorums::new(4);
+        let mut state = BrachaState::default();
+        assert_eq!(
+            state.on_send(ids[0], "a", quorums),
+            vec![Step::Echo(String::from("a"))]
+        );
+        assert!(state.on_send(ids[0], "b", quorums).is_empty());
+    }
+
+    #[test]
+    fn echo_quorum_readies_and_ready_quorum_delivers() {
+        let ids = identities(4);
+        let quorums = Quorums::new(4);
+        let mut state = BrachaState::default();
+        state.on_send(ids[0], "a", quorums);
+        assert!(state.on_echo(ids[0], ids[1], "a", quorums).is_empty());
+        // An equivocating peer cannot replace its first Echo.
+        assert!(state.on_echo(ids[0], ids[1], "b", quorums).is_empty());
+        assert_eq!(
+            state.on_echo(ids[0], ids[2], "a", quorums),
+            vec![Step::Ready(String::from("a"))]
+        );
+        assert!(state.on_ready(ids[0], ids[1], "a", quorums).is_empty());
+        assert_eq!(
+            state.on_ready(ids[0], ids[2], "a", quorums),
+            vec![Step::Deliver(String::from("a"))]
+        );
+        assert!(state.on_ready(ids[0], ids[3], "a", quorums).is_empty());
+    }
+
+    #[test]
+    fn ready_amplification_without_echoes() {
+        let ids = identities(4);
+        let quorums = Quorums::new(4);
+        let mut state = BrachaState::default();
+        assert!(state.on_ready(ids[0], ids[1], "a", quorums).is_empty());
+        assert_eq!(
+            state.on_ready(ids[0], ids[2], "a", quorums),
+            vec![
+                Step::Ready(String::from("a")),
+                Step::Deliver(String::from("a"))
+            ]
+        );
+    }
+}
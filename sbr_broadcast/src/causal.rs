@@ -0,0 +1,422 @@
+use crate::layer::BroadcastLayer;
+use crate::message::{Instance, Message};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use talk::crypto::Identity;
+use tokio::sync::Mutex;
+
+/// Payload of a causal broadcast : the content broadcast by the application, and the vector clock of the
+/// source when it broadcast it. The clock holds, for every other source, the next sequence the source
+/// had to causally deliver from it. The Messages of the source itself precede it by their sequence
+/// number.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CausalPayload {
+    pub clock: Vec<(Identity, u64)>,
+    pub payload: String,
+}
+
+/// Counters of the causal buffer of a Node, reported on the control socket.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CausalStats {
+    /// Messages waiting for their causal predecessors.
+    pub pending: usize,
+    /// Sequences given up on, because the buffer was full or the FIFO ordering skipped them.
+    pub skipped: usize,
+    /// Messages delivered after their sequence was skipped, which are dropped.
+    pub late: usize,
+}
+
+/// Messages delivered by the layer below whose causal predecessors are not all delivered yet, and the
+/// next sequence to deliver per source. At most `capacity` Messages wait : once the buffer is full, the
+/// missing predecessors of the oldest one are skipped and reported.
+#[derive(Debug)]
+pub struct CausalBuffer {
+    capacity: usize,
+    delivered: HashMap<Identity, u64>,
+    pending: Vec<(Instance, CausalPayload, Message)>,
+    stats: CausalStats,
+}
+
+impl CausalBuffer {
+    pub fn new(capacity: usize) -> Self {
+        CausalBuffer {
+            capacity,
+            delivered: HashMap::new(),
+            pending: Vec::new(),
+            stats: CausalStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CausalStats {
+        CausalStats {
+            pending: self.pending.len(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Give up on the sequences of a source below the given one, which the layer below will never
+    /// deliver.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the running Node, used for debug purpose.
+    /// * `source` - The source of the skipped sequences.
+    /// * `next` - The first sequence which is not skipped.
+    ///
+    pub fn skip(&mut self, id: usize, source: Identity, next: u64) {
+        let delivered = self.delivered.entry(source).or_insert(0);
+        if next > *delivered {
+            my_print!(format!(
+                "{} causal skipped sequences {} to {} from {:?}",
+                id,
+                delivered,
+                next - 1,
+                source
+            ));
+            self.stats.skipped += (next - *delivered) as usize;
+            *delivered = next;
+        }
+    }
+
+    /// Attach the vector clock of the Node to a payload it broadcasts in the given instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - The instance broadcast by the Node.
+    /// * `payload` - The payload to broadcast.
+    ///
+    pub fn stamp(&self, instance: Instance, payload: String) -> String {
+        let clock = self
+            .delivered
+            .iter()
+            .filter(|(source, _)| **source != instance.source)
+            .map(|(source, next)| (*source, *next))
+            .collect();
+        serde_json::to_string(&CausalPayload { clock, payload }).unwrap()
+    }
+
+    /// Buffer a delivered Message, and return the Messages which can now be causally delivered, in
+    /// causal order, with their payload as content. Messages without instance or clock are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the running Node, used for debug purpose.
+    /// * `message` - The delivered Message.
+    ///
+    pub fn push(&mut self, id: usize, message: Message) -> Vec<Message> {
+        let instance = match message.instance {
+            Some(instance) => instance,
+            None => {
+                println!("ERROR : <{}> causal Message without instance", id);
+                return Vec::new();
+            }
+        };
+        let causal: CausalPayload = match serde_json::from_str(&message.content) {
+            Ok(causal) => causal,
+            Err(e) => {
+                println!("ERROR : <{}> causal Message without clock : {}", id, e);
+                return Vec::new();
+            }
+        };
+        self.pending.push((instance, causal, message));
+
+        let mut released = Vec::new();
+        loop {
+            // Messages of skipped sequences are late, and never delivered.
+            let waiting = self.pending.len();
+            let delivered = &self.delivered;
+            self.pending.retain(|(instance, _, _)| {
+                instance.sequence >= *delivered.get(&instance.source).unwrap_or(&0)
+            });
+            if self.pending.len() < waiting {
+                my_print!(format!(
+                    "{} causal dropped {} late Messages",
+                    id,
+                    waiting - self.pending.len()
+                ));
+                self.stats.late += waiting - self.pending.len();
+            }
+            let position = match self
+                .pending
+                .iter()
+                .position(|(instance, causal, _)| self.is_deliverable(instance, causal))
+            {
+                Some(position) => position,
+                None if self.pending.len() > self.capacity => {
+                    // The buffer is full : give up on the missing predecessors of the oldest Message.
+                    let (instance, causal, _) = self.pending[0].clone();
+                    self.skip(id, instance.source, instance.sequence);
+                    for (source, needed) in causal.clock.iter() {
+                        if *source != instance.source {
+                            self.skip(id, *source, *needed);
+                        }
+                    }
+                    0
+                }
+                None => {
+                    break;
+                }
+            };
+            let (instance, causal, message) = self.pending.remove(position);
+            self.delivered
+                .insert(instance.source, instance.sequence + 1);
+            let mut message = message;
+            message.content = causal.payload;
+            released.push(message);
+        }
+        if !self.pending.is_empty() {
+            my_print!(format!(
+                "{} causal : {} Messages waiting for their predecessors",
+                id,
+                self.pending.len()
+            ));
+        }
+        released
+    }
+
+    /// The next sequence to deliver from a source.
+    fn next(&self, source: &Identity) -> u64 {
+        *self.delivered.get(source).unwrap_or(&0)
+    }
+
+    /// Whether every causal predecessor of a Message has been delivered or skipped : the previous
+    /// Messages of its source, and the Messages of the other sources counted in its clock.
+    fn is_deliverable(&self, instance: &Instance, causal: &CausalPayload) -> bool {
+        self.next(&instance.source) == instance.sequence
+            && causal
+                .clock
+                .iter()
+                .all(|(source, needed)| *source == instance.source || self.next(source) >= *needed)
+    }
+}
+
+/// Causal ordering above the highest layer : a Message is delivered to the application once every
+/// Message its source had delivered before broadcasting it is delivered locally.
+pub struct CausalLayer {
+    pub id: usize,
+    pub buffer: Arc<Mutex<CausalBuffer>>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+#[async_trait]
+impl BroadcastLayer for CausalLayer {
+    async fn deliver(&self, message: Message) {
+        // The buffer stays locked until the released Messages are delivered, so that concurrent
+        // deliveries cannot overtake them.
+        let mut locked_buffer = self.buffer.lock().await;
+        let released = locked_buffer.push(self.id, message);
+        for message in released.into_iter() {
+            self.upper.deliver(message).await;
+        }
+        drop(locked_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FifoSettings;
+    use crate::fifo::{FifoBuffer, FifoLayer};
+    use rand::prelude::*;
+    use std::time::Duration;
+    use talk::crypto::KeyChain;
+
+    /// Layer recording the Messages delivered to it.
+    struct Recorder {
+        delivered: Arc<Mutex<Vec<Message>>>,
+    }
+
+    #[async_trait]
+    impl BroadcastLayer for Recorder {
+        async fn deliver(&self, message: Message) {
+            self.delivered.lock().await.push(message);
+        }
+    }
+
+    /// Broadcast a payload from a Node, stamped with the clock of its buffer.
+    fn broadcast(buffer: &CausalBuffer, source: Identity, sequence: u64, payload: &str) -> Message {
+        let instance = Instance { source, sequence };
+        let content = buffer.stamp(instance, String::from(payload));
+        Message::with_instance(2, instance, content)
+    }
+
+    /// A causal history between three sources : a0 -> b0 -> c0 -> c1, and a0 -> a1.
+    /// Returns the Messages and the pairs (before, after) which have to be respected.
+    fn history() -> (Vec<Message>, Vec<(&'static str, &'static str)>) {
+        let a = KeyChain::random().keycard().identity();
+        let b = KeyChain::random().keycard().identity();
+        let c = KeyChain::random().keycard().identity();
+        let mut node_a = CausalBuffer::new(16);
+        let mut node_b = CausalBuffer::new(16);
+        let mut node_c = CausalBuffer::new(16);
+
+        let a0 = broadcast(&node_a, a, 0, "a0");
+        node_a.push(0, a0.clone());
+        let a1 = broadcast(&node_a, a, 1, "a1");
+        node_b.push(1, a0.clone());
+        let b0 = broadcast(&node_b, b, 0, "b0");
+        node_c.push(2, a0.clone());
+        node_c.push(2, b0.clone());
+        let c0 = broadcast(&node_c, c, 0, "c0");
+        // Stamped with an empty clock, c1 only follows c0 through its sequence.
+        let c1 = broadcast(&CausalBuffer::new(16), c, 1, "c1");
+        (
+            vec![a0, a1, b0, c0, c1],
+            vec![
+                ("a0", "a1"),
+                ("a0", "b0"),
+                ("b0", "c0"),
+                ("a0", "c0"),
+                ("c0", "c1"),
+            ],
+        )
+    }
+
+    fn check_order(delivered: &[Message], order: &[(&str, &str)]) {
+        let position = |payload: &str| delivered.iter().position(|m| m.content == payload);
+        assert_eq!(delivered.len(), 5);
+        for (before, after) in order.iter() {
+            assert!(position(before).unwrap() < position(after).unwrap());
+        }
+    }
+
+    #[test]
+    fn random_delivery_orders_respect_causality() {
+        let (messages, order) = history();
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let mut shuffled = messages.clone();
+            shuffled.shuffle(&mut rng);
+            let mut buffer = CausalBuffer::new(16);
+            let mut delivered = Vec::new();
+            for message in shuffled.into_iter() {
+                delivered.extend(buffer.push(3, message));
+            }
+            check_order(&delivered, &order);
+            assert_eq!(buffer.stats().pending, 0);
+        }
+    }
+
+    #[test]
+    fn message_without_clock_is_dropped() {
+        let source = KeyChain::random().keycard().identity();
+        let mut buffer = CausalBuffer::new(16);
+        let message = Message::with_instance(
+            2,
+            Instance {
+                source,
+                sequence: 0,
+            },
+            String::from("no clock"),
+        );
+        assert!(buffer.push(0, message).is_empty());
+        assert_eq!(buffer.stats().pending, 0);
+    }
+
+    #[tokio::test]
+    async fn layer_under_random_delays() {
+        let (messages, order) = history();
+        for _ in 0..10 {
+            let delivered = Arc::new(Mutex::new(Vec::new()));
+            let layer = Arc::new(CausalLayer {
+                id: 3,
+                buffer: Arc::new(Mutex::new(CausalBuffer::new(16))),
+                upper: Arc::new(Recorder {
+                    delivered: delivered.clone(),
+                }),
+            });
+            let mut tasks = Vec::new();
+            for message in messages.clone().into_iter() {
+                let layer = layer.clone();
+                let delay = rand::thread_rng().gen_range(0..20);
+                tasks.push(tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    layer.deliver(message).await;
+                }));
+            }
+            for task in tasks.into_iter() {
+                task.await.unwrap();
+            }
+            check_order(&delivered.lock().await, &order);
+        }
+    }
+
+    #[test]
+    fn full_buffer_skips_the_missing_predecessors() {
+        let source = KeyChain::random().keycard().identity();
+        let node = CausalBuffer::new(16);
+        let mut buffer = CausalBuffer::new(1);
+        let first = broadcast(&node, source, 0, "first");
+        assert!(buffer
+            .push(0, broadcast(&node, source, 1, "second"))
+            .is_empty());
+        let released = buffer.push(0, broadcast(&node, source, 2, "third"));
+        let payloads: Vec<String> = released.into_iter().map(|m| m.content).collect();
+        assert_eq!(payloads, vec!["second", "third"]);
+        assert_eq!(buffer.stats().skipped, 1);
+        // The skipped predecessor is dropped once delivered.
+        assert!(buffer.push(0, first).is_empty());
+        assert_eq!(buffer.stats().late, 1);
+        assert_eq!(buffer.stats().pending, 0);
+    }
+
+    #[tokio::test]
+    async fn sequences_skipped_by_fifo_are_skipped_by_causal() {
+        let a = KeyChain::random();
+        let b = KeyChain::random();
+        let (a_id, b_id) = (a.keycard().identity(), b.keycard().identity());
+        let mut keycards = HashMap::new();
+        keycards.insert(a_id, a.keycard());
+        keycards.insert(b_id, b.keycard());
+
+        // b0 follows every Message of a, and a1 is never delivered.
+        let mut node_a = CausalBuffer::new(16);
+        let mut node_b = CausalBuffer::new(16);
+        let mut messages = Vec::new();
+        for sequence in 0..4 {
+            let message = broadcast(&node_a, a_id, sequence, &format!("a{}", sequence));
+            node_a.push(0, message.clone());
+            node_b.push(1, message.clone());
+            messages.push(message.signed_by_source(&a));
+        }
+        let b0 = broadcast(&node_b, b_id, 0, "b0").signed_by_source(&b);
+
+        let causal = Arc::new(Mutex::new(CausalBuffer::new(16)));
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let layer = FifoLayer {
+            id: 2,
+            own: KeyChain::random().keycard(),
+            keycards: Arc::new(Mutex::new(keycards)),
+            buffer: Arc::new(Mutex::new(FifoBuffer::new(FifoSettings {
+                capacity: 1,
+                sources: 16,
+            }))),
+            causal: Some(causal.clone()),
+            upper: Arc::new(CausalLayer {
+                id: 2,
+                buffer: causal.clone(),
+                upper: Arc::new(Recorder {
+                    delivered: delivered.clone(),
+                }),
+            }),
+        };
+        layer.deliver(b0).await;
+        layer.deliver(messages[0].clone()).await;
+        layer.deliver(messages[2].clone()).await;
+        assert_eq!(delivered.lock().await.len(), 1);
+        // The FIFO buffer is full, it skips a1, and so does the causal ordering.
+        layer.deliver(messages[3].clone()).await;
+        let payloads: Vec<String> = delivered
+            .lock()
+            .await
+            .iter()
+            .map(|m| m.content.clone())
+            .collect();
+        assert_eq!(payloads, vec!["a0", "a2", "a3", "b0"]);
+        let stats = causal.lock().await.stats();
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.pending, 0);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::link::load_membership;
+use crate::message::{Message, SignedMessage};
+use crate::message_headers::Ready;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use talk::crypto::primitives::sign::Signature as SignSignature;
+use talk::crypto::{Identity, KeyCard};
+
+/// Transferable proof that a Message has been Probabilistic Reliable Broadcast delivered. It holds the
+/// Delivery sample of the delivering Node, with the number of times each peer was drawn, and the signed
+/// Ready Messages of the Delivery peers which made the delivery threshold be reached, so that a client
+/// outside the system can check, using only the membership KeyCards, that the signers carry the Delivery
+/// threshold of a sample of the configured size. The sample is drawn by the delivering Node, which the
+/// client trusts to have drawn it at random.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeliveryCertificate {
+    message: Message,
+    sample: Vec<(Identity, usize)>,
+    signatures: Vec<(Identity, SignSignature)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CertificateError {
+    UnknownSigner(Identity),
+    UnsampledSigner(Identity),
+    InvalidSignature(Identity),
+    InvalidSample(usize),
+    NotEnoughSignatures(usize),
+}
+
+/// Whether a signed Ready is for the delivered Message : the same instance, topic, epoch and content.
+fn is_for(ready: &Message, message: &Message) -> bool {
+    ready.instance == message.instance
+        && ready.topic == message.topic
+        && ready.epoch == message.epoch
+        && ready.content == message.content
+}
+
+impl DeliveryCertificate {
+    /// Assemble a certificate for the delivered Message from the signed Ready Messages received from the
+    /// Delivery peers. Only one signature per sampled peer is kept, and only if it signs the delivered
+    /// Message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The Ready Message which has been delivered.
+    /// * `sample` - The Delivery peers, with the number of times each one was drawn.
+    /// * `ready_signatures` - The signed Ready Messages received from each Delivery peer.
+    ///
+    pub fn assemble(
+        message: Message,
+        sample: &HashMap<Identity, usize>,
+        ready_signatures: &HashMap<Identity, Vec<SignedMessage>>,
+    ) -> Self {
+        let mut signatures: Vec<(Identity, SignSignature)> = Vec::new();
+        for (identity, signed_messages) in ready_signatures.iter() {
+            if !sample.contains_key(identity) {
+                continue;
+            }
+            let matching = signed_messages
+                .iter()
+                .find(|signed_msg| is_for(&signed_msg.clone().get_message(), &message));
+            if let Some(signed_msg) = matching {
+                signatures.push((identity.clone(), signed_msg.clone().get_signature()));
+            }
+        }
+        DeliveryCertificate {
+            message,
+            sample: sample.iter().map(|(id, weight)| (*id, *weight)).collect(),
+            signatures,
+        }
+    }
+
+    pub fn get_message(self) -> Message {
+        self.message
+    }
+
+    pub fn get_signers(&self) -> Vec<Identity> {
+        self.signatures.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Verify the certificate against the membership KeyCards and the Delivery settings of the system.
+    /// The sample must be made of members and weigh `size`, every signature must be a valid Ready
+    /// signature of a sampled peer, and the signers must weigh at least `threshold` in the sample, as
+    /// the delivering Node counted them.
+    ///
+    /// # Arguments
+    ///
+    /// * `keycards` - The KeyCards of the members of the system.
+    /// * `size` - The size of the Delivery samples, D.
+    /// * `threshold` - The Delivery threshold, D_thr.
+    ///
+    pub fn verify(
+        &self,
+        keycards: &HashMap<Identity, KeyCard>,
+        size: usize,
+        threshold: usize,
+    ) -> Result<(), CertificateError> {
+        let mut sample: HashMap<Identity, usize> = HashMap::new();
+        for (identity, weight) in self.sample.iter() {
+            if !keycards.contains_key(identity) {
+                return Err(CertificateError::UnknownSigner(identity.clone()));
+            }
+            *sample.entry(identity.clone()).or_insert(0) += weight;
+        }
+        let sample_weight: usize = sample.values().sum();
+        if sample_weight != size {
+            return Err(CertificateError::InvalidSample(sample_weight));
+        }
+        let mut signers: Vec<Identity> = Vec::new();
+        for (identity, signature) in self.signatures.iter() {
+            let keycard = match keycards.get(identity) {
+                Some(kc) => kc,
+                None => {
+                    return Err(CertificateError::UnknownSigner(identity.clone()));
+                }
+            };
+            if !sample.contains_key(identity) {
+                return Err(CertificateError::UnsampledSigner(identity.clone()));
+            }
+            if signature
+                .verify(keycard, &Ready(self.message.clone()))
+                .is_err()
+            {
+                return Err(CertificateError::InvalidSignature(identity.clone()));
+            }
+            if !signers.contains(identity) {
+                signers.push(identity.clone());
+            }
+        }
+        let weight: usize = signers.iter().map(|signer| sample[signer]).sum();
+        if weight < threshold {
+            return Err(CertificateError::NotEnoughSignatures(weight));
+        }
+        Ok(())
+    }
+}
+
+/// Verify a certificate stored in a file against the members of a static membership file, for a client
+/// outside the system. Returns the certified Message.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the certificate is stored, as JSON.
+/// * `members` - The static membership file listing the KeyCards of the members.
+/// * `size` - The size of the Delivery samples, D.
+/// * `threshold` - The Delivery threshold, D_thr.
+///
+pub fn verify_file(
+    path: &str,
+    members: &str,
+    size: usize,
+    threshold: usize,
+) -> Result<Message, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("read {} : {}", path, e))?;
+    let certificate: DeliveryCertificate =
+        serde_json::from_str(&content).map_err(|e| format!("parse {} : {}", path, e))?;
+    let keycards: HashMap<Identity, KeyCard> = load_membership(members)?
+        .into_iter()
+        .map(|member| (member.keycard.identity(), member.keycard))
+        .collect();
+    certificate
+        .verify(&keycards, size, threshold)
+        .map_err(|e| format!("invalid certificate : {:?}", e))?;
+    Ok(certificate.get_message())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::StaticMember;
+    use talk::crypto::KeyChain;
+
+    fn ready(content: &str) -> Message {
+        Message::new(2, String::from(content))
+    }
+
+    fn sign(keychain: &KeyChain, msg: Message) -> SignedMessage {
+        let signature = keychain.sign(&Ready(msg.clone())).unwrap();
+        SignedMessage::new(msg, signature)
+    }
+
+    fn signed_ready(keychain: &KeyChain, content: &str) -> SignedMessage {
+        sign(keychain, ready(content))
+    }
+
+    /// The sample of the given peers, the first one drawn twice.
+    fn sample(keychains: &[KeyChain]) -> HashMap<Identity, usize> {
+        keychains
+            .iter()
+            .enumerate()
+            .map(|(i, kc)| (kc.keycard().identity(), if i == 0 { 2 } else { 1 }))
+            .collect()
+    }
+
+    #[test]
+    fn certificate_verification() {
+        let keychains: Vec<KeyChain> = (0..3).map(|_| KeyChain::random()).collect();
+        let mut keycards: HashMap<Identity, KeyCard> = HashMap::new();
+        let mut ready_signatures: HashMap<Identity, Vec<SignedMessage>> = HashMap::new();
+        for kc in keychains.iter() {
+            keycards.insert(kc.keycard().identity(), kc.keycard());
+            ready_signatures.insert(
+                kc.keycard().identity(),
+                vec![signed_ready(kc, "Other"), signed_ready(kc, "Test1")],
+            );
+        }
+        let sample = sample(&keychains);
+        let certificate = DeliveryCertificate::assemble(ready("Test1"), &sample, &ready_signatures);
+        assert_eq!(certificate.get_signers().len(), 3);
+        assert_eq!(certificate.verify(&keycards, 4, 4), Ok(()));
+        assert_eq!(
+            certificate.verify(&keycards, 4, 5),
+            Err(CertificateError::NotEnoughSignatures(4))
+        );
+        // The sample must have the size of the Delivery samples of the system.
+        assert_eq!(
+            certificate.verify(&keycards, 5, 4),
+            Err(CertificateError::InvalidSample(4))
+        );
+    }
+
+    #[test]
+    fn readies_of_other_topics_are_not_certified() {
+        let keychain = KeyChain::random();
+        let keycards = HashMap::from([(keychain.keycard().identity(), keychain.keycard())]);
+        let message = ready("Test1").in_topic(1).in_epoch(2);
+        let ready_signatures = HashMap::from([(
+            keychain.keycard().identity(),
+            vec![
+                signed_ready(&keychain, "Test1"),
+                sign(&keychain, ready("Test1").in_topic(1)),
+                sign(&keychain, message.clone()),
+            ],
+        )]);
+        let sample = HashMap::from([(keychain.keycard().identity(), 1)]);
+        let certificate = DeliveryCertificate::assemble(message, &sample, &ready_signatures);
+        assert_eq!(certificate.verify(&keycards, 1, 1), Ok(()));
+    }
+
+    #[test]
+    fn certificates_are_verified_from_files() {
+        let keychains: Vec<KeyChain> = (0..3).map(|_| KeyChain::random()).collect();
+        let members: Vec<StaticMember> = keychains
+            .iter()
+            .enumerate()
+            .map(|(i, kc)| StaticMember {
+                keycard: kc.keycard(),
+                address: format!("127.0.0.1:{}", 5000 + i).parse().unwrap(),
+            })
+            .collect();
+        // The peer drawn twice in the Delivery sample signs once, and weighs twice.
+        let ready_signatures: HashMap<Identity, Vec<SignedMessage>> = keychains[..2]
+            .iter()
+            .map(|kc| (kc.keycard().identity(), vec![signed_ready(kc, "Test1")]))
+            .collect();
+        let sample = sample(&keychains);
+        let certificate = DeliveryCertificate::assemble(ready("Test1"), &sample, &ready_signatures);
+        let dir = std::env::temp_dir();
+        let suffix = rand::random::<u64>();
+        let path = dir.join(format!("certificate_{}.json", suffix));
+        let members_path = dir.join(format!("members_{}.json", suffix));
+        let (path, members_path) = (path.to_str().unwrap(), members_path.to_str().unwrap());
+        fs::write(path, serde_json::to_string(&certificate).unwrap()).unwrap();
+        fs::write(members_path, serde_json::to_string(&members).unwrap()).unwrap();
+
+        let message = verify_file(path, members_path, 4, 3).unwrap();
+        assert_eq!(message.content, "Test1");
+        assert!(verify_file(path, members_path, 4, 4).is_err());
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(members_path);
+    }
+
+    #[test]
+    fn certificate_rejects_forged_content() {
+        let keychain = KeyChain::random();
+        let mut keycards: HashMap<Identity, KeyCard> = HashMap::new();
+        keycards.insert(keychain.keycard().identity(), keychain.keycard());
+        let mut ready_signatures: HashMap<Identity, Vec<SignedMessage>> = HashMap::new();
+        ready_signatures.insert(
+            keychain.keycard().identity(),
+            vec![signed_ready(&keychain, "Test1")],
+        );
+        let sample = HashMap::from([(keychain.keycard().identity(), 1)]);
+        let mut certificate =
+            DeliveryCertificate::assemble(ready("Test1"), &sample, &ready_signatures);
+        certificate.message = ready("Forged");
+        assert_eq!(
+            certificate.verify(&keycards, 1, 1),
+            Err(CertificateError::InvalidSignature(
+                keychain.keycard().identity()
+            ))
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use crate::layer::Layer;
+use crate::limits::Limits;
+use crate::membership::{Allowlist, UnknownPolicy};
+use crate::push::{Backoff, RetrySettings};
+use std::fs;
+
+/// Broadcast protocol run by the nodes : the sample-based SBR stack, or Bracha's deterministic reliable
+/// broadcast, all-to-all, used as a baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Sbr,
+    Bracha,
+}
+
+/// Sizes of the samples a Node draws in every epoch.
+#[derive(Clone, Copy, Debug)]
+pub struct SampleSizes {
+    pub gossip: usize,
+    pub echo: usize,
+    pub ready: usize,
+    pub delivery: usize,
+}
+
+/// How often a Node pulls the Messages it missed from random peers.
+#[derive(Clone, Copy, Debug)]
+pub struct PullSettings {
+    /// Milliseconds between two pulls, 0 disabling them.
+    pub interval: u64,
+    /// Number of peers a Summary is sent to in every pull.
+    pub peers: usize,
+    /// Maximum number of Messages sent in answer to a Summary.
+    pub batch: usize,
+}
+
+/// How the FIFO ordering buffers the Messages delivered out of order.
+#[derive(Clone, Copy, Debug)]
+pub struct FifoSettings {
+    /// Number of Messages buffered per source.
+    pub capacity: usize,
+    /// Number of sources whose order is tracked.
+    pub sources: usize,
+}
+
+/// Settings of a Node, taken from the configuration : its thresholds and samples, how it pushes, pulls
+/// and bounds what it stores, how it treats unknown and misbehaving peers, and the protocol, layers and
+/// applications it runs.
+#[derive(Clone, Debug)]
+pub struct NodeSettings {
+    pub echo_threshold: usize,
+    pub ready_threshold: usize,
+    pub delivery_threshold: usize,
+    pub sizes: SampleSizes,
+    pub pull: PullSettings,
+    pub retry: RetrySettings,
+    pub fault_threshold: usize,
+    pub limits: Limits,
+    /// Address, registry port and shard of the Rendezvous server.
+    pub rendezvous: (String, u16, u32),
+    pub allowlist: Option<Allowlist>,
+    pub unknown_policy: UnknownPolicy,
+    pub protocol: Protocol,
+    pub top: Layer,
+    pub fifo: Option<FifoSettings>,
+    pub causal: Option<usize>,
+    pub initial_balance: Option<u64>,
+    pub store: bool,
+    pub state_dir: Option<String>,
+    /// Milliseconds after which an undelivered instance no longer keeps its past epoch running.
+    pub epoch_timeout: u64,
+}
+
+/// Configuration of the Broadcast, read from the `broadcast.config` file. It is shared by the nodes
+/// spawned in a single process and by the nodes running as their own process.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub addr: String,
+    pub port: u16,
+    pub registry_port: u16,
+    pub spawn: usize,
+    pub g: usize,
+    pub e: usize,
+    pub e_thr: usize,
+    pub r: usize,
+    pub r_thr: usize,
+    pub d: usize,
+    pub d_thr: usize,
+    pub f_thr: usize,
+    pub exclusion_dir: String,
+    pub r_cap: usize,
+    pub i_cap: usize,
+    pub s_cap: usize,
+    pub unknown_log: bool,
+    pub unknown_refresh: usize,
+    pub operator_key: String,
+    pub operator_card: String,
+    pub operator_socket: String,
+    pub control_socket: String,
+    pub key_dir: String,
+    pub membership_file: String,
+    pub shard: u32,
+    pub allowlist_file: String,
+    pub protocol: Protocol,
+    pub layer: Layer,
+    pub fifo: bool,
+    pub fifo_buffer: usize,
+    pub fifo_sources: usize,
+    pub causal: bool,
+    pub causal_buffer: usize,
+    pub transfers: bool,
+    pub initial_balance: u64,
+    pub store: bool,
+    pub topics: Vec<u32>,
+    pub state_dir: String,
+    pub join: bool,
+    pub epoch_timeout: u64,
+    pub pull_interval: u64,
+    pub pull_peers: usize,
+    pub pull_batch: usize,
+    pub retry_backoff: Backoff,
+    pub retry_base: u64,
+    pub retry_cap: u64,
+    pub retry_jitter: f64,
+    pub push_timeout: u64,
+}
+
+impl Config {
+    /// Read the configuration file. Fields which are not specified keep their default value.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the configuration file.
+    ///
+    pub fn load(path: &str) -> Self {
+        let content = fs::read_to_string(path).expect("Error reading config file");
+        let lines = content.split("\n");
+        // Default values, if not specified in config file.
+        let mut config = Config {
+            addr: String::from("127.0.0.1"),
+            port: 4446,
+            registry_port: 4447,
+            spawn: 1,
+            g: 10,
+            e: 40,
+            e_thr: 10,
+            r: 30,
+            r_thr: 10,
+            d: 25,
+            d_thr: 14,
+            f_thr: 3,
+            exclusion_dir: String::from("exclusions"),
+            r_cap: 4,
+            i_cap: 16,
+            s_cap: 1024,
+            unknown_log: true,
+            unknown_refresh: 10,
+            operator_key: String::from("operator.key"),
+            operator_card: String::from("operator.card"),
+            operator_socket: String::from("sbr_operator.sock"),
+            control_socket: String::from("sbr.sock"),
+            key_dir: String::from("keys"),
+            membership_file: String::new(),
+            shard: 0,
+            allowlist_file: String::new(),
+            protocol: Protocol::Sbr,
+            layer: Layer::Contagion,
+            fifo: false,
+            fifo_buffer: 64,
+            fifo_sources: 1024,
+            causal: false,
+            causal_buffer: 1024,
+            transfers: false,
+            initial_balance: 100,
+            store: false,
+            topics: vec![0],
+            state_dir: String::new(),
+            join: false,
+            epoch_timeout: 60000,
+            pull_interval: 0,
+            pull_peers: 2,
+            pull_batch: 64,
+            retry_backoff: Backoff::Constant,
+            retry_base: 100,
+            retry_cap: 5000,
+            retry_jitter: 0.0,
+            push_timeout: 60000,
+        };
+        for line in lines {
+            let mut elems = line.split("=");
+            match elems.next().unwrap() {
+                "addr" => {
+                    config.addr = elems.next().unwrap().to_string();
+                }
+                "port" => {
+                    config.port = elems.next().unwrap().parse().unwrap();
+                }
+                "registry_port" => {
+                    config.registry_port = elems.next().unwrap().parse().unwrap();
+                }
+                "spawn" => {
+                    config.spawn = elems.next().unwrap().parse().unwrap();
+                }
+                "N" => {}
+                "shards" => {}
+                "rendezvous_state" => {}
+                "G" => {
+                    config.g = elems.next().unwrap().parse().unwrap();
+                }
+                "E" => {
+                    config.e = elems.next().unwrap().parse().unwrap();
+                }
+                "E_thr" => {
+                    config.e_thr = elems.next().unwrap().parse().unwrap();
+                }
+                "R" => {
+                    config.r = elems.next().unwrap().parse().unwrap();
+                }
+                "R_thr" => {
+                    config.r_thr = elems.next().unwrap().parse().unwrap();
+                }
+                "D" => {
+                    config.d = elems.next().unwrap().parse().unwrap();
+                }
+                "D_thr" => {
+                    config.d_thr = elems.next().unwrap().parse().unwrap();
+                }
+                "F_thr" => {
+                    config.f_thr = elems.next().unwrap().parse().unwrap();
+                }
+                "exclusion_dir" => {
+                    config.exclusion_dir = elems.next().unwrap().to_string();
+                }
+                "R_cap" => {
+                    config.r_cap = elems.next().unwrap().parse().unwrap();
+                }
+                "I_cap" => {
+                    config.i_cap = elems.next().unwrap().parse().unwrap();
+                }
+                "S_cap" => {
+                    config.s_cap = elems.next().unwrap().parse().unwrap();
+                }
+                "unknown_log" => {
+                    config.unknown_log = elems.next().unwrap().parse().unwrap();
+                }
+                "unknown_refresh" => {
+                    config.unknown_refresh = elems.next().unwrap().parse().unwrap();
+                }
+                "operator_key" => {
+                    config.operator_key = elems.next().unwrap().to_string();
+                }
+                "operator_card" => {
+                    config.operator_card = elems.next().unwrap().to_string();
+                }
+                "operator_socket" => {
+                    config.operator_socket = elems.next().unwrap().to_string();
+                }
+                "control_socket" => {
+                    config.control_socket = elems.next().unwrap().to_string();
+                }
+                "key_dir" => {
+                    config.key_dir = elems.next().unwrap().to_string();
+                }
+                "membership_file" => {
+                    config.membership_file = elems.next().unwrap().to_string();
+                }
+                "shard" => {
+                    config.shard = elems.next().unwrap().parse().unwrap();
+                }
+                "allowlist_file" => {
+                    config.allowlist_file = elems.next().unwrap().to_string();
+                }
+                "protocol" => {
+                    config.protocol = match elems.next().unwrap() {
+                        "sbr" => Protocol::Sbr,
+                        "bracha" => Protocol::Bracha,
+                        other => {
+                            panic!("Unknown protocol : {}", other);
+                        }
+                    };
+                }
+                "layer" => {
+                    let name = elems.next().unwrap();
+                    config.layer = match Layer::from_name(name) {
+                        Some(layer) => layer,
+                        None => {
+                            panic!("Unknown layer : {}", name);
+                        }
+                    };
+                }
+                "fifo" => {
+                    config.fifo = elems.next().unwrap().parse().unwrap();
+                }
+                "fifo_buffer" => {
+                    config.fifo_buffer = elems.next().unwrap().parse().unwrap();
+                }
+                "fifo_sources" => {
+                    config.fifo_sources = elems.next().unwrap().parse().unwrap();
+                }
+                "causal" => {
+                    config.causal = elems.next().unwrap().parse().unwrap();
+                }
+                "causal_buffer" => {
+                    config.causal_buffer = elems.next().unwrap().parse().unwrap();
+                }
+                "transfers" => {
+                    config.transfers = elems.next().unwrap().parse().unwrap();
+                }
+                "initial_balance" => {
+                    config.initial_balance = elems.next().unwrap().parse().unwrap();
+                }
+                "store" => {
+                    config.store = elems.next().unwrap().parse().unwrap();
+                }
+                "state_dir" => {
+                    config.state_dir = elems.next().unwrap().to_string();
+                }
+                "join" => {
+                    config.join = elems.next().unwrap().parse().unwrap();
+                }
+                "epoch_timeout" => {
+                    config.epoch_timeout = elems.next().unwrap().parse().unwrap();
+                }
+                "pull_interval" => {
+                    config.pull_interval = elems.next().unwrap().parse().unwrap();
+                }
+                "pull_peers" => {
+                    config.pull_peers = elems.next().unwrap().parse().unwrap();
+                }
+                "pull_batch" => {
+                    config.pull_batch = elems.next().unwrap().parse().unwrap();
+                }
+                "retry_backoff" => {
+                    let name = elems.next().unwrap();
+                    config.retry_backoff = match Backoff::from_name(name) {
+                        Some(backoff) => backoff,
+                        None => {
+                            panic!("Unknown retry backoff : {}", name);
+                        }
+                    };
+                }
+                "retry_base" => {
+                    config.retry_base = elems.next().unwrap().parse().unwrap();
+                }
+                "retry_cap" => {
+                    config.retry_cap = elems.next().unwrap().parse().unwrap();
+                }
+                "retry_jitter" => {
+                    config.retry_jitter = elems.next().unwrap().parse().unwrap();
+                }
+                "push_timeout" => {
+                    config.push_timeout = elems.next().unwrap().parse().unwrap();
+                }
+                "topics" => {
+                    config.topics = elems
+                        .next()
+                        .unwrap()
+                        .split(",")
+                        .map(|topic| topic.trim())
+                        .filter(|topic| !topic.is_empty())
+                        .map(|topic| topic.parse().unwrap())
+                        .collect();
+                    // Without any topic, the nodes run the default one.
+                    if config.topics.is_empty() {
+                        config.topics = vec![0];
+                    }
+                }
+                "" => {}
+                _ => {
+                    println!("Unknown configuration : {}", line);
+                }
+            }
+        }
+        config
+    }
+
+    /// The settings of the Nodes.
+    pub fn node_settings(&self) -> NodeSettings {
+        NodeSettings {
+            echo_threshold: self.e_thr,
+            ready_threshold: self.r_thr,
+            delivery_threshold: self.d_thr,
+            sizes: self.sample_sizes(),
+            pull: self.pull_settings(),
+            retry: self.retry_settings(),
+            fault_threshold: self.f_thr,
+            limits: self.limits(),
+            rendezvous: (self.addr.clone(), self.registry_port, self.shard),
+            allowlist: self.allowlist(),
+            unknown_policy: self.unknown_policy(),
+            protocol: self.protocol,
+            top: self.layer,
+            fifo: self.fifo_settings(),
+            causal: self.causal_capacity(),
+            initial_balance: self.initial_balance(),
+            store: self.store,
+            state_dir: self.state_dir(),
+            epoch_timeout: self.epoch_timeout,
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            ready_per_peer: self.r_cap,
+            ready_per_instance: self.i_cap,
+            instances_per_source: self.s_cap,
+        }
+    }
+
+    pub fn sample_sizes(&self) -> SampleSizes {
+        SampleSizes {
+            gossip: self.g,
+            echo: self.e,
+            ready: self.r,
+            delivery: self.d,
+        }
+    }
+
+    pub fn pull_settings(&self) -> PullSettings {
+        PullSettings {
+            interval: self.pull_interval,
+            peers: self.pull_peers,
+            batch: self.pull_batch,
+        }
+    }
+
+    pub fn retry_settings(&self) -> RetrySettings {
+        RetrySettings {
+            backoff: self.retry_backoff,
+            base: self.retry_base,
+            cap: self.retry_cap,
+            jitter: self.retry_jitter,
+            timeout: self.push_timeout,
+        }
+    }
+
+    /// A static membership is fixed, so it is never refreshed.
+    pub fn unknown_policy(&self) -> UnknownPolicy {
+        let refresh_after = if self.membership_file.is_empty() {
+            self.unknown_refresh
+        } else {
+            0
+        };
+        UnknownPolicy {
+            log: self.unknown_log,
+            refresh_after,
+        }
+    }
+
+    /// The buffers of the FIFO ordering, if enabled.
+    pub fn fifo_settings(&self) -> Option<FifoSettings> {
+        if self.fifo {
+            Some(FifoSettings {
+                capacity: self.fifo_buffer,
+                sources: self.fifo_sources,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The number of Messages waiting for their predecessors in the causal ordering, if enabled.
+    pub fn causal_capacity(&self) -> Option<usize> {
+        if self.causal {
+            Some(self.causal_buffer)
+        } else {
+            None
+        }
+    }
+
+    /// The initial balance of every account, if the Nodes run the asset transfer application.
+    pub fn initial_balance(&self) -> Option<u64> {
+        if self.transfers {
+            Some(self.initial_balance)
+        } else {
+            None
+        }
+    }
+
+    /// The directory of the journals of the Nodes, if they keep one.
+    pub fn state_dir(&self) -> Option<String> {
+        if self.state_dir.is_empty() {
+            None
+        } else {
+            Some(self.state_dir.clone())
+        }
+    }
+
+    /// The Identities allowed in the system, if an allowlist file is configured.
+    pub fn allowlist(&self) -> Option<Allowlist> {
+        if self.allowlist_file.is_empty() {
+            return None;
+        }
+        match Allowlist::load(&self.allowlist_file) {
+            Ok(allowlist) => Some(allowlist),
+            Err(e) => {
+                panic!("Error reading allowlist file : {}", e);
+            }
+        }
+    }
+
+    /// Path of the file holding the KeyChain of the given node.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the node.
+    ///
+    pub fn key_file(&self, id: usize) -> String {
+        format!("{}/node_{}.key", self.key_dir, id)
+    }
+}
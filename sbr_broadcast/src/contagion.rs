@@ -1,16 +1,20 @@
+use crate::certificate::DeliveryCertificate;
+use crate::journal::{record, Journal, Record};
+use crate::layer::BroadcastLayer;
+use crate::limits::{store_ready, CapCounters, Limits};
 use crate::message::{Message, SignedMessage};
 use crate::message_headers::{Ready, ReadySubscription};
+use crate::push::Pusher;
+use crate::tasks::Tasks;
 use crate::utils::{check_message_occurrences_contagion, sample_contagion};
+use async_trait::async_trait;
 use itertools::Itertools;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::OpenOptions;
 use std::io::Write;
 use std::sync::Arc;
-use std::time::Duration;
-use talk::broadcast::{BestEffort, BestEffortSettings};
 use talk::crypto::{Identity, KeyCard, KeyChain};
-use talk::time::sleep_schedules::Constant;
-use talk::unicast::{Acknowledgement, PushSettings, Sender};
+use talk::unicast::Sender;
 use tokio::sync::Mutex;
 
 /// Initialises the Ready set and Delivery set used in the Contagion algorithm. Sample randomly a number
@@ -45,34 +49,37 @@ pub async fn init(
 ///
 /// * `keychain` - KeyChain used to sign the Message.
 /// * `node_sender` - The Node's Sender used to send Messages.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `ready_replies` - The Ready replies used to get the Ready peers.
 /// * `delivery_replies` - The Delivery replies used to get the Delivery peers.
+/// * `topic` - The topic the peers are sampled for.
+/// * `epoch` - The epoch the peers are sampled in.
+/// * `incarnation` - The number of times the Node restarted, which lets the peers accept the
+///   subscriptions of a restarted Node.
 ///
 pub async fn ready_subscribe(
     keychain: KeyChain,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     ready_replies: HashMap<Identity, Vec<Message>>,
     delivery_replies: HashMap<Identity, Vec<Message>>,
+    topic: u32,
+    epoch: u64,
+    incarnation: u64,
 ) {
-    let push_settings = PushSettings {
-        stop_condition: Acknowledgement::Strong,
-        retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-    };
-    let settings: BestEffortSettings = BestEffortSettings { push_settings };
     let mut peers_ready: Vec<Identity> = ready_replies.into_keys().collect();
     let mut peers_delivery: Vec<Identity> = delivery_replies.into_keys().collect();
     peers_ready.append(&mut peers_delivery);
     let peers_ready: Vec<Identity> = peers_ready.into_iter().unique().collect::<Vec<_>>();
-    let msg = Message::new(5, String::from("ReadySubscription"));
+    let msg = Message::new(5, String::from("ReadySubscription"))
+        .in_topic(topic)
+        .in_epoch(epoch)
+        .in_incarnation(incarnation);
     let signature = keychain.sign(&ReadySubscription(msg.clone())).unwrap();
     let signed_msg = SignedMessage::new(msg, signature);
-    let best_effort = BestEffort::new(
-        node_sender.clone(),
-        peers_ready,
-        signed_msg,
-        settings.clone(),
-    );
-    best_effort.complete().await;
+    pusher
+        .best_effort("ready_subscribe", node_sender, peers_ready, signed_msg)
+        .await;
     my_print!("Finished Contagion Subscriptions");
 }
 
@@ -107,46 +114,136 @@ pub async fn ready_subscription(
         }
     }
     let mut locked_ready_subscribers = ready_subscribers.lock().await;
-    locked_ready_subscribers.push(from);
+    if !locked_ready_subscribers.contains(&from) {
+        locked_ready_subscribers.push(from);
+    }
     drop(locked_ready_subscribers);
 }
 
+/// Contagion, the Probabilistic Reliable Broadcast layer of an instance : the Messages delivered by Sieve
+/// are sent as Ready to the Ready subscribers, and a Message Ready for enough Delivery peers is
+/// delivered to the layer above.
+pub struct ContagionLayer {
+    pub keychain: KeyChain,
+    pub id: usize,
+    pub node_sender: Sender<SignedMessage>,
+    pub pusher: Pusher,
+    pub ready_subscribers: Vec<Identity>,
+    pub ready_messages: Arc<Mutex<Vec<Message>>>,
+    pub ready_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
+    pub duplicate_ready: HashMap<Identity, usize>,
+    pub delivery_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
+    pub duplicate_delivery: HashMap<Identity, usize>,
+    pub r_thr: usize,
+    pub d_thr: usize,
+    pub delivered: Arc<Mutex<Option<Message>>>,
+    pub ready_signatures: Arc<Mutex<HashMap<Identity, Vec<SignedMessage>>>>,
+    pub certificate: Arc<Mutex<Option<DeliveryCertificate>>>,
+    pub limits: Limits,
+    pub cap_counters: Arc<Mutex<CapCounters>>,
+    pub journal: Option<Arc<Journal>>,
+    pub tasks: Tasks,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+impl ContagionLayer {
+    /// Handle a Ready received from a peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `signed_msg` - The verified signed Ready Message.
+    /// * `from` - The Identity of the Node sending the Ready.
+    ///
+    pub async fn ready_received(&self, signed_msg: SignedMessage, from: Identity) {
+        deliver_ready(
+            self.keychain.clone(),
+            self.id,
+            signed_msg,
+            from,
+            self.ready_subscribers.clone(),
+            self.ready_replies.clone(),
+            self.duplicate_ready.clone(),
+            self.delivery_replies.clone(),
+            self.duplicate_delivery.clone(),
+            self.node_sender.clone(),
+            self.pusher.clone(),
+            self.ready_messages.clone(),
+            self.r_thr,
+            self.d_thr,
+            self.delivered.clone(),
+            self.ready_signatures.clone(),
+            self.certificate.clone(),
+            self.limits,
+            self.cap_counters.clone(),
+            self.journal.clone(),
+            self.tasks.clone(),
+            self.upper.clone(),
+        )
+        .await;
+    }
+}
+
+#[async_trait]
+impl BroadcastLayer for ContagionLayer {
+    /// Send a Ready for a Message delivered by Sieve.
+    async fn deliver(&self, message: Message) {
+        deliver(
+            self.keychain.clone(),
+            message,
+            self.node_sender.clone(),
+            self.pusher.clone(),
+            self.ready_subscribers.clone(),
+            self.ready_messages.clone(),
+            self.journal.clone(),
+        )
+        .await;
+    }
+}
+
 /// Probabilistic Consistent Broadcast Deliver. If the Message is verified, send a Ready of the Message to the
-/// Ready peers.
+/// Ready peers, unless the Node is already Ready for it. The Ready is journaled before it is set and
+/// sent, and neither if it cannot be journaled.
 ///
 /// # Arguments
 ///
 /// * `keychain` - KeyChain used to sign the Message.
 /// * `message` - The received Message.
 /// * `node_sender` - The Node's Sender used to send the Gossip Subscription to the peers.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `ready_subscribers` - The Ready peers subscribed to this Node.
 /// * `ready_messages` - The Atomic Reference Counter to the vector of all Messages which are ready.
+/// * `journal` - The journal of the Node, if it keeps one.
 ///
 pub async fn deliver(
     keychain: KeyChain,
     message: Message,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     ready_subscribers: Vec<Identity>,
     ready_messages: Arc<Mutex<Vec<Message>>>,
+    journal: Option<Arc<Journal>>,
 ) {
     let mut locked_ready_replies = ready_messages.lock().await;
-    locked_ready_replies.push(message.clone());
+    if locked_ready_replies
+        .iter()
+        .any(|r| r.content == message.content)
+    {
+        return;
+    }
+    let msg: Message = message.with_type(2);
+    if msg.instance.is_some() {
+        let message = msg.clone();
+        if !record(&journal, Record::Ready { message }).await {
+            return;
+        }
+    }
+    locked_ready_replies.push(message);
     drop(locked_ready_replies);
-    let msg: Message = Message::new(2, message.content.clone());
     let signature = keychain.sign(&Ready(msg.clone())).unwrap();
     let signed_msg = SignedMessage::new(msg, signature);
-    let push_settings = PushSettings {
-        stop_condition: Acknowledgement::Strong,
-        retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-    };
-    let settings: BestEffortSettings = BestEffortSettings { push_settings };
-    let best_effort = BestEffort::new(
-        node_sender.clone(),
-        ready_subscribers.clone(),
-        signed_msg.clone(),
-        settings.clone(),
-    );
-    best_effort.complete().await;
+    pusher
+        .best_effort("ready", node_sender, ready_subscribers, signed_msg)
+        .await;
 }
 
 /// Deliver a Ready type Message. Check if the sending Node is in the Ready peers and/or Delivery peers,
@@ -164,10 +261,18 @@ pub async fn deliver(
 /// * `delivery_replies` - The Atomic Reference Counter to the Delivery replies to update.
 /// * `duplicate_delivery` - The HashMap containing information on Delivery peers sampled multiple times.
 /// * `node_sender` - The Node's Sender used to send the Gossip Subscription to the peers.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `ready_messages` - The Atomic Reference Counter to the Messages which are Ready.
 /// * `r_thr` - The threshold defining if enough Ready replies have been received.
 /// * `d_thr` - The threshold defining if enough Delivery replies have been received.
 /// * `delivered` - The Atomic Reference Counter to the delivered Message.
+/// * `ready_signatures` - The Atomic Reference Counter to the signed Ready Messages received from Delivery peers.
+/// * `certificate` - The Atomic Reference Counter to the DeliveryCertificate of the delivered Message.
+/// * `limits` - The bounds on the Ready Messages stored per peer and per instance.
+/// * `cap_counters` - The Atomic Reference Counter to the counters of dropped Ready Messages.
+/// * `journal` - The journal of the Node, if it keeps one.
+/// * `tasks` - The tasks of the Node, which the check of the Ready replies is spawned in.
+/// * `upper` - The layer above Contagion, the application.
 ///
 pub async fn deliver_ready(
     keychain: KeyChain,
@@ -180,42 +285,68 @@ pub async fn deliver_ready(
     delivery_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
     duplicate_delivery: HashMap<Identity, usize>,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     ready_messages: Arc<Mutex<Vec<Message>>>,
     r_thr: usize,
     d_thr: usize,
     delivered: Arc<Mutex<Option<Message>>>,
+    ready_signatures: Arc<Mutex<HashMap<Identity, Vec<SignedMessage>>>>,
+    certificate: Arc<Mutex<Option<DeliveryCertificate>>>,
+    limits: Limits,
+    cap_counters: Arc<Mutex<CapCounters>>,
+    journal: Option<Arc<Journal>>,
+    tasks: Tasks,
+    upper: Arc<dyn BroadcastLayer>,
 ) {
-    let rp: Vec<Identity> = ready_replies.lock().await.clone().into_keys().collect();
     let new_reply: Message = signed_msg.clone().get_message();
-    if rp.contains(&from) {
-        drop(rp);
-        let mut locked_ready_replies = ready_replies.lock().await;
-        let mut old_messages: Vec<Message> = locked_ready_replies.get(&from).unwrap().clone();
-        old_messages.push(new_reply.clone());
-        locked_ready_replies.insert(from, old_messages.clone());
-        drop(locked_ready_replies);
-        tokio::spawn(async move {
-            check_ready(
-                keychain,
-                from.clone(),
-                node_sender,
-                ready_messages,
-                r_thr,
-                ready_subscribers,
-                ready_replies,
-                duplicate_ready,
-            )
+    let mut locked_ready_replies = ready_replies.lock().await;
+    let mut locked_counters = cap_counters.lock().await;
+    let stored_ready = store_ready(
+        &mut locked_ready_replies,
+        from,
+        new_reply.clone(),
+        limits,
+        &mut locked_counters,
+    );
+    drop(locked_counters);
+    drop(locked_ready_replies);
+    if stored_ready {
+        tasks
+            .spawn(async move {
+                check_ready(
+                    keychain,
+                    from.clone(),
+                    node_sender,
+                    pusher,
+                    ready_messages,
+                    r_thr,
+                    ready_subscribers,
+                    ready_replies,
+                    duplicate_ready,
+                    journal,
+                )
+                .await;
+            })
             .await;
-        });
     }
-    let dp: Vec<Identity> = delivery_replies.lock().await.clone().into_keys().collect();
-    if dp.contains(&from) {
-        drop(dp);
-        let mut locked_delivery_replies = delivery_replies.lock().await;
-        let mut old_messages: Vec<Message> = locked_delivery_replies.get(&from).unwrap().clone();
-        old_messages.push(new_reply.clone());
-        locked_delivery_replies.insert(from, old_messages.clone());
-        drop(locked_delivery_replies);
+    let mut locked_delivery_replies = delivery_replies.lock().await;
+    let mut locked_counters = cap_counters.lock().await;
+    let stored_delivery = store_ready(
+        &mut locked_delivery_replies,
+        from,
+        new_reply.clone(),
+        limits,
+        &mut locked_counters,
+    );
+    drop(locked_counters);
+    drop(locked_delivery_replies);
+    if stored_delivery {
+        let mut locked_ready_signatures = ready_signatures.lock().await;
+        locked_ready_signatures
+            .entry(from)
+            .or_insert(Vec::new())
+            .push(signed_msg.clone());
+        drop(locked_ready_signatures);
         check_delivery(
             id,
             from,
@@ -223,6 +354,9 @@ pub async fn deliver_ready(
             delivered,
             delivery_replies,
             duplicate_delivery,
+            ready_signatures,
+            certificate,
+            upper,
         )
         .await;
     }
@@ -236,21 +370,25 @@ pub async fn deliver_ready(
 /// * `keychain` - KeyChain used to sign the Message.
 /// * `from` - The Identity of the Node sending the Ready.
 /// * `node_sender` - The Node's Sender used to send the Gossip Subscription to the peers.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `ready_messages` - The Atomic Reference Counter to the vector of all Messages which are ready.
 /// * `r_thr` - The threshold defining if enough Ready replies have been received.
 /// * `ready_subscribers` - The Ready peers subscribed to this Node.
 /// * `ready_replies` - The Atomic Reference Counter to the Ready replies from the chosen peers.
 /// * `duplicate_ready` - The HashMap containing information on Ready peers sampled multiple times.
+/// * `journal` - The journal of the Node, if it keeps one.
 ///
 async fn check_ready(
     keychain: KeyChain,
     from: Identity,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     ready_messages: Arc<Mutex<Vec<Message>>>,
     r_thr: usize,
     ready_subscribers: Vec<Identity>,
     ready_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
     duplicate_ready: HashMap<Identity, usize>,
+    journal: Option<Arc<Journal>>,
 ) {
     let ready_replies: HashMap<Identity, Vec<Message>> = ready_replies.lock().await.clone();
     if ready_replies
@@ -259,35 +397,43 @@ async fn check_ready(
         .collect::<Vec<Identity>>()
         .contains(&from)
     {
-        let occ = check_message_occurrences_contagion(ready_replies, duplicate_ready);
+        let occ = check_message_occurrences_contagion(ready_replies.clone(), duplicate_ready);
         for m in occ {
             if m.1 >= r_thr {
-                let msg = Message::new(2, m.0.clone());
-                let signature = keychain.sign(&Ready(msg.clone())).unwrap();
+                let msg = find_reply(&ready_replies, &m.0).with_type(2);
                 let mut locked_ready_replies = ready_messages.lock().await;
+                // A Message already Ready has already been sent to the Ready peers.
+                if locked_ready_replies
+                    .iter()
+                    .any(|r| r.content == msg.content)
+                {
+                    continue;
+                }
+                if msg.instance.is_some() {
+                    let message = msg.clone();
+                    if !record(&journal, Record::Ready { message }).await {
+                        continue;
+                    }
+                }
                 locked_ready_replies.push(msg.clone());
                 drop(locked_ready_replies);
-
-                let push_settings = PushSettings {
-                    stop_condition: Acknowledgement::Strong,
-                    retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-                };
+                let signature = keychain.sign(&Ready(msg.clone())).unwrap();
                 let signed_msg = SignedMessage::new(msg, signature);
-                let settings: BestEffortSettings = BestEffortSettings { push_settings };
-                let best_effort = BestEffort::new(
-                    node_sender.clone(),
-                    ready_subscribers.clone(),
-                    signed_msg.clone(),
-                    settings.clone(),
-                );
-                best_effort.complete().await;
+                pusher
+                    .best_effort(
+                        "ready",
+                        node_sender.clone(),
+                        ready_subscribers.clone(),
+                        signed_msg,
+                    )
+                    .await;
             }
         }
     }
 }
 
 /// Check the status of the Delivery replies received. If more than the threshold have been
-/// received Probabilistic Reliable Broadcast Deliver the Message.
+/// received Probabilistic Reliable Broadcast Deliver the Message to the layer above.
 ///
 /// # Arguments
 ///
@@ -297,6 +443,9 @@ async fn check_ready(
 /// * `delivered` - The Atomic Reference Counter to the delivered Message.
 /// * `delivery_replies` - The Atomic Reference Counter to the delivery replies from the chosen peers.
 /// * `duplicate_ready` - The HashMap containing information on Ready peers sampled multiple times.
+/// * `ready_signatures` - The Atomic Reference Counter to the signed Ready Messages received from Delivery peers.
+/// * `certificate` - The Atomic Reference Counter to the DeliveryCertificate to assemble on delivery.
+/// * `upper` - The layer above Contagion, the application.
 ///
 async fn check_delivery(
     id: usize,
@@ -305,6 +454,9 @@ async fn check_delivery(
     delivered: Arc<Mutex<Option<Message>>>,
     delivery_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
     duplicate_delivery: HashMap<Identity, usize>,
+    ready_signatures: Arc<Mutex<HashMap<Identity, Vec<SignedMessage>>>>,
+    certificate: Arc<Mutex<Option<DeliveryCertificate>>>,
+    upper: Arc<dyn BroadcastLayer>,
 ) {
     let delivery_replies: HashMap<Identity, Vec<Message>> = delivery_replies.lock().await.clone();
     if delivery_replies
@@ -315,15 +467,29 @@ async fn check_delivery(
     {
         let mut locked_delivered = delivered.lock().await;
         if locked_delivered.is_none() {
-            let occ = check_message_occurrences_contagion(delivery_replies, duplicate_delivery);
+            let occ = check_message_occurrences_contagion(
+                delivery_replies.clone(),
+                duplicate_delivery.clone(),
+            );
             for m in occ {
                 if m.1 >= d_thr {
-                    my_print!(format!("{} delivered : {}", id, m.0.clone()));
-                    let msg = Message::new(2, m.0.clone());
-                    let msg: Option<Message> = Some(msg);
-                    *locked_delivered = msg.clone();
+                    let msg = find_reply(&delivery_replies, &m.0).with_type(2);
+                    *locked_delivered = Some(msg.clone());
                     drop(locked_delivered);
-                    prb_deliver(m.0.clone(), id.to_string()).await;
+                    upper.deliver(msg.clone()).await;
+                    let new_certificate = DeliveryCertificate::assemble(
+                        msg,
+                        &duplicate_delivery,
+                        &ready_signatures.lock().await.clone(),
+                    );
+                    my_print!(format!(
+                        "{} certificate signed by {} Delivery peers",
+                        id,
+                        new_certificate.get_signers().len()
+                    ));
+                    let mut locked_certificate = certificate.lock().await;
+                    *locked_certificate = Some(new_certificate);
+                    drop(locked_certificate);
                     break;
                 }
             }
@@ -331,8 +497,24 @@ async fn check_delivery(
     }
 }
 
-/// Probabilistic Reliable Broadcast Deliver. Save the delivered Message in a file with a unique name.
-/// This is used to see which Nodes have delivered which Message.
+/// Find the reply with the given content, which keeps the instance the content was sent for.
+///
+/// # Arguments
+///
+/// * `replies` - The replies of an instance.
+/// * `content` - The content to look for.
+///
+fn find_reply(replies: &HashMap<Identity, Vec<Message>>, content: &String) -> Message {
+    replies
+        .values()
+        .flatten()
+        .find(|m| m.content == *content)
+        .unwrap()
+        .clone()
+}
+
+/// Probabilistic Reliable Broadcast Deliver. Append the delivered Message to a file with a unique name.
+/// This is used to see which Nodes have delivered which Messages.
 ///
 /// # Arguments
 ///
@@ -342,13 +524,19 @@ async fn check_delivery(
 pub async fn prb_deliver(message: String, uid: String) {
     // *** Optional lines used to verify delivery of messages. ***
     loop {
-        let file = File::create(format!("check/tmp_{}.txt", uid));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("check/tmp_{}.txt", uid));
         match file {
             Ok(mut f) => {
                 loop {
-                    let r = write!(f, "DELIVERED : {}", message);
+                    let r = writeln!(f, "DELIVERED : {}", message);
                     match r {
                         Ok(_) => {
+                            if let Err(e) = f.sync_data() {
+                                println!("ERROR : prb_deliver sync : {}", e);
+                            }
                             break;
                         }
                         Err(e) => {
@@ -0,0 +1,935 @@
+use crate::causal::{CausalBuffer, CausalStats};
+use crate::certificate::DeliveryCertificate;
+use crate::evidence::Evidence;
+use crate::exclusion::ExclusionList;
+use crate::fifo::{FifoBuffer, FifoStats};
+use crate::instance::InstanceState;
+use crate::limits::CapCounters;
+use crate::link::Bootstrap;
+use crate::membership::Epoch;
+use crate::message::Instance;
+use crate::operator::Operator;
+use crate::push::PushCounters;
+use crate::store::{LogEntry, Put, Store};
+use crate::transfer::{Account, Ledger, TRANSFER_TOPIC};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use talk::crypto::Identity;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::time::Instant;
+
+/// Phase of the lifecycle of a Node.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Registering with the Rendez-Vous server and waiting for the membership to be complete.
+    Setup,
+    /// Listening, and waiting for every sampled peer to acknowledge the subscriptions.
+    Subscribing,
+    /// Every subscription is acknowledged, the Node can broadcast.
+    Ready,
+    Stopped,
+}
+
+/// Requests accepted on the control socket, one JSON object per line. The source of a Broadcast or a
+/// Transfer is chosen either by its index or by its Identity. The account credited by a Transfer is a
+/// node index or an Identity, as are the members leaving in a new epoch. Requests about a topic apply
+/// to topic 0 if it is not given. Transfers are always broadcast in the transfer topic.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Broadcast {
+        node: Option<usize>,
+        identity: Option<String>,
+        payload: String,
+        #[serde(default)]
+        topic: u32,
+    },
+    Transfer {
+        node: Option<usize>,
+        identity: Option<String>,
+        to: String,
+        amount: u64,
+    },
+    Balance {
+        node: usize,
+        account: Option<String>,
+    },
+    Put {
+        node: Option<usize>,
+        identity: Option<String>,
+        key: String,
+        value: String,
+        #[serde(default)]
+        topic: u32,
+    },
+    Get {
+        node: usize,
+        key: String,
+        #[serde(default)]
+        topic: u32,
+    },
+    Log {
+        node: usize,
+        #[serde(default)]
+        topic: u32,
+    },
+    Subscribe {
+        node: usize,
+        topics: Vec<u32>,
+    },
+    Membership {
+        node: usize,
+    },
+    Epoch {
+        join_shard: Option<u32>,
+        #[serde(default)]
+        leave: Vec<String>,
+    },
+    List,
+    Dump {
+        node: usize,
+        #[serde(default)]
+        topic: u32,
+    },
+    Stop {
+        node: usize,
+    },
+    Shutdown,
+}
+
+/// Responses sent on the control socket, one JSON object per line.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Instance {
+        source: String,
+        sequence: u64,
+    },
+    Nodes {
+        ready: bool,
+        nodes: Vec<NodeInfo>,
+    },
+    State {
+        state: NodeState,
+    },
+    Balances {
+        accounts: Vec<Account>,
+        rejected: usize,
+    },
+    Value {
+        key: String,
+        value: Option<String>,
+    },
+    Log {
+        log: BTreeMap<String, Vec<LogEntry>>,
+    },
+    Membership {
+        epoch: u64,
+        members: Vec<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NodeInfo {
+    pub id: usize,
+    pub identity: String,
+    pub phase: Phase,
+    pub delivered: usize,
+    pub topics: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeliveredInstance {
+    pub source: String,
+    pub sequence: u64,
+    pub content: String,
+    pub certificate_signers: usize,
+    /// The delivery certificate, which `verify` checks outside the system.
+    pub certificate: Option<DeliveryCertificate>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NodeState {
+    pub id: usize,
+    pub identity: String,
+    pub phase: Phase,
+    pub topic: u32,
+    pub epoch: u64,
+    pub subscribed: bool,
+    pub delivered: Vec<DeliveredInstance>,
+    pub gossip_peers: usize,
+    pub echo_subscribers: usize,
+    pub ready_subscribers: usize,
+    pub evidence: usize,
+    pub excluded: usize,
+    pub cap_counters: CapCounters,
+    pub push_counters: PushCounters,
+    pub unknown_senders: usize,
+    pub unauthorized_control: usize,
+    pub messages_received: usize,
+    pub fifo: Option<FifoStats>,
+    pub causal: Option<CausalStats>,
+}
+
+/// References to the state of a running Node, used to report it on the control socket.
+#[derive(Clone)]
+pub struct NodeView {
+    pub gossip_peers: Arc<Mutex<Vec<Identity>>>,
+    pub echo_subscribers: Arc<Mutex<Vec<Identity>>>,
+    pub ready_subscribers: Arc<Mutex<Vec<Identity>>>,
+    pub instances: Arc<Mutex<HashMap<Instance, InstanceState>>>,
+    pub evidence: Arc<Mutex<Vec<Evidence>>>,
+    pub exclusions: Arc<Mutex<ExclusionList>>,
+    pub cap_counters: Arc<Mutex<CapCounters>>,
+    pub push_counters: Arc<Mutex<PushCounters>>,
+    pub unknown_senders: Arc<Mutex<HashMap<Identity, usize>>>,
+    pub unauthorized_control: Arc<Mutex<usize>>,
+    pub received: Arc<Mutex<usize>>,
+    pub fifo: Option<Arc<Mutex<FifoBuffer>>>,
+    pub causal: Option<Arc<Mutex<CausalBuffer>>>,
+    pub ledger: Option<Arc<Mutex<Ledger>>>,
+    pub store: Option<Arc<Mutex<Store>>>,
+    pub topic: u32,
+    pub epoch: u64,
+    pub subscribed: Arc<AtomicBool>,
+}
+
+impl NodeView {
+    /// The instances delivered by the Node, ordered by source and sequence.
+    async fn delivered(&self) -> Vec<DeliveredInstance> {
+        let instances: Vec<(Instance, InstanceState)> = self
+            .instances
+            .lock()
+            .await
+            .iter()
+            .map(|(instance, state)| (*instance, state.clone()))
+            .collect();
+        let mut delivered = Vec::new();
+        for (instance, state) in instances.into_iter() {
+            let content = match state.delivered_msg.lock().await.as_ref() {
+                Some(msg) => msg.content.clone(),
+                None => {
+                    continue;
+                }
+            };
+            let certificate = state.certificate.lock().await.clone();
+            let certificate_signers = match certificate.as_ref() {
+                Some(certificate) => certificate.get_signers().len(),
+                None => 0,
+            };
+            delivered.push(DeliveredInstance {
+                source: format!("{:?}", instance.source),
+                sequence: instance.sequence,
+                content,
+                certificate_signers,
+                certificate,
+            });
+        }
+        delivered.sort_by(|a, b| (&a.source, a.sequence).cmp(&(&b.source, b.sequence)));
+        delivered
+    }
+}
+
+/// Handle kept by the control server for every Node it manages, with the views of its topics in its
+/// current epoch, and the members of that epoch once the Node joined one.
+#[derive(Clone)]
+pub struct NodeHandle {
+    pub id: usize,
+    pub identity: Identity,
+    pub phase: Arc<Mutex<Phase>>,
+    pub views: Arc<Mutex<HashMap<u32, NodeView>>>,
+    pub membership: Arc<Mutex<Option<Epoch>>>,
+    pub stop: Arc<Notify>,
+    next_sequence: Arc<Mutex<HashMap<u32, u64>>>,
+}
+
+impl NodeHandle {
+    pub fn new(id: usize, identity: Identity) -> Self {
+        NodeHandle {
+            id,
+            identity,
+            phase: Arc::new(Mutex::new(Phase::Setup)),
+            views: Arc::new(Mutex::new(HashMap::new())),
+            membership: Arc::new(Mutex::new(None)),
+            stop: Arc::new(Notify::new()),
+            next_sequence: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Allocate the next instance broadcast by this Node in a topic, so that every send gets its own
+    /// instance. Every topic numbers its instances from 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic of the instance.
+    ///
+    pub async fn next_instance(&self, topic: u32) -> Instance {
+        let mut locked_sequence = self.next_sequence.lock().await;
+        let sequence = locked_sequence.entry(topic).or_insert(0);
+        let instance = Instance {
+            source: self.identity,
+            sequence: *sequence,
+        };
+        *sequence += 1;
+        instance
+    }
+
+    /// Continue the numbering of the instances of a restarted Node in a topic from the given sequence.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    /// * `sequence` - The first sequence the Node has not used.
+    ///
+    pub async fn resume(&self, topic: u32, sequence: u64) {
+        let mut locked_sequence = self.next_sequence.lock().await;
+        let next = locked_sequence.entry(topic).or_insert(0);
+        *next = (*next).max(sequence);
+    }
+
+    /// The topics run by the Node, once it is set up.
+    pub async fn topics(&self) -> Vec<u32> {
+        let mut topics: Vec<u32> = self.views.lock().await.keys().cloned().collect();
+        topics.sort_unstable();
+        topics
+    }
+
+    /// The view of a topic of the Node.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    ///
+    async fn view(&self, topic: u32) -> Result<NodeView, String> {
+        let locked_views = self.views.lock().await;
+        if locked_views.is_empty() {
+            return Err(format!("Node {} is not set up yet", self.id));
+        }
+        locked_views
+            .get(&topic)
+            .cloned()
+            .ok_or(format!("Node {} does not run topic {}", self.id, topic))
+    }
+
+    /// The number of instances delivered by the Node, in every topic.
+    async fn delivered(&self) -> usize {
+        let views: Vec<NodeView> = self.views.lock().await.values().cloned().collect();
+        let mut delivered = 0;
+        for view in views.iter() {
+            delivered += view.delivered().await.len();
+        }
+        delivered
+    }
+
+    async fn info(&self) -> NodeInfo {
+        NodeInfo {
+            id: self.id,
+            identity: format!("{:?}", self.identity),
+            phase: *self.phase.lock().await,
+            delivered: self.delivered().await,
+            topics: self.topics().await,
+        }
+    }
+
+    /// The ledger of the Node, shared by its topics, if it runs the asset transfer application.
+    async fn ledger(&self) -> Result<Arc<Mutex<Ledger>>, String> {
+        self.view(TRANSFER_TOPIC)
+            .await?
+            .ledger
+            .ok_or(String::from("The asset transfers are not enabled"))
+    }
+
+    /// The store of a topic of the Node, if it runs the replicated store.
+    async fn store(&self, topic: u32) -> Result<Arc<Mutex<Store>>, String> {
+        self.view(topic)
+            .await?
+            .store
+            .ok_or(String::from("The replicated store is not enabled"))
+    }
+
+    /// Subscribe the application of the Node to the given topics only.
+    ///
+    /// # Arguments
+    ///
+    /// * `topics` - The topics delivered to the application.
+    ///
+    async fn subscribe(&self, topics: &[u32]) -> Result<(), String> {
+        let locked_views = self.views.lock().await;
+        if let Some(topic) = topics.iter().find(|t| !locked_views.contains_key(t)) {
+            return Err(format!("Node {} does not run topic {}", self.id, topic));
+        }
+        for view in locked_views.values() {
+            view.subscribed
+                .store(topics.contains(&view.topic), Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    async fn state(&self, topic: u32) -> Result<NodeState, String> {
+        let view = self.view(topic).await?;
+        let delivered = view.delivered().await;
+        Ok(NodeState {
+            id: self.id,
+            identity: format!("{:?}", self.identity),
+            phase: *self.phase.lock().await,
+            topic,
+            epoch: view.epoch,
+            subscribed: view.subscribed.load(Ordering::SeqCst),
+            delivered,
+            gossip_peers: view.gossip_peers.lock().await.len(),
+            echo_subscribers: view.echo_subscribers.lock().await.len(),
+            ready_subscribers: view.ready_subscribers.lock().await.len(),
+            evidence: view.evidence.lock().await.len(),
+            excluded: view.exclusions.lock().await.excluded.len(),
+            cap_counters: view.cap_counters.lock().await.clone(),
+            push_counters: view.push_counters.lock().await.clone(),
+            unknown_senders: view.unknown_senders.lock().await.values().sum(),
+            unauthorized_control: *view.unauthorized_control.lock().await,
+            messages_received: *view.received.lock().await,
+            fifo: match &view.fifo {
+                Some(buffer) => Some(buffer.lock().await.stats()),
+                None => None,
+            },
+            causal: match &view.causal {
+                Some(buffer) => Some(buffer.lock().await.stats()),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Serve the control socket. Every connection can send several requests, each answered on its own line.
+///
+/// # Arguments
+///
+/// * `path` - The path of the Unix domain socket.
+/// * `nodes` - The handles of the Nodes managed by this process.
+/// * `bootstrap` - How the Nodes are reached.
+/// * `operator` - The operator, which signs the control Messages sent to the Nodes.
+/// * `shutdown` - The channel used to stop the whole process.
+///
+pub async fn serve(
+    path: String,
+    nodes: Arc<Vec<NodeHandle>>,
+    bootstrap: Bootstrap,
+    operator: Operator,
+    shutdown: mpsc::Sender<()>,
+) {
+    let _ = fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("ERROR : control socket {} bind : {}", path, e);
+            return;
+        }
+    };
+    my_print!(format!("Control socket listening on {}", path));
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_client(
+                    stream,
+                    nodes.clone(),
+                    bootstrap.clone(),
+                    operator.clone(),
+                    shutdown.clone(),
+                ));
+            }
+            Err(e) => {
+                println!("ERROR : control socket accept : {}", e);
+            }
+        }
+    }
+}
+
+async fn serve_client(
+    stream: UnixStream,
+    nodes: Arc<Vec<NodeHandle>>,
+    bootstrap: Bootstrap,
+    operator: Operator,
+    shutdown: mpsc::Sender<()>,
+) {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => execute(request, &nodes, &bootstrap, &operator, &shutdown).await,
+            Err(e) => Response::Error {
+                message: format!("Invalid request : {}", e),
+            },
+        };
+        let mut output = serde_json::to_string(&response).unwrap();
+        output.push('\n');
+        if write_half.write_all(output.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Check whether the system is ready : every Node is ready, none is still in setup, subscribing or
+/// stopped.
+///
+/// # Arguments
+///
+/// * `nodes` - The handles of the Nodes managed by this process.
+///
+pub async fn system_ready(nodes: &[NodeHandle]) -> bool {
+    for handle in nodes.iter() {
+        match *handle.phase.lock().await {
+            Phase::Setup | Phase::Subscribing | Phase::Stopped => {
+                return false;
+            }
+            Phase::Ready => {}
+        }
+    }
+    true
+}
+
+/// Wait until the system is ready, and report it.
+///
+/// # Arguments
+///
+/// * `nodes` - The handles of the Nodes managed by this process.
+///
+pub async fn report_ready(nodes: Arc<Vec<NodeHandle>>) {
+    while !system_ready(&nodes).await {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    my_print!(format!("System ready : {} nodes", nodes.len()));
+}
+
+/// Wait until every Node stopped, or until the timeout expires.
+///
+/// # Arguments
+///
+/// * `nodes` - The handles of the Nodes managed by this process.
+/// * `timeout` - The maximum time to wait.
+///
+pub async fn wait_stopped(nodes: &[NodeHandle], timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut stopped = true;
+        for handle in nodes.iter() {
+            stopped &= *handle.phase.lock().await == Phase::Stopped;
+        }
+        if stopped {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Print a summary of the state of every Node, used when the Broadcast stops.
+///
+/// # Arguments
+///
+/// * `nodes` - The handles of the Nodes managed by this process.
+///
+pub async fn print_summary(nodes: &[NodeHandle]) {
+    let mut delivered = 0;
+    let mut messages = 0;
+    for handle in nodes.iter() {
+        // The counters other than the deliveries are shared by the topics of the Node.
+        let topic = handle.topics().await.first().cloned().unwrap_or(0);
+        match handle.state(topic).await {
+            Ok(state) => {
+                let node_delivered = handle.delivered().await;
+                delivered += node_delivered;
+                messages += state.messages_received;
+                my_print!(format!(
+                    "{} : {:?}, {} delivered, {} messages, {} evidence, {} excluded, {} unknown, {} unauthorized",
+                    state.id,
+                    state.phase,
+                    node_delivered,
+                    state.messages_received,
+                    state.evidence,
+                    state.excluded,
+                    state.unknown_senders,
+                    state.unauthorized_control
+                ));
+            }
+            Err(_) => {
+                my_print!(format!("{} : {:?}", handle.id, *handle.phase.lock().await));
+            }
+        }
+    }
+    my_print!(format!(
+        "Summary : {} nodes, {} deliveries, {} messages",
+        nodes.len(),
+        delivered,
+        messages
+    ));
+}
+
+/// Check that a Broadcast can be started from the given Node.
+///
+/// # Arguments
+///
+/// * `nodes` - The handles of the Nodes managed by this process.
+/// * `source` - The handle of the Node which would broadcast.
+///
+pub async fn can_broadcast(nodes: &[NodeHandle], source: &NodeHandle) -> Result<(), String> {
+    if !system_ready(nodes).await {
+        return Err(String::from("The system is not ready yet"));
+    }
+    if *source.phase.lock().await != Phase::Ready {
+        return Err(format!("Node {} is not ready", source.id));
+    }
+    match source.membership.lock().await.as_ref() {
+        Some(epoch) if epoch.contains(&source.identity) => Ok(()),
+        Some(epoch) => Err(format!(
+            "Node {} is not a member of epoch {}",
+            source.id, epoch.epoch
+        )),
+        None => Err(format!("Node {} did not join yet", source.id)),
+    }
+}
+
+fn find_node(nodes: &[NodeHandle], node: usize) -> Result<&NodeHandle, Response> {
+    nodes.iter().find(|h| h.id == node).ok_or(Response::Error {
+        message: format!("Unknown node {}", node),
+    })
+}
+
+fn find_source<'a>(
+    nodes: &'a [NodeHandle],
+    node: Option<usize>,
+    identity: Option<String>,
+) -> Result<&'a NodeHandle, Response> {
+    match (node, identity) {
+        (Some(node), None) => find_node(nodes, node),
+        (None, Some(identity)) => nodes
+            .iter()
+            .find(|h| format!("{:?}", h.identity) == identity)
+            .ok_or(Response::Error {
+                message: format!("Unknown identity {}", identity),
+            }),
+        _ => Err(Response::Error {
+            message: String::from("Exactly one of node and identity is required"),
+        }),
+    }
+}
+
+/// The latest epoch joined by the Nodes managed by this process.
+///
+/// # Arguments
+///
+/// * `nodes` - The handles of the Nodes managed by this process.
+///
+async fn latest_epoch(nodes: &[NodeHandle]) -> Option<Epoch> {
+    let mut latest: Option<Epoch> = None;
+    for handle in nodes.iter() {
+        let membership = handle.membership.lock().await.clone();
+        if let Some(epoch) = membership {
+            if latest.as_ref().map_or(true, |l| l.epoch < epoch.epoch) {
+                latest = Some(epoch);
+            }
+        }
+    }
+    latest
+}
+
+fn find_member(nodes: &[NodeHandle], epoch: &Epoch, member: &str) -> Result<Identity, Response> {
+    let identity = match member.parse::<usize>() {
+        Ok(node) => find_node(nodes, node)?.identity,
+        Err(_) => match epoch
+            .members
+            .iter()
+            .find(|m| format!("{:?}", m.identity()) == member)
+        {
+            Some(keycard) => keycard.identity(),
+            None => {
+                return Err(Response::Error {
+                    message: format!("Unknown member {}", member),
+                });
+            }
+        },
+    };
+    if !epoch.contains(&identity) {
+        return Err(Response::Error {
+            message: format!("{} is not a member of epoch {}", member, epoch.epoch),
+        });
+    }
+    Ok(identity)
+}
+
+async fn execute(
+    request: Request,
+    nodes: &[NodeHandle],
+    bootstrap: &Bootstrap,
+    operator: &Operator,
+    shutdown: &mpsc::Sender<()>,
+) -> Response {
+    match request {
+        Request::Broadcast {
+            node,
+            identity,
+            payload,
+            topic,
+        } => match find_source(nodes, node, identity) {
+            Ok(handle) => {
+                if let Err(message) = can_broadcast(nodes, handle).await {
+                    return Response::Error { message };
+                }
+                if let Err(message) = handle.view(topic).await {
+                    return Response::Error { message };
+                }
+                let instance = handle.next_instance(topic).await;
+                let sent = crate::trigger_send(
+                    bootstrap.clone(),
+                    operator.clone(),
+                    instance,
+                    topic,
+                    payload,
+                )
+                .await;
+                if let Err(message) = sent {
+                    return Response::Error { message };
+                }
+                Response::Instance {
+                    source: format!("{:?}", instance.source),
+                    sequence: instance.sequence,
+                }
+            }
+            Err(response) => response,
+        },
+        Request::Transfer {
+            node,
+            identity,
+            to,
+            amount,
+        } => match find_source(nodes, node, identity) {
+            Ok(handle) => {
+                if let Err(message) = can_broadcast(nodes, handle).await {
+                    return Response::Error { message };
+                }
+                let ledger = match handle.ledger().await {
+                    Ok(ledger) => ledger,
+                    Err(message) => {
+                        return Response::Error { message };
+                    }
+                };
+                let to = match to.parse::<usize>() {
+                    Ok(node) => match find_node(nodes, node) {
+                        Ok(handle) => format!("{:?}", handle.identity),
+                        Err(response) => {
+                            return response;
+                        }
+                    },
+                    Err(_) => to,
+                };
+                // The instance is only allocated once the transfer is valid, so that the account has
+                // no gap in its sequence.
+                let mut locked_ledger = ledger.lock().await;
+                let mut transfer = match locked_ledger.prepare(to, amount) {
+                    Ok(transfer) => transfer,
+                    Err(message) => {
+                        return Response::Error { message };
+                    }
+                };
+                let instance = handle.next_instance(TRANSFER_TOPIC).await;
+                transfer.sequence = instance.sequence;
+                locked_ledger.reserve(transfer.clone());
+                drop(locked_ledger);
+                let payload = serde_json::to_string(&transfer).unwrap();
+                let sent = crate::trigger_send(
+                    bootstrap.clone(),
+                    operator.clone(),
+                    instance,
+                    TRANSFER_TOPIC,
+                    payload,
+                )
+                .await;
+                if let Err(message) = sent {
+                    ledger.lock().await.release(instance.sequence);
+                    return Response::Error { message };
+                }
+                Response::Instance {
+                    source: format!("{:?}", instance.source),
+                    sequence: instance.sequence,
+                }
+            }
+            Err(response) => response,
+        },
+        Request::Balance { node, account } => match find_node(nodes, node) {
+            Ok(handle) => match handle.ledger().await {
+                Ok(ledger) => {
+                    let locked_ledger = ledger.lock().await;
+                    let accounts = match account {
+                        Some(account) => vec![locked_ledger.account(&account)],
+                        None => locked_ledger.accounts(),
+                    };
+                    Response::Balances {
+                        accounts,
+                        rejected: locked_ledger.rejected,
+                    }
+                }
+                Err(message) => Response::Error { message },
+            },
+            Err(response) => response,
+        },
+        Request::Put {
+            node,
+            identity,
+            key,
+            value,
+            topic,
+        } => match find_source(nodes, node, identity) {
+            Ok(handle) => {
+                if let Err(message) = can_broadcast(nodes, handle).await {
+                    return Response::Error { message };
+                }
+                if let Err(message) = handle.store(topic).await {
+                    return Response::Error { message };
+                }
+                let instance = handle.next_instance(topic).await;
+                let payload = serde_json::to_string(&Put { key, value }).unwrap();
+                let sent = crate::trigger_send(
+                    bootstrap.clone(),
+                    operator.clone(),
+                    instance,
+                    topic,
+                    payload,
+                )
+                .await;
+                if let Err(message) = sent {
+                    return Response::Error { message };
+                }
+                Response::Instance {
+                    source: format!("{:?}", instance.source),
+                    sequence: instance.sequence,
+                }
+            }
+            Err(response) => response,
+        },
+        Request::Get { node, key, topic } => match find_node(nodes, node) {
+            Ok(handle) => match handle.store(topic).await {
+                Ok(store) => {
+                    let value = store.lock().await.get(&key);
+                    Response::Value { key, value }
+                }
+                Err(message) => Response::Error { message },
+            },
+            Err(response) => response,
+        },
+        Request::Log { node, topic } => match find_node(nodes, node) {
+            Ok(handle) => match handle.store(topic).await {
+                Ok(store) => Response::Log {
+                    log: store.lock().await.log(),
+                },
+                Err(message) => Response::Error { message },
+            },
+            Err(response) => response,
+        },
+        Request::Subscribe { node, topics } => match find_node(nodes, node) {
+            Ok(handle) => match handle.subscribe(&topics).await {
+                Ok(()) => Response::Ok,
+                Err(message) => Response::Error { message },
+            },
+            Err(response) => response,
+        },
+        Request::Membership { node } => match find_node(nodes, node) {
+            Ok(handle) => match handle.membership.lock().await.as_ref() {
+                Some(epoch) => Response::Membership {
+                    epoch: epoch.epoch,
+                    members: epoch.identities(),
+                },
+                None => Response::Error {
+                    message: format!("Node {} did not join yet", node),
+                },
+            },
+            Err(response) => response,
+        },
+        Request::Epoch { join_shard, leave } => {
+            let current = match latest_epoch(nodes).await {
+                Some(current) => current,
+                None => {
+                    return Response::Error {
+                        message: String::from("No node of this process is a member yet"),
+                    };
+                }
+            };
+            let mut leaving = Vec::new();
+            for member in leave.iter() {
+                match find_member(nodes, &current, member) {
+                    Ok(identity) => leaving.push(identity),
+                    Err(response) => {
+                        return response;
+                    }
+                }
+            }
+            let joining = match join_shard {
+                Some(shard) => match bootstrap.shard(shard).await {
+                    Ok(keycards) => keycards,
+                    Err(message) => {
+                        return Response::Error { message };
+                    }
+                },
+                None => Vec::new(),
+            };
+            let next = current.next(joining, &leaving);
+            // The members of both epochs are told, so that the leaving ones know they left.
+            let mut recipients: Vec<Identity> =
+                current.members.iter().map(|m| m.identity()).collect();
+            for keycard in next.members.iter() {
+                if !current.contains(&keycard.identity()) {
+                    recipients.push(keycard.identity());
+                }
+            }
+            let announced =
+                crate::announce_epoch(bootstrap.clone(), operator.clone(), &next, recipients).await;
+            if let Err(message) = announced {
+                return Response::Error { message };
+            }
+            Response::Membership {
+                epoch: next.epoch,
+                members: next.identities(),
+            }
+        }
+        Request::List => {
+            let mut infos = Vec::new();
+            for handle in nodes.iter() {
+                infos.push(handle.info().await);
+            }
+            Response::Nodes {
+                ready: system_ready(nodes).await,
+                nodes: infos,
+            }
+        }
+        Request::Dump { node, topic } => match find_node(nodes, node) {
+            Ok(handle) => match handle.state(topic).await {
+                Ok(state) => Response::State { state },
+                Err(message) => Response::Error { message },
+            },
+            Err(response) => response,
+        },
+        Request::Stop { node } => match find_node(nodes, node) {
+            Ok(handle) => {
+                handle.stop.notify_one();
+                Response::Ok
+            }
+            Err(response) => response,
+        },
+        Request::Shutdown => {
+            for handle in nodes.iter() {
+                handle.stop.notify_one();
+            }
+            let _ = shutdown.send(()).await;
+            Response::Ok
+        }
+    }
+}
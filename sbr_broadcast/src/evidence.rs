@@ -0,0 +1,222 @@
+use crate::message::{Instance, Message, SignedMessage};
+use crate::message_headers::Echo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use talk::crypto::{Identity, KeyCard};
+use tokio::sync::Mutex;
+
+/// Cryptographic evidence that a peer signed two conflicting Echoes for the same instance, topic and epoch. Both
+/// signed Messages are kept, so that anyone holding the KeyCard of the culprit can check the misbehaviour. Readies
+/// are never evidence : a correct peer sends a Ready for every content reaching the Ready threshold, which are
+/// several when the source signed several payloads for the instance.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Evidence {
+    pub culprit: Identity,
+    pub first: SignedMessage,
+    pub second: SignedMessage,
+}
+
+impl Evidence {
+    /// Check that both Messages are Echoes of the same instance, topic and epoch, have different contents
+    /// and are correctly signed by the culprit.
+    ///
+    /// # Arguments
+    ///
+    /// * `keycard` - The KeyCard of the culprit.
+    ///
+    pub fn verify(&self, keycard: &KeyCard) -> bool {
+        let first = self.first.clone().get_message();
+        let second = self.second.clone().get_message();
+        keycard.identity() == self.culprit
+            && conflicting(&first, &second)
+            && verify_signature(keycard, &self.first)
+            && verify_signature(keycard, &self.second)
+    }
+}
+
+/// Whether two Messages are Echoes of the same instance, topic and epoch with different contents.
+fn conflicting(first: &Message, second: &Message) -> bool {
+    first.message_type == 1
+        && second.message_type == 1
+        && first.instance.is_some()
+        && first.instance == second.instance
+        && first.topic == second.topic
+        && first.epoch == second.epoch
+        && first.content != second.content
+}
+
+fn verify_signature(keycard: &KeyCard, signed_msg: &SignedMessage) -> bool {
+    let msg = signed_msg.clone().get_message();
+    let signature = signed_msg.clone().get_signature();
+    signature.verify(keycard, &Echo(msg)).is_ok()
+}
+
+/// Record the first signed Echo received from a peer for an instance, and detect if a later verified Echo from
+/// the same peer, instance, topic and epoch conflicts with it. When a conflict is found the Evidence is stored
+/// and logged, once per culprit since a single Evidence is enough to exclude it. Other Messages, and Messages
+/// without instance, are not recorded.
+///
+/// # Arguments
+///
+/// * `id` - The id of the running Node, used for debug purpose.
+/// * `from` - The Identity of the Node which signed the Message.
+/// * `signed_msg` - The verified signed Message received.
+/// * `first_signed` - The Atomic Reference Counter to the first signed Message received per instance, peer and layer.
+/// * `evidence` - The Atomic Reference Counter to the Evidence gathered so far.
+///
+pub async fn check_equivocation(
+    id: usize,
+    from: Identity,
+    signed_msg: SignedMessage,
+    first_signed: Arc<Mutex<HashMap<Instance, HashMap<(Identity, u32), SignedMessage>>>>,
+    evidence: Arc<Mutex<Vec<Evidence>>>,
+) -> bool {
+    let msg_type = signed_msg.clone().get_type();
+    if msg_type != 1 {
+        return false;
+    }
+    let instance = match signed_msg.clone().get_message().instance {
+        Some(instance) => instance,
+        None => {
+            return false;
+        }
+    };
+    let mut locked_first_signed = first_signed.lock().await;
+    let first = locked_first_signed
+        .entry(instance)
+        .or_default()
+        .entry((from, msg_type))
+        .or_insert(signed_msg.clone())
+        .clone();
+    drop(locked_first_signed);
+    if !conflicting(
+        &first.clone().get_message(),
+        &signed_msg.clone().get_message(),
+    ) {
+        return false;
+    }
+    let mut locked_evidence = evidence.lock().await;
+    if !locked_evidence.iter().any(|e| e.culprit == from) {
+        my_print!(format!(
+            "{} equivocation of type {} detected from {:?}",
+            id, msg_type, from
+        ));
+        locked_evidence.push(Evidence {
+            culprit: from,
+            first,
+            second: signed_msg,
+        });
+    }
+    drop(locked_evidence);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_headers::Ready;
+    use talk::crypto::KeyChain;
+
+    fn signed_echo(keychain: &KeyChain, sequence: u64, content: &str) -> SignedMessage {
+        signed_echo_in(keychain, sequence, content, 0)
+    }
+
+    fn signed_echo_in(
+        keychain: &KeyChain,
+        sequence: u64,
+        content: &str,
+        topic: u32,
+    ) -> SignedMessage {
+        let instance = Instance {
+            source: keychain.keycard().identity(),
+            sequence,
+        };
+        let msg = Message::with_instance(1, instance, String::from(content)).in_topic(topic);
+        let signature = keychain.sign(&Echo(msg.clone())).unwrap();
+        SignedMessage::new(msg, signature)
+    }
+
+    #[tokio::test]
+    async fn conflicting_echoes() {
+        let keychain = KeyChain::random();
+        let from = keychain.keycard().identity();
+        let first_signed = Arc::new(Mutex::new(HashMap::new()));
+        let evidence = Arc::new(Mutex::new(Vec::new()));
+        let first = signed_echo(&keychain, 0, "Test1");
+        let second = signed_echo(&keychain, 0, "Test2");
+        let (fs, ev) = (first_signed.clone(), evidence.clone());
+        assert!(!check_equivocation(0, from, first.clone(), fs, ev).await);
+        assert!(!check_equivocation(0, from, first, first_signed.clone(), evidence.clone()).await);
+        assert!(check_equivocation(0, from, second, first_signed.clone(), evidence.clone()).await);
+        // A second equivocation of the same culprit adds no Evidence.
+        let (fs, ev) = (first_signed.clone(), evidence.clone());
+        assert!(!check_equivocation(0, from, signed_echo(&keychain, 1, "Test1"), fs, ev).await);
+        assert!(
+            check_equivocation(
+                0,
+                from,
+                signed_echo(&keychain, 1, "Test2"),
+                first_signed,
+                evidence.clone()
+            )
+            .await
+        );
+        let evidence = evidence.lock().await.clone();
+        assert_eq!(evidence.len(), 1);
+        assert!(evidence[0].verify(&keychain.keycard()));
+        assert!(!evidence[0].verify(&KeyChain::random().keycard()));
+    }
+
+    #[tokio::test]
+    async fn echoes_of_different_topics_or_without_instance_do_not_conflict() {
+        let keychain = KeyChain::random();
+        let from = keychain.keycard().identity();
+        let first_signed = Arc::new(Mutex::new(HashMap::new()));
+        let evidence = Arc::new(Mutex::new(Vec::new()));
+        let first = signed_echo_in(&keychain, 0, "Test1", 0);
+        let second = signed_echo_in(&keychain, 0, "Test2", 1);
+        let (fs, ev) = (first_signed.clone(), evidence.clone());
+        assert!(!check_equivocation(0, from, first.clone(), fs, ev).await);
+        let (fs, ev) = (first_signed.clone(), evidence.clone());
+        assert!(!check_equivocation(0, from, second.clone(), fs, ev).await);
+        assert!(evidence.lock().await.is_empty());
+        let across_topics = Evidence {
+            culprit: from,
+            first,
+            second,
+        };
+        assert!(!across_topics.verify(&keychain.keycard()));
+        let sign = |content: &str| {
+            let msg = Message::new(1, String::from(content));
+            let signature = keychain.sign(&Echo(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        let without_instance = Evidence {
+            culprit: from,
+            first: sign("Test1"),
+            second: sign("Test2"),
+        };
+        assert!(!without_instance.verify(&keychain.keycard()));
+    }
+
+    #[tokio::test]
+    async fn readies_of_several_contents_are_no_evidence() {
+        let keychain = KeyChain::random();
+        let from = keychain.keycard().identity();
+        let first_signed = Arc::new(Mutex::new(HashMap::new()));
+        let evidence = Arc::new(Mutex::new(Vec::new()));
+        let instance = Instance {
+            source: KeyChain::random().keycard().identity(),
+            sequence: 0,
+        };
+        for content in ["Test1", "Test2"].iter() {
+            let msg = Message::with_instance(2, instance, String::from(*content));
+            let signature = keychain.sign(&Ready(msg.clone())).unwrap();
+            let (fs, ev) = (first_signed.clone(), evidence.clone());
+            let ready = SignedMessage::new(msg, signature);
+            assert!(!check_equivocation(0, from, ready, fs, ev).await);
+        }
+        assert!(evidence.lock().await.is_empty());
+    }
+}
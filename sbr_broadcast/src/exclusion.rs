@@ -0,0 +1,362 @@
+use crate::evidence::Evidence;
+use crate::message::Message;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use talk::crypto::{Identity, KeyCard};
+use tokio::sync::Mutex;
+
+/// Kinds of misbehaviour which are reported against a peer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Misbehaviour {
+    InvalidSignature,
+    Equivocation,
+    SubscriptionFlooding,
+}
+
+/// The list of excluded peers of a Node. Peers excluded because of Evidence keep it attached, so that the
+/// list can be shared with other Nodes which can check it instead of trusting it blindly.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ExclusionList {
+    pub excluded: Vec<Identity>,
+    pub evidence: Vec<Evidence>,
+}
+
+impl ExclusionList {
+    /// Load the exclusion list persisted by a Node. An empty list is returned if the file does not
+    /// exist or cannot be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file in which the list is persisted.
+    ///
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(list) => list,
+                Err(e) => {
+                    println!("ERROR : exclusion list {} parse : {}", path, e);
+                    ExclusionList::default()
+                }
+            },
+            Err(_) => ExclusionList::default(),
+        }
+    }
+
+    /// Persist the exclusion list, without blocking the runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file in which the list is persisted.
+    ///
+    pub async fn store(&self, path: &str) -> Result<(), String> {
+        if let Some(dir) = Path::new(path).parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| format!("exclusion dir {:?} create : {}", dir, e))?;
+        }
+        let content = serde_json::to_string(self).unwrap();
+        tokio::fs::write(path, content)
+            .await
+            .map_err(|e| format!("exclusion list {} write : {}", path, e))
+    }
+
+    /// Drop the exclusions backed by Evidence which does not verify, such as Evidence gathered by an
+    /// older version of the Node. The exclusions without Evidence, reached through the fault
+    /// threshold, are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `keycards` - The KeyCards of the system, used to verify the Evidence.
+    ///
+    pub fn retain_verified(&mut self, keycards: &HashMap<Identity, KeyCard>) {
+        let unbacked: Vec<Identity> = self
+            .evidence
+            .iter()
+            .filter(|evidence| !is_verified(evidence, keycards))
+            .map(|evidence| evidence.culprit)
+            .collect();
+        self.evidence
+            .retain(|evidence| is_verified(evidence, keycards));
+        self.excluded.retain(|culprit| !unbacked.contains(culprit));
+    }
+
+    /// Merge an exclusion list shared by another Node. Only the peers for which valid Evidence is
+    /// provided are excluded, the remaining entries of the shared list are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `shared` - The exclusion list shared by another Node.
+    /// * `keycards` - The KeyCards of the system, used to verify the Evidence.
+    ///
+    pub fn merge_shared(&mut self, shared: ExclusionList, keycards: &HashMap<Identity, KeyCard>) {
+        for evidence in shared.evidence.into_iter() {
+            if self.excluded.contains(&evidence.culprit) {
+                continue;
+            }
+            if is_verified(&evidence, keycards) {
+                self.excluded.push(evidence.culprit);
+                self.evidence.push(evidence);
+            }
+        }
+    }
+}
+
+/// Whether Evidence is signed by its culprit, a member of the system, and proves a misbehaviour.
+///
+/// # Arguments
+///
+/// * `evidence` - The Evidence.
+/// * `keycards` - The KeyCards of the system.
+///
+pub fn is_verified(evidence: &Evidence, keycards: &HashMap<Identity, KeyCard>) -> bool {
+    match keycards.get(&evidence.culprit) {
+        Some(keycard) => evidence.verify(keycard),
+        None => false,
+    }
+}
+
+/// Load the exclusion list of a Node, along with the lists shared by other Nodes in the same directory.
+/// The Evidence of every list, the own one included, is verified before it excludes a peer.
+///
+/// # Arguments
+///
+/// * `dir` - The directory in which the exclusion lists are persisted.
+/// * `path` - The file in which the list of this Node is persisted.
+/// * `keycards` - The KeyCards of the system, used to verify shared Evidence.
+///
+pub fn load_exclusions(
+    dir: &str,
+    path: &str,
+    keycards: &HashMap<Identity, KeyCard>,
+) -> ExclusionList {
+    let mut list = ExclusionList::load(path);
+    list.retain_verified(keycards);
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let shared_path = entry.path();
+            if shared_path == Path::new(path) {
+                continue;
+            }
+            if let Some(shared_path) = shared_path.to_str() {
+                list.merge_shared(ExclusionList::load(shared_path), keycards);
+            }
+        }
+    }
+    list
+}
+
+/// Count a misbehaviour of a peer. Returns true if the peer has to be excluded, either because
+/// verified Evidence of the misbehaviour exists or because the fault threshold is reached. An
+/// equivocation without verified Evidence only counts as a fault.
+///
+/// # Arguments
+///
+/// * `id` - The id of the running Node, used for debug purpose.
+/// * `from` - The Identity of the misbehaving peer.
+/// * `misbehaviour` - The kind of misbehaviour.
+/// * `verified` - Whether verified Evidence of the misbehaviour exists.
+/// * `faults` - The Atomic Reference Counter to the number of faults reported per peer.
+/// * `f_thr` - The number of faults after which a peer is excluded.
+///
+pub async fn report(
+    id: usize,
+    from: Identity,
+    misbehaviour: Misbehaviour,
+    verified: bool,
+    faults: Arc<Mutex<HashMap<Identity, usize>>>,
+    f_thr: usize,
+) -> bool {
+    my_print!(format!(
+        "{} reported {:?} from {:?}",
+        id, misbehaviour, from
+    ));
+    if misbehaviour == Misbehaviour::Equivocation && verified {
+        return true;
+    }
+    let mut locked_faults = faults.lock().await;
+    let count = locked_faults.entry(from).or_insert(0);
+    *count += 1;
+    *count >= f_thr
+}
+
+/// Randomly choose a peer of the system which is not excluded.
+///
+/// # Arguments
+///
+/// * `system` - The system in which the peer is randomly chosen.
+/// * `excluded` - The excluded peers.
+///
+pub fn resample(system: &Vec<KeyCard>, excluded: &Vec<Identity>) -> Option<Identity> {
+    let candidates: Vec<Identity> = system
+        .iter()
+        .map(|keycard| keycard.identity())
+        .filter(|identity| !excluded.contains(identity))
+        .collect();
+    let mut rng = rand::thread_rng();
+    candidates.choose(&mut rng).cloned()
+}
+
+/// Remove an excluded peer from the given peers. Returns true if the peer was present.
+///
+/// # Arguments
+///
+/// * `culprit` - The Identity of the excluded peer.
+/// * `peers` - The Atomic Reference Counter to the peers to update.
+///
+pub async fn remove_peer(culprit: Identity, peers: &Arc<Mutex<Vec<Identity>>>) -> bool {
+    let mut locked_peers = peers.lock().await;
+    let before = locked_peers.len();
+    locked_peers.retain(|peer| *peer != culprit);
+    before != locked_peers.len()
+}
+
+/// Replace an excluded peer in a Sieve sample, dropping its reply, or only remove it if no peer is left
+/// to replace it. Returns true if the excluded peer was part of the sample.
+///
+/// # Arguments
+///
+/// * `culprit` - The Identity of the excluded peer.
+/// * `replacement` - The Identity of the peer replacing it, if any.
+/// * `replies` - The Atomic Reference Counter to the replies of the sample.
+///
+pub async fn replace_in_sieve_sample(
+    culprit: Identity,
+    replacement: Option<Identity>,
+    replies: &Arc<Mutex<HashMap<Identity, Option<Message>>>>,
+) -> bool {
+    let mut locked_replies = replies.lock().await;
+    if locked_replies.remove(&culprit).is_none() {
+        return false;
+    }
+    if let Some(replacement) = replacement {
+        locked_replies.entry(replacement).or_insert(None);
+    }
+    true
+}
+
+/// Replace an excluded peer in a Contagion sample, dropping its replies, or only remove it if no peer
+/// is left to replace it. Returns true if the excluded peer was part of the sample.
+///
+/// # Arguments
+///
+/// * `culprit` - The Identity of the excluded peer.
+/// * `replacement` - The Identity of the peer replacing it, if any.
+/// * `replies` - The Atomic Reference Counter to the replies of the sample.
+///
+pub async fn replace_in_contagion_sample(
+    culprit: Identity,
+    replacement: Option<Identity>,
+    replies: &Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
+) -> bool {
+    let mut locked_replies = replies.lock().await;
+    if locked_replies.remove(&culprit).is_none() {
+        return false;
+    }
+    if let Some(replacement) = replacement {
+        locked_replies.entry(replacement).or_insert(Vec::new());
+    }
+    true
+}
+
+/// Give the peer replacing an excluded peer the number of times the excluded peer was drawn, so that
+/// the sample keeps its weight.
+///
+/// # Arguments
+///
+/// * `culprit` - The Identity of the excluded peer.
+/// * `replacement` - The Identity of the peer replacing it, if any.
+/// * `duplicates` - The Atomic Reference Counter to the number of times the peers of the sample were
+///   drawn.
+///
+pub async fn replace_weight(
+    culprit: Identity,
+    replacement: Option<Identity>,
+    duplicates: &Arc<Mutex<HashMap<Identity, usize>>>,
+) {
+    let mut locked_duplicates = duplicates.lock().await;
+    let weight = locked_duplicates.remove(&culprit);
+    if let (Some(replacement), Some(weight)) = (replacement, weight) {
+        locked_duplicates.insert(replacement, weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Instance, SignedMessage};
+    use crate::message_headers::Echo;
+    use talk::crypto::KeyChain;
+
+    #[tokio::test]
+    async fn fault_threshold() {
+        let from: Identity = Identity::from_bytes([1; 32]);
+        let faults = Arc::new(Mutex::new(HashMap::new()));
+        let invalid = Misbehaviour::InvalidSignature;
+        assert!(!report(0, from, invalid, false, faults.clone(), 2).await);
+        let flooding = Misbehaviour::SubscriptionFlooding;
+        assert!(report(0, from, flooding, false, faults.clone(), 2).await);
+        let other: Identity = Identity::from_bytes([2; 32]);
+        // Without verified Evidence, an equivocation is only a fault.
+        let equivocation = Misbehaviour::Equivocation;
+        assert!(!report(0, other, equivocation, false, faults.clone(), 2).await);
+        let backed: Identity = Identity::from_bytes([4; 32]);
+        assert!(report(0, backed, equivocation, true, faults, 2).await);
+    }
+
+    #[test]
+    fn shared_list_requires_evidence() {
+        let culprit = KeyChain::random();
+        let mut keycards: HashMap<Identity, KeyCard> = HashMap::new();
+        keycards.insert(culprit.keycard().identity(), culprit.keycard());
+        let instance = Instance {
+            source: culprit.keycard().identity(),
+            sequence: 0,
+        };
+        let sign = |content: &str| {
+            let msg = Message::with_instance(1, instance, String::from(content));
+            let signature = culprit.sign(&Echo(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        let unbacked: Identity = Identity::from_bytes([3; 32]);
+        let shared = ExclusionList {
+            excluded: vec![culprit.keycard().identity(), unbacked],
+            evidence: vec![Evidence {
+                culprit: culprit.keycard().identity(),
+                first: sign("Test1"),
+                second: sign("Test2"),
+            }],
+        };
+        let mut list = ExclusionList::default();
+        list.merge_shared(shared, &keycards);
+        assert_eq!(list.excluded, vec![culprit.keycard().identity()]);
+    }
+
+    #[test]
+    fn own_list_drops_unverified_evidence() {
+        let culprit = KeyChain::random();
+        let mut keycards: HashMap<Identity, KeyCard> = HashMap::new();
+        keycards.insert(culprit.keycard().identity(), culprit.keycard());
+        // Echoes without instance, as an older version took for equivocation.
+        let sign = |content: &str| {
+            let msg = Message::new(1, String::from(content));
+            let signature = culprit.sign(&Echo(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        let faulty: Identity = Identity::from_bytes([3; 32]);
+        let mut list = ExclusionList {
+            excluded: vec![culprit.keycard().identity(), faulty],
+            evidence: vec![Evidence {
+                culprit: culprit.keycard().identity(),
+                first: sign("Test1"),
+                second: sign("Test2"),
+            }],
+        };
+        list.retain_verified(&keycards);
+        assert_eq!(list.excluded, vec![faulty]);
+        assert!(list.evidence.is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+use crate::causal::CausalBuffer;
+use crate::config::FifoSettings;
+use crate::layer::BroadcastLayer;
+use crate::message::Message;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use talk::crypto::{Identity, KeyCard};
+use tokio::sync::Mutex;
+
+/// Counters of the FIFO buffers of a Node, reported on the control socket.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FifoStats {
+    /// Messages delivered out of order and waiting for the missing sequences.
+    pub pending: usize,
+    /// Sequences given up on because the buffer of their source was full.
+    pub skipped: usize,
+    /// Messages delivered after their sequence was skipped, which are dropped.
+    pub late: usize,
+    /// Messages not signed by the member they claim as source, which are dropped.
+    pub unverified: usize,
+    /// Messages of sources beyond the tracked ones, released without ordering.
+    pub untracked: usize,
+}
+
+/// Buffers of the Messages delivered out of order, per source. The Messages of a source are released in
+/// the order of their sequence numbers, starting from 0. A source holds at most `capacity` buffered
+/// Messages : once it is full, the missing sequences are skipped and reported. At most `sources`
+/// sources are tracked, the Messages of any other source are released as they come and reported.
+#[derive(Debug)]
+pub struct FifoBuffer {
+    capacity: usize,
+    sources: usize,
+    next: HashMap<Identity, u64>,
+    pending: HashMap<Identity, BTreeMap<u64, Message>>,
+    stats: FifoStats,
+}
+
+impl FifoBuffer {
+    pub fn new(settings: FifoSettings) -> Self {
+        FifoBuffer {
+            capacity: settings.capacity,
+            sources: settings.sources,
+            next: HashMap::new(),
+            pending: HashMap::new(),
+            stats: FifoStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FifoStats {
+        self.stats.clone()
+    }
+
+    /// The next sequence to release from a source, if it is tracked.
+    pub fn next(&self, source: &Identity) -> Option<u64> {
+        self.next.get(source).copied()
+    }
+
+    /// Buffer a delivered Message, and return the Messages of its source which can be released, in
+    /// order. Messages without instance are released directly.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the running Node, used for debug purpose.
+    /// * `message` - The delivered Message.
+    ///
+    pub fn push(&mut self, id: usize, message: Message) -> Vec<Message> {
+        let instance = match message.instance {
+            Some(instance) => instance,
+            None => {
+                return vec![message];
+            }
+        };
+        let source = instance.source;
+        if !self.next.contains_key(&source) {
+            if self.next.len() >= self.sources {
+                self.stats.untracked += 1;
+                my_print!(format!(
+                    "{} FIFO released sequence {} from untracked {:?}",
+                    id, instance.sequence, source
+                ));
+                return vec![message];
+            }
+            self.next.insert(source, 0);
+        }
+        let next = self.next[&source];
+        if instance.sequence < next {
+            self.stats.late += 1;
+            my_print!(format!(
+                "{} FIFO dropped late sequence {} from {:?}, expecting {}",
+                id, instance.sequence, source, next
+            ));
+            return Vec::new();
+        }
+        let pending = self.pending.entry(source).or_insert(BTreeMap::new());
+        if pending.insert(instance.sequence, message).is_none() {
+            self.stats.pending += 1;
+        }
+
+        let mut next = next;
+        if !pending.contains_key(&next) {
+            if pending.len() <= self.capacity {
+                my_print!(format!(
+                    "{} FIFO gap from {:?} : waiting for sequence {}, {} buffered",
+                    id,
+                    source,
+                    next,
+                    pending.len()
+                ));
+                return Vec::new();
+            }
+            // The buffer is full : give up on the missing sequences up to the first buffered one.
+            let first = *pending.keys().next().unwrap();
+            my_print!(format!(
+                "{} FIFO skipped sequences {} to {} from {:?}",
+                id,
+                next,
+                first - 1,
+                source
+            ));
+            self.stats.skipped += (first - next) as usize;
+            next = first;
+        }
+        let mut released = Vec::new();
+        while let Some(message) = pending.remove(&next) {
+            released.push(message);
+            next += 1;
+        }
+        self.stats.pending -= released.len();
+        self.next.insert(source, next);
+        released
+    }
+}
+
+/// Per-source FIFO ordering above the highest layer : the Messages it delivers are released to the
+/// application in the order their source broadcast them. Only the Messages signed by their source, the
+/// Node or one of the members, are ordered, so that no other Identity takes a buffer. The sequences it
+/// skips are skipped by the causal ordering above it as well, which would otherwise wait for them.
+pub struct FifoLayer {
+    pub id: usize,
+    pub own: KeyCard,
+    pub keycards: Arc<Mutex<HashMap<Identity, KeyCard>>>,
+    pub buffer: Arc<Mutex<FifoBuffer>>,
+    pub causal: Option<Arc<Mutex<CausalBuffer>>>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+impl FifoLayer {
+    /// Whether a Message is signed by the source of its instance. Messages without instance have no
+    /// source to order them by.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The delivered Message.
+    ///
+    async fn is_verified(&self, message: &Message) -> bool {
+        let source = match message.instance {
+            Some(instance) => instance.source,
+            None => {
+                return true;
+            }
+        };
+        let keycard = if source == self.own.identity() {
+            Some(self.own.clone())
+        } else {
+            self.keycards.lock().await.get(&source).cloned()
+        };
+        match keycard {
+            Some(keycard) => message.verify_source(&keycard),
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl BroadcastLayer for FifoLayer {
+    async fn deliver(&self, message: Message) {
+        if !self.is_verified(&message).await {
+            self.buffer.lock().await.stats.unverified += 1;
+            println!(
+                "ERROR : <{}> FIFO dropped a Message not signed by its source {:?}",
+                self.id,
+                message.instance.map(|instance| instance.source)
+            );
+            return;
+        }
+        // The buffer stays locked until the released Messages are delivered, so that concurrent
+        // deliveries cannot overtake them.
+        let mut locked_buffer = self.buffer.lock().await;
+        let source = message.instance.map(|instance| instance.source);
+        let expected = source.and_then(|source| locked_buffer.next(&source));
+        let released = locked_buffer.push(self.id, message);
+        if let (Some(causal), Some(source)) = (&self.causal, source) {
+            // The Messages of an untracked source come in any order, they skip nothing.
+            let tracked = locked_buffer.next(&source).is_some();
+            match released.first().and_then(|message| message.instance) {
+                Some(first) if tracked && first.sequence > expected.unwrap_or(0) => {
+                    causal.lock().await.skip(self.id, source, first.sequence);
+                }
+                _ => {}
+            }
+        }
+        for message in released.into_iter() {
+            self.upper.deliver(message).await;
+        }
+        drop(locked_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Instance;
+    use talk::crypto::KeyChain;
+
+    fn settings(capacity: usize, sources: usize) -> FifoSettings {
+        FifoSettings { capacity, sources }
+    }
+
+    fn message(source: Identity, sequence: u64) -> Message {
+        Message::with_instance(2, Instance { source, sequence }, sequence.to_string())
+    }
+
+    fn sequences(messages: Vec<Message>) -> Vec<u64> {
+        messages
+            .into_iter()
+            .map(|m| m.instance.unwrap().sequence)
+            .collect()
+    }
+
+    #[test]
+    fn releases_in_order() {
+        let source = KeyChain::random().keycard().identity();
+        let mut buffer = FifoBuffer::new(settings(4, 16));
+        assert_eq!(sequences(buffer.push(0, message(source, 0))), vec![0]);
+        assert!(buffer.push(0, message(source, 2)).is_empty());
+        assert!(buffer.push(0, message(source, 3)).is_empty());
+        assert_eq!(buffer.stats().pending, 2);
+        assert_eq!(sequences(buffer.push(0, message(source, 1))), vec![1, 2, 3]);
+        assert_eq!(buffer.stats().pending, 0);
+    }
+
+    #[test]
+    fn sources_are_independent() {
+        let a = KeyChain::random().keycard().identity();
+        let b = KeyChain::random().keycard().identity();
+        let mut buffer = FifoBuffer::new(settings(4, 16));
+        assert!(buffer.push(0, message(a, 1)).is_empty());
+        assert_eq!(sequences(buffer.push(0, message(b, 0))), vec![0]);
+        assert_eq!(sequences(buffer.push(0, message(a, 0))), vec![0, 1]);
+    }
+
+    #[test]
+    fn full_buffer_skips_the_gap() {
+        let source = KeyChain::random().keycard().identity();
+        let mut buffer = FifoBuffer::new(settings(2, 16));
+        assert!(buffer.push(0, message(source, 2)).is_empty());
+        assert!(buffer.push(0, message(source, 3)).is_empty());
+        assert_eq!(sequences(buffer.push(0, message(source, 5))), vec![2, 3]);
+        assert_eq!(buffer.stats().skipped, 2);
+        assert_eq!(buffer.stats().pending, 1);
+        // Skipped sequences delivered later are dropped.
+        assert!(buffer.push(0, message(source, 0)).is_empty());
+        assert_eq!(buffer.stats().late, 1);
+        assert_eq!(sequences(buffer.push(0, message(source, 4))), vec![4, 5]);
+    }
+
+    #[test]
+    fn sources_beyond_the_cap_are_not_tracked() {
+        let a = KeyChain::random().keycard().identity();
+        let b = KeyChain::random().keycard().identity();
+        let mut buffer = FifoBuffer::new(settings(4, 1));
+        assert!(buffer.push(0, message(a, 1)).is_empty());
+        // Another source takes no buffer, its Messages are released as they come.
+        assert_eq!(sequences(buffer.push(0, message(b, 3))), vec![3]);
+        assert_eq!(sequences(buffer.push(0, message(b, 1))), vec![1]);
+        assert_eq!(buffer.stats().untracked, 2);
+        assert_eq!(sequences(buffer.push(0, message(a, 0))), vec![0, 1]);
+    }
+
+    /// Layer recording the Messages delivered to it.
+    struct Recorder {
+        delivered: Arc<Mutex<Vec<Message>>>,
+    }
+
+    #[async_trait]
+    impl BroadcastLayer for Recorder {
+        async fn deliver(&self, message: Message) {
+            self.delivered.lock().await.push(message);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_messages_signed_by_their_source_are_ordered() {
+        let own = KeyChain::random();
+        let member = KeyChain::random();
+        let forger = KeyChain::random();
+        let mut keycards = HashMap::new();
+        keycards.insert(member.keycard().identity(), member.keycard());
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let buffer = Arc::new(Mutex::new(FifoBuffer::new(settings(4, 16))));
+        let layer = FifoLayer {
+            id: 0,
+            own: own.keycard(),
+            keycards: Arc::new(Mutex::new(keycards)),
+            buffer: buffer.clone(),
+            causal: None,
+            upper: Arc::new(Recorder {
+                delivered: delivered.clone(),
+            }),
+        };
+        let member_id = member.keycard().identity();
+        layer
+            .deliver(message(member_id, 0).signed_by_source(&member))
+            .await;
+        layer
+            .deliver(message(own.keycard().identity(), 0).signed_by_source(&own))
+            .await;
+        // Neither a Message signed by another member, nor a Message of an unknown source, takes a
+        // buffer.
+        layer
+            .deliver(message(member_id, 2).signed_by_source(&forger))
+            .await;
+        let forged = forger.keycard().identity();
+        layer
+            .deliver(message(forged, 1).signed_by_source(&forger))
+            .await;
+        assert_eq!(delivered.lock().await.len(), 2);
+        let stats = buffer.lock().await.stats();
+        assert_eq!(stats.unverified, 2);
+        assert_eq!(stats.pending, 0);
+    }
+}
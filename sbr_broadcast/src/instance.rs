@@ -0,0 +1,140 @@
+use crate::bracha::BrachaState;
+use crate::certificate::DeliveryCertificate;
+use crate::message::{Instance, Message, SignedMessage};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use talk::crypto::Identity;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// State of a single Broadcast instance. The samples of the Node are shared by all instances of an
+/// epoch, but the replies received from the sampled peers and the delivery status are kept per instance.
+/// An instance finishes in the epoch it started in.
+#[derive(Clone)]
+pub struct InstanceState {
+    pub epoch: u64,
+    pub opened: Instant,
+    pub delivered_gossip: Arc<Mutex<Option<Message>>>,
+    pub echo: Arc<Mutex<Option<Message>>>,
+    pub echo_replies: Arc<Mutex<HashMap<Identity, Option<Message>>>>,
+    pub delivered_echo: Arc<Mutex<Option<Message>>>,
+    pub ready_messages: Arc<Mutex<Vec<Message>>>,
+    pub ready_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
+    pub delivery_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
+    pub delivered_msg: Arc<Mutex<Option<Message>>>,
+    pub ready_signatures: Arc<Mutex<HashMap<Identity, Vec<SignedMessage>>>>,
+    pub certificate: Arc<Mutex<Option<DeliveryCertificate>>>,
+    pub bracha: Arc<Mutex<BrachaState>>,
+}
+
+impl InstanceState {
+    /// Create the state of a new instance, with empty replies for the peers of the given samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `epoch` - The epoch the instance starts in.
+    /// * `echo_sample` - The Echo peers of the Node.
+    /// * `ready_sample` - The Ready peers of the Node.
+    /// * `delivery_sample` - The Delivery peers of the Node.
+    ///
+    pub fn new(
+        epoch: u64,
+        echo_sample: Vec<Identity>,
+        ready_sample: Vec<Identity>,
+        delivery_sample: Vec<Identity>,
+    ) -> Self {
+        InstanceState {
+            epoch,
+            opened: Instant::now(),
+            delivered_gossip: Arc::new(Mutex::new(None)),
+            echo: Arc::new(Mutex::new(None)),
+            echo_replies: Arc::new(Mutex::new(
+                echo_sample.into_iter().map(|id| (id, None)).collect(),
+            )),
+            delivered_echo: Arc::new(Mutex::new(None)),
+            ready_messages: Arc::new(Mutex::new(Vec::new())),
+            ready_replies: Arc::new(Mutex::new(
+                ready_sample
+                    .into_iter()
+                    .map(|id| (id, Vec::new()))
+                    .collect(),
+            )),
+            delivery_replies: Arc::new(Mutex::new(
+                delivery_sample
+                    .into_iter()
+                    .map(|id| (id, Vec::new()))
+                    .collect(),
+            )),
+            delivered_msg: Arc::new(Mutex::new(None)),
+            ready_signatures: Arc::new(Mutex::new(HashMap::new())),
+            certificate: Arc::new(Mutex::new(None)),
+            bracha: Arc::new(Mutex::new(BrachaState::default())),
+        }
+    }
+}
+
+/// The instances a Node keeps for each source, so that a source cannot exhaust the memory of the Node
+/// by opening instances. Past the bound, the oldest instance of the source is pruned, and neither it
+/// nor an older one of the same source is ever opened again.
+#[derive(Debug, Default)]
+pub struct SourceInstances {
+    open: HashMap<Identity, BTreeSet<u64>>,
+    pruned_below: HashMap<Identity, u64>,
+}
+
+impl SourceInstances {
+    /// Whether the instance is older than the instances its source may still open.
+    pub fn is_pruned(&self, instance: &Instance) -> bool {
+        self.pruned_below
+            .get(&instance.source)
+            .map_or(false, |below| instance.sequence < *below)
+    }
+
+    /// Open an instance which is not pruned. Returns the instance of the same source pruned to keep
+    /// at most `bound` of them, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - The instance to open.
+    /// * `bound` - The maximum number of instances kept for the source.
+    ///
+    pub fn open(&mut self, instance: Instance, bound: usize) -> Option<Instance> {
+        let open = self.open.entry(instance.source).or_default();
+        open.insert(instance.sequence);
+        if open.len() <= bound.max(1) {
+            return None;
+        }
+        let oldest = *open.iter().next().unwrap();
+        open.remove(&oldest);
+        self.pruned_below.insert(instance.source, oldest + 1);
+        Some(Instance {
+            source: instance.source,
+            sequence: oldest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_instances_are_pruned() {
+        let source = Identity::from_bytes([1; 32]);
+        let instance = |sequence: u64| Instance { source, sequence };
+        let mut sources = SourceInstances::default();
+        assert_eq!(sources.open(instance(1), 2), None);
+        assert_eq!(sources.open(instance(3), 2), None);
+        assert_eq!(sources.open(instance(2), 2), Some(instance(1)));
+        assert_eq!(sources.open(instance(4), 2), Some(instance(2)));
+        // Nothing up to the last pruned instance is opened again.
+        assert!(sources.is_pruned(&instance(0)));
+        assert!(sources.is_pruned(&instance(2)));
+        assert!(!sources.is_pruned(&instance(3)));
+        let other = Instance {
+            source: Identity::from_bytes([2; 32]),
+            sequence: 0,
+        };
+        assert!(!sources.is_pruned(&other));
+    }
+}
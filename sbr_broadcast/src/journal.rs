@@ -0,0 +1,466 @@
+use crate::layer::BroadcastLayer;
+use crate::membership::Epoch;
+use crate::message::{Instance, Message};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use talk::crypto::Identity;
+use tokio::sync::{mpsc, oneshot};
+
+/// Samples of a Node. The peers of the Sieve and Contagion samples come with the number of times they
+/// were drawn.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Samples {
+    pub gossip: Vec<Identity>,
+    pub echo: Vec<(Identity, usize)>,
+    pub ready: Vec<(Identity, usize)>,
+    pub delivery: Vec<(Identity, usize)>,
+}
+
+/// Record of the write-ahead journal of a Node. Every record is written and synced before the action
+/// it describes is visible to other Nodes. The Messages of an instance are journaled whole, with the
+/// signature of their source, so that a restarted Node can send them again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum Record {
+    /// The Node started, every start after the first one is a restart.
+    Start,
+    /// The Node entered an epoch, with new samples and new subscribers.
+    Epoch { epoch: Epoch },
+    /// The samples of the Node, which replace the previous ones.
+    Samples { samples: Samples },
+    /// A peer subscribed to the Node, with the type of its subscription.
+    Subscriber { subscription: u32, peer: Identity },
+    /// The Node broadcast the Message in one of its instances.
+    Sent { message: Message },
+    /// The Node echoed the Message.
+    Echo { message: Message },
+    /// The Node was ready for the Message.
+    Ready { message: Message },
+    /// The Node delivered the Message to the application.
+    Delivered { message: Message },
+    /// The application wrote the output of the delivery of the instance.
+    Acknowledged { instance: Instance },
+}
+
+/// What the journal tells about a single instance.
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    pub sent: Option<Message>,
+    pub echo: Option<Message>,
+    pub readies: Vec<Message>,
+    pub delivered: Option<Message>,
+}
+
+impl Progress {
+    /// The epoch the instance started in, as journaled with its Messages.
+    pub fn epoch(&self) -> Option<u64> {
+        self.sent
+            .iter()
+            .chain(self.echo.iter())
+            .chain(self.readies.iter())
+            .chain(self.delivered.iter())
+            .map(|message| message.epoch)
+            .next()
+    }
+}
+
+/// State of a Node rebuilt from its journal.
+#[derive(Debug, Default)]
+pub struct Recovered {
+    /// The number of times the Node started before.
+    pub incarnation: u64,
+    /// The last epoch the Node entered, if it entered one after epoch 0.
+    pub epoch: Option<Epoch>,
+    pub samples: Option<Samples>,
+    pub subscribers: Vec<(u32, Identity)>,
+    pub instances: HashMap<Instance, Progress>,
+    /// The delivered instances, in the order they were delivered to the application.
+    pub deliveries: Vec<Instance>,
+    /// The delivered instances whose output the application wrote.
+    pub acknowledged: HashSet<Instance>,
+}
+
+impl Recovered {
+    /// Fold the records of a journal, in the order they were written.
+    ///
+    /// # Arguments
+    ///
+    /// * `records` - The records of the journal.
+    ///
+    pub fn from_records(records: Vec<Record>) -> Self {
+        let mut recovered = Recovered::default();
+        for record in records.into_iter() {
+            match record {
+                Record::Start => {
+                    recovered.incarnation += 1;
+                }
+                Record::Epoch { epoch } => {
+                    recovered.epoch = Some(epoch);
+                    recovered.samples = None;
+                    recovered.subscribers.clear();
+                }
+                Record::Samples { samples } => {
+                    recovered.samples = Some(samples);
+                }
+                Record::Subscriber { subscription, peer } => {
+                    if !recovered.subscribers.contains(&(subscription, peer)) {
+                        recovered.subscribers.push((subscription, peer));
+                    }
+                }
+                Record::Sent { message } => {
+                    if let Some(progress) = recovered.progress(&message) {
+                        progress.sent.get_or_insert(message);
+                    }
+                }
+                Record::Echo { message } => {
+                    if let Some(progress) = recovered.progress(&message) {
+                        progress.echo.get_or_insert(message);
+                    }
+                }
+                Record::Ready { message } => {
+                    if let Some(progress) = recovered.progress(&message) {
+                        if !progress
+                            .readies
+                            .iter()
+                            .any(|r| r.content == message.content)
+                        {
+                            progress.readies.push(message);
+                        }
+                    }
+                }
+                Record::Delivered { message } => {
+                    let instance = message.instance;
+                    if let Some(progress) = recovered.progress(&message) {
+                        if progress.delivered.is_none() {
+                            progress.delivered = Some(message);
+                            recovered.deliveries.extend(instance);
+                        }
+                    }
+                }
+                Record::Acknowledged { instance } => {
+                    recovered.acknowledged.insert(instance);
+                }
+            }
+        }
+        recovered
+    }
+
+    /// The progress of the instance of a journaled Message. Messages without instance are never
+    /// journaled.
+    fn progress(&mut self, message: &Message) -> Option<&mut Progress> {
+        let instance = message.instance?;
+        Some(self.instances.entry(instance).or_default())
+    }
+
+    /// The first sequence a source has not broadcast in, so that a restarted Node continues the
+    /// numbering of its instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The Identity of the Node.
+    ///
+    pub fn next_sequence(&self, source: Identity) -> u64 {
+        self.instances
+            .iter()
+            .filter(|(instance, progress)| instance.source == source && progress.sent.is_some())
+            .map(|(instance, _)| instance.sequence + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// The journal file of a topic of a Node.
+///
+/// # Arguments
+///
+/// * `dir` - The directory in which the journals are kept.
+/// * `id` - The ID of the Node.
+/// * `topic` - The topic.
+///
+pub fn journal_file(dir: &str, id: usize, topic: u32) -> String {
+    format!("{}/node_{}_topic_{}.log", dir, id, topic)
+}
+
+/// Write-ahead journal of a Node, one JSON record per line, from which a restarted Node recovers its
+/// epoch, its samples, its subscribers and what it sent, echoed, readied and delivered. The records are
+/// written and synced by a thread of their own, in the order they are appended, so that no task waits
+/// for the disk while holding a lock of the runtime.
+#[derive(Debug)]
+pub struct Journal {
+    path: String,
+    writer: mpsc::UnboundedSender<(Record, oneshot::Sender<Result<(), String>>)>,
+}
+
+impl Journal {
+    pub fn new(path: String) -> Self {
+        let (writer, records) = mpsc::unbounded_channel();
+        let file = path.clone();
+        thread::spawn(move || write_records(file, records));
+        Journal { path, writer }
+    }
+
+    /// Append a record to the journal. Returns once the record is synced, or could not be written.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The record to append.
+    ///
+    pub async fn append(&self, record: &Record) -> Result<(), String> {
+        let (done, written) = oneshot::channel();
+        self.writer
+            .send((record.clone(), done))
+            .map_err(|_| format!("journal {} closed", self.path))?;
+        written
+            .await
+            .map_err(|_| format!("journal {} closed", self.path))?
+    }
+
+    /// Read the journal and mark a new start of the Node. A record torn by a crash can only be the last
+    /// one : it is dropped, and the journal is rewritten without it so that the next records are
+    /// readable.
+    pub async fn recover(&self) -> Recovered {
+        let path = self.path.clone();
+        let records = tokio::task::spawn_blocking(move || read_records(&path))
+            .await
+            .unwrap();
+        let recovered = Recovered::from_records(records);
+        if let Err(e) = self.append(&Record::Start).await {
+            println!("ERROR : {}", e);
+        }
+        recovered
+    }
+}
+
+/// Write the records appended to a journal until it is dropped, and tell each append whether its
+/// record was written.
+///
+/// # Arguments
+///
+/// * `path` - The path of the journal.
+/// * `records` - The records appended, with whom to tell.
+///
+fn write_records(
+    path: String,
+    mut records: mpsc::UnboundedReceiver<(Record, oneshot::Sender<Result<(), String>>)>,
+) {
+    while let Some((record, done)) = records.blocking_recv() {
+        let _ = done.send(write_record(&path, &record));
+    }
+}
+
+/// Append a record to the journal file, and sync it.
+///
+/// # Arguments
+///
+/// * `path` - The path of the journal.
+/// * `record` - The record to write.
+///
+fn write_record(path: &str, record: &Record) -> Result<(), String> {
+    if let Some(dir) = Path::new(path).parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let line = serde_json::to_string(record).unwrap();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("journal {} open : {}", path, e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("journal {} write : {}", path, e))?;
+    file.sync_data()
+        .map_err(|e| format!("journal {} sync : {}", path, e))
+}
+
+/// Read the records of a journal file, dropping a torn last record.
+///
+/// # Arguments
+///
+/// * `path` - The path of the journal.
+///
+fn read_records(path: &str) -> Vec<Record> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let mut records = Vec::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        match serde_json::from_str::<Record>(line) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                println!("ERROR : journal {} torn record dropped : {}", path, e);
+                break;
+            }
+        }
+    }
+    if records.len() < content.lines().filter(|line| !line.is_empty()).count() {
+        let kept: Vec<String> = records
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap() + "\n")
+            .collect();
+        let tmp = format!("{}.tmp", path);
+        let rewritten = fs::write(&tmp, kept.concat()).and_then(|_| fs::rename(&tmp, path));
+        if let Err(e) = rewritten {
+            println!("ERROR : journal {} rewrite : {}", path, e);
+        }
+    }
+    records
+}
+
+/// Append a record to the journal of a Node, if it keeps one. Returns false if the record could not be
+/// written, in which case the action it describes is not taken.
+///
+/// # Arguments
+///
+/// * `journal` - The journal of the Node.
+/// * `record` - The record to append.
+///
+pub async fn record(journal: &Option<Arc<Journal>>, record: Record) -> bool {
+    match journal {
+        Some(journal) => match journal.append(&record).await {
+            Ok(()) => true,
+            Err(e) => {
+                println!("ERROR : {}", e);
+                false
+            }
+        },
+        None => true,
+    }
+}
+
+/// Bottom of the application : journals every delivery before passing it on, so that a restarted Node
+/// never delivers an instance twice. The application acknowledges a delivery once its output is
+/// written, and a restarted Node writes the output of the deliveries it journaled but never
+/// acknowledged. A delivery which cannot be journaled is not passed on.
+pub struct JournalLayer {
+    pub journal: Arc<Journal>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+#[async_trait]
+impl BroadcastLayer for JournalLayer {
+    async fn deliver(&self, message: Message) {
+        if message.instance.is_some() {
+            let record = Record::Delivered {
+                message: message.clone(),
+            };
+            if let Err(e) = self.journal.append(&record).await {
+                println!("ERROR : delivery not journaled : {}", e);
+                return;
+            }
+        }
+        self.upper.deliver(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use talk::crypto::KeyChain;
+
+    fn instance(sequence: u64) -> Instance {
+        Instance {
+            source: KeyChain::random().keycard().identity(),
+            sequence,
+        }
+    }
+
+    fn message(instance: Instance, content: &str) -> Message {
+        Message::with_instance(2, instance, String::from(content))
+    }
+
+    #[test]
+    fn records_fold_into_the_progress_of_instances() {
+        let (a, b) = (instance(0), instance(1));
+        let peer = KeyChain::random().keycard().identity();
+        let recovered = Recovered::from_records(vec![
+            Record::Start,
+            Record::Subscriber {
+                subscription: 4,
+                peer,
+            },
+            Record::Echo {
+                message: message(a, "x"),
+            },
+            Record::Sent {
+                message: message(b, "y"),
+            },
+            Record::Ready {
+                message: message(a, "x"),
+            },
+            Record::Ready {
+                message: message(a, "x"),
+            },
+            Record::Delivered {
+                message: message(b, "y"),
+            },
+            Record::Delivered {
+                message: message(a, "x"),
+            },
+            Record::Acknowledged { instance: b },
+            Record::Start,
+        ]);
+        assert_eq!(recovered.incarnation, 2);
+        assert_eq!(recovered.subscribers, vec![(4, peer)]);
+        assert_eq!(recovered.deliveries, vec![b, a]);
+        // The output of the delivery of a may not have been written.
+        assert!(recovered.acknowledged.contains(&b));
+        assert!(!recovered.acknowledged.contains(&a));
+        let progress = &recovered.instances[&a];
+        assert_eq!(progress.echo.as_ref().unwrap().content, "x");
+        assert_eq!(progress.readies.len(), 1);
+        assert_eq!(progress.delivered.as_ref().unwrap().content, "x");
+        // Only the instances the Node sent count for its sequence.
+        assert_eq!(recovered.next_sequence(a.source), 0);
+        assert_eq!(recovered.next_sequence(b.source), 2);
+    }
+
+    #[test]
+    fn new_epoch_replaces_samples_and_subscribers() {
+        let peer = KeyChain::random().keycard().identity();
+        let epoch = Epoch {
+            epoch: 1,
+            members: vec![KeyChain::random().keycard()],
+        };
+        let recovered = Recovered::from_records(vec![
+            Record::Samples {
+                samples: Samples::default(),
+            },
+            Record::Subscriber {
+                subscription: 3,
+                peer,
+            },
+            Record::Epoch {
+                epoch: epoch.clone(),
+            },
+        ]);
+        assert_eq!(recovered.epoch, Some(epoch));
+        assert!(recovered.samples.is_none());
+        assert!(recovered.subscribers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn torn_record_is_dropped() {
+        let path = std::env::temp_dir().join(format!("journal_{}.log", rand::random::<u64>()));
+        let journal = Journal::new(path.to_str().unwrap().to_string());
+        let delivered = Record::Delivered {
+            message: message(instance(0), "x"),
+        };
+        assert_eq!(journal.recover().await.incarnation, 0);
+        journal.append(&delivered).await.unwrap();
+        // A crash in the middle of a record.
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(f, "{{\"record\":\"echo\",\"inst").unwrap();
+        drop(f);
+
+        let recovered = journal.recover().await;
+        assert_eq!(recovered.incarnation, 1);
+        assert_eq!(recovered.deliveries.len(), 1);
+        journal.append(&delivered).await.unwrap();
+        let recovered = journal.recover().await;
+        assert_eq!(recovered.incarnation, 2);
+        assert_eq!(recovered.deliveries.len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+use talk::crypto::{KeyCard, KeyChain};
+
+/// Load a KeyChain stored in a file.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the KeyChain is stored.
+///
+pub fn load_keychain(path: &str) -> Result<KeyChain, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("read {} : {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("parse {} : {}", path, e))
+}
+
+/// Store a KeyChain in a file, creating its directory if needed.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the KeyChain is stored.
+/// * `keychain` - The KeyChain to store.
+///
+pub fn store_keychain(path: &str, keychain: &KeyChain) -> Result<(), String> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("create {:?} : {}", dir, e))?;
+    }
+    let content = serde_json::to_string(keychain).unwrap();
+    fs::write(path, content).map_err(|e| format!("write {} : {}", path, e))
+}
+
+/// Load a KeyCard stored in a file.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the KeyCard is stored.
+///
+pub fn load_keycard(path: &str) -> Result<KeyCard, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("read {} : {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("parse {} : {}", path, e))
+}
+
+/// Load the operator KeyChain used to sign the control Messages sent to the Nodes. If the file does not
+/// exist yet, a new KeyChain is created and stored in it, so that later runs keep the same operator.
+/// Its KeyCard is stored in its own file, the only one node processes read.
+///
+/// # Arguments
+///
+/// * `path` - The file in which the operator KeyChain is stored.
+/// * `card_path` - The file in which the operator KeyCard is stored.
+///
+pub fn load_or_create_operator(path: &str, card_path: &str) -> KeyChain {
+    let keychain = if Path::new(path).exists() {
+        load_keychain(path).expect("Error reading operator key file")
+    } else {
+        let keychain = KeyChain::random();
+        store_keychain(path, &keychain).expect("Error writing operator key file");
+        my_print!(format!("Created operator key in {}", path));
+        keychain
+    };
+    if let Some(dir) = Path::new(card_path).parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let card = serde_json::to_string(&keychain.keycard()).unwrap();
+    fs::write(card_path, card).expect("Error writing operator card file");
+    keychain
+}
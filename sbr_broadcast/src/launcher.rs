@@ -0,0 +1,142 @@
+use crate::config::Config;
+use crate::keys::{load_keychain, load_or_create_operator, store_keychain};
+use crate::membership::Allowlist;
+use crate::operator;
+use std::env;
+use std::path::Path;
+use std::process::Stdio;
+use talk::crypto::KeyChain;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Create and store the KeyChains of the nodes 0 to count - 1. Existing KeyChains are kept, so that the
+/// nodes keep their Identity across runs.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the Broadcast, giving the directory of the KeyChains.
+/// * `count` - The number of nodes.
+///
+pub fn keygen(config: &Config, count: usize) {
+    for id in 0..count {
+        let path = config.key_file(id);
+        if Path::new(&path).exists() {
+            continue;
+        }
+        let keychain = KeyChain::random();
+        match store_keychain(&path, &keychain) {
+            Ok(()) => {
+                my_print!(format!(
+                    "Created {} : {:?}",
+                    path,
+                    keychain.keycard().identity()
+                ));
+            }
+            Err(e) => {
+                println!("ERROR : keygen : {}", e);
+            }
+        }
+    }
+}
+
+/// Write the allowlist file with the Identities of the nodes 0 to count - 1, from their stored
+/// KeyChains.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the Broadcast.
+/// * `count` - The number of nodes.
+///
+pub fn write_allowlist(config: &Config, count: usize) -> Result<(), String> {
+    if config.allowlist_file.is_empty() {
+        return Err(String::from("allowlist_file is not configured"));
+    }
+    let mut identities = Vec::new();
+    for id in 0..count {
+        identities.push(load_keychain(&config.key_file(id))?.keycard().identity());
+    }
+    Allowlist::store(&config.allowlist_file, &identities)
+}
+
+/// Start one process per node locally, running the `node` command with the stored KeyChain of the node.
+/// The processes are stopped with SIGTERM on the exit command, SIGINT or SIGTERM.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the Broadcast.
+/// * `count` - The number of node processes to start.
+///
+pub async fn launch(config: &Config, count: usize) {
+    keygen(config, count);
+    // Created before the nodes start, so that they all trust the same operator. The launcher keeps the
+    // KeyChain and signs the control Messages of the nodes on the operator socket.
+    let operator = load_or_create_operator(&config.operator_key, &config.operator_card);
+    tokio::spawn(operator::serve(config.operator_socket.clone(), operator));
+
+    let exe = env::current_exe().expect("Error finding the executable");
+    let mut children: Vec<(usize, Child)> = Vec::new();
+    for id in 0..count {
+        let child = Command::new(&exe)
+            .arg("node")
+            .arg(id.to_string())
+            .stdin(Stdio::null())
+            .spawn();
+        match child {
+            Ok(child) => {
+                children.push((id, child));
+            }
+            Err(e) => {
+                println!("ERROR : launch node {} : {}", id, e);
+            }
+        }
+    }
+    my_print!(format!("Launched {} node processes", children.len()));
+
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
+    loop {
+        tokio::select! {
+            line = lines.next_line(), if stdin_open => match line {
+                Ok(Some(input)) => {
+                    if input == "exit" {
+                        break;
+                    }
+                }
+                _ => {
+                    stdin_open = false;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => {
+                break;
+            }
+            _ = terminate.recv() => {
+                break;
+            }
+        }
+    }
+
+    for (id, child) in children.iter() {
+        if let Some(pid) = child.id() {
+            let r = Command::new("kill")
+                .arg("-TERM")
+                .arg(pid.to_string())
+                .status()
+                .await;
+            if let Err(e) = r {
+                println!("ERROR : stop node {} : {}", id, e);
+            }
+        }
+    }
+    for (id, mut child) in children.into_iter() {
+        match child.wait().await {
+            Ok(status) => {
+                my_print!(format!("Node process {} exited : {}", id, status));
+            }
+            Err(e) => {
+                println!("ERROR : wait node {} : {}", id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::contagion::prb_deliver;
+use crate::instance::InstanceState;
+use crate::journal::{record, Journal, Record};
+use crate::message::{Instance, Message};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Broadcast primitive of the stack. Every layer takes the Messages delivered by the layer below it, or
+/// broadcast by the application for the lowest layer, and delivers its own Messages to the layer above
+/// it. The full SBR stack is Murmur, Sieve and Contagion composed over each other.
+#[async_trait]
+pub trait BroadcastLayer: Send + Sync {
+    /// Take a Message delivered by the layer below, or broadcast by the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The Message to broadcast through this layer.
+    ///
+    async fn deliver(&self, message: Message);
+}
+
+/// The layers of the SBR stack, from the lowest to the highest.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Layer {
+    /// Probabilistic Broadcast.
+    Murmur,
+    /// Probabilistic Consistent Broadcast.
+    Sieve,
+    /// Probabilistic Reliable Broadcast.
+    Contagion,
+}
+
+impl Layer {
+    /// Parse the name of a layer, as written in the configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the layer.
+    ///
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "murmur" => Some(Layer::Murmur),
+            "sieve" => Some(Layer::Sieve),
+            "contagion" => Some(Layer::Contagion),
+            _ => None,
+        }
+    }
+}
+
+/// Top of the stack : the application, which receives the Messages delivered by the highest layer, or
+/// released by the FIFO ordering, and records them in the state of their instance. The layers below
+/// deliver at most once per instance. The Messages of a topic the application is not subscribed to are
+/// only recorded. Once the output of a delivery is written, it is acknowledged in the journal : the
+/// deliveries replayed from the journal of a restarted Node only write the output of the unacknowledged
+/// ones.
+pub struct Application {
+    pub id: usize,
+    pub instances: Arc<Mutex<HashMap<Instance, InstanceState>>>,
+    pub subscribed: Arc<AtomicBool>,
+    /// The acknowledged deliveries, when the deliveries are replayed from the journal.
+    pub replayed: Option<Arc<HashSet<Instance>>>,
+    pub journal: Option<Arc<Journal>>,
+}
+
+#[async_trait]
+impl BroadcastLayer for Application {
+    async fn deliver(&self, message: Message) {
+        let written = match (&self.replayed, message.instance) {
+            (Some(acknowledged), Some(instance)) => acknowledged.contains(&instance),
+            _ => false,
+        };
+        if !written {
+            if self.subscribed.load(Ordering::SeqCst) {
+                my_print!(format!("{} delivered : {}", self.id, message.content));
+                // Written before any other await, so that a cancelled task never loses a delivery.
+                prb_deliver(message.content.clone(), self.id.to_string()).await;
+            }
+            if let Some(instance) = message.instance {
+                record(&self.journal, Record::Acknowledged { instance }).await;
+            }
+        }
+        let state = match message.instance {
+            Some(instance) => self.instances.lock().await.get(&instance).cloned(),
+            None => None,
+        };
+        if let Some(state) = state {
+            *state.delivered_msg.lock().await = Some(message);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::message::Message;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use talk::crypto::Identity;
+
+/// Bounds on the Ready Messages stored by a Node, so that a Byzantine peer cannot exhaust its memory.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Maximum number of distinct Ready contents stored for a single peer.
+    pub ready_per_peer: usize,
+    /// Maximum number of distinct Ready contents stored for the instance, all peers together.
+    pub ready_per_instance: usize,
+    /// Maximum number of instances kept for a single source, the oldest ones are pruned past it.
+    pub instances_per_source: usize,
+}
+
+/// Counters of how often the Limits were hit and of the duplicates which were dropped.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CapCounters {
+    pub ready_peer_cap: usize,
+    pub ready_instance_cap: usize,
+    pub duplicate_ready: usize,
+    pub duplicate_subscription: usize,
+    pub early_pull: usize,
+    pub source_cap: usize,
+}
+
+/// Store a Ready Message received from a peer in the given replies, unless it is a duplicate or a Limit
+/// is reached. Returns true if the Message has been stored.
+///
+/// # Arguments
+///
+/// * `replies` - The Ready or Delivery replies to update.
+/// * `from` - The Identity of the Node sending the Ready.
+/// * `message` - The Ready Message received.
+/// * `limits` - The Limits to enforce.
+/// * `counters` - The counters to update when a Message is dropped.
+///
+pub fn store_ready(
+    replies: &mut HashMap<Identity, Vec<Message>>,
+    from: Identity,
+    message: Message,
+    limits: Limits,
+    counters: &mut CapCounters,
+) -> bool {
+    let stored: &Vec<Message> = match replies.get(&from) {
+        Some(stored) => stored,
+        None => {
+            return false;
+        }
+    };
+    if stored.iter().any(|m| m.content == message.content) {
+        counters.duplicate_ready += 1;
+        return false;
+    }
+    if stored.len() >= limits.ready_per_peer {
+        counters.ready_peer_cap += 1;
+        return false;
+    }
+    let contents: Vec<&String> = replies
+        .values()
+        .flatten()
+        .map(|m| &m.content)
+        .unique()
+        .collect();
+    if !contents.contains(&&message.content) && contents.len() >= limits.ready_per_instance {
+        counters.ready_instance_cap += 1;
+        return false;
+    }
+    replies.get_mut(&from).unwrap().push(message);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_caps() {
+        let limits = Limits {
+            ready_per_peer: 2,
+            ready_per_instance: 3,
+            instances_per_source: 1,
+        };
+        let mut counters = CapCounters::default();
+        let id1: Identity = Identity::from_bytes([1; 32]);
+        let id2: Identity = Identity::from_bytes([2; 32]);
+        let id3: Identity = Identity::from_bytes([3; 32]);
+        let mut replies: HashMap<Identity, Vec<Message>> = HashMap::new();
+        replies.insert(id1, Vec::new());
+        replies.insert(id2, Vec::new());
+        let ready = |content: &str| Message::new(2, String::from(content));
+
+        let cases = [
+            (id1, "Test1", true),
+            (id1, "Test1", false),
+            (id1, "Test2", true),
+            (id1, "Test3", false),
+            (id2, "Test3", true),
+            (id2, "Test4", false),
+            (id2, "Test1", true),
+            // Peers outside the sample are never stored.
+            (id3, "Test1", false),
+        ];
+        for (from, content, stored) in cases {
+            let r = store_ready(&mut replies, from, ready(content), limits, &mut counters);
+            assert_eq!(r, stored);
+        }
+
+        assert_eq!(counters.duplicate_ready, 1);
+        assert_eq!(counters.ready_peer_cap, 1);
+        assert_eq!(counters.ready_instance_cap, 1);
+    }
+
+    #[test]
+    fn instance_cap_evicts_nothing() {
+        let limits = Limits {
+            ready_per_peer: 4,
+            ready_per_instance: 2,
+            instances_per_source: 1,
+        };
+        let mut counters = CapCounters::default();
+        let id1: Identity = Identity::from_bytes([1; 32]);
+        let id2: Identity = Identity::from_bytes([2; 32]);
+        let mut replies: HashMap<Identity, Vec<Message>> = HashMap::new();
+        replies.insert(id1, Vec::new());
+        replies.insert(id2, Vec::new());
+        let ready = |content: &str| Message::new(2, String::from(content));
+
+        assert!(store_ready(
+            &mut replies,
+            id1,
+            ready("Test1"),
+            limits,
+            &mut counters
+        ));
+        assert!(store_ready(
+            &mut replies,
+            id1,
+            ready("Test2"),
+            limits,
+            &mut counters
+        ));
+        let before = replies.clone();
+        for content in ["Test3", "Test4"] {
+            for from in [id1, id2] {
+                let r = store_ready(&mut replies, from, ready(content), limits, &mut counters);
+                assert!(!r);
+            }
+        }
+        assert_eq!(counters.ready_instance_cap, 4);
+        for (from, stored) in before.iter() {
+            let contents: Vec<&String> = stored.iter().map(|m| &m.content).collect();
+            let kept: Vec<&String> = replies[from].iter().map(|m| &m.content).collect();
+            assert_eq!(contents, kept);
+        }
+        // The contents already stored are still accepted from the other peers.
+        assert!(store_ready(
+            &mut replies,
+            id2,
+            ready("Test2"),
+            limits,
+            &mut counters
+        ));
+        assert_eq!(replies[&id1].len(), 2);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::config::Config;
+use crate::keys::load_keychain;
+use crate::membership::Allowlist;
+use crate::message::SignedMessage;
+use crate::registry;
+use async_trait::async_trait;
+use doom::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use talk::crypto::{Identity, KeyCard, KeyChain};
+use talk::link::rendezvous::{Connector, Listener};
+use talk::net::{
+    ConnectError, Connector as NetConnector, ListenError, Listener as NetListener, PlainConnection,
+    SecureConnection,
+};
+use talk::unicast::{Receiver, Sender};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+/// Time given to a peer to complete the handshake of a direct connection.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bounds of the pause of the static listener after a failed accept, doubled on every failure.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Member of a static membership : its KeyCard and the address on which it listens.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StaticMember {
+    pub keycard: KeyCard,
+    pub address: SocketAddr,
+}
+
+/// How the nodes find each other : through a shard of the Rendez-Vous server, with the port of its
+/// registry and the allowlist of the system if any, or from a static membership file which fixes the
+/// members and their addresses.
+#[derive(Clone)]
+pub enum Bootstrap {
+    Rendezvous(String, u16, u16, u32, Option<Allowlist>),
+    Static(Vec<StaticMember>),
+}
+
+impl Bootstrap {
+    /// The static membership is used if a membership file is configured, the Rendez-Vous server
+    /// otherwise. Fails if the membership file cannot be read.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the Broadcast.
+    ///
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        if config.membership_file.is_empty() {
+            return Ok(Bootstrap::Rendezvous(
+                config.addr.clone(),
+                config.port,
+                config.registry_port,
+                config.shard,
+                config.allowlist(),
+            ));
+        }
+        load_membership(&config.membership_file).map(Bootstrap::Static)
+    }
+
+    pub fn is_static(&self) -> bool {
+        matches!(self, Bootstrap::Static(_))
+    }
+
+    /// Get the KeyCards of every member of the system. Through the Rendez-Vous server, the KeyCard of
+    /// the node is registered first, and the shard is only returned once every node registered its
+    /// card. A registration refused by the registry is retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `keychain` - The KeyChain of the node.
+    ///
+    pub async fn membership(&self, keychain: &KeyChain) -> Vec<KeyCard> {
+        match self {
+            Bootstrap::Rendezvous(addr, _, registry_port, shard, _) => {
+                while let Err(e) = registry::register(addr, *registry_port, *shard, keychain).await
+                {
+                    println!("ERROR : register in shard {} : {}", shard, e);
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+                loop {
+                    match registry::get_shard(addr, *registry_port, *shard).await {
+                        Ok(keycards) => {
+                            break keycards;
+                        }
+                        Err(_) => {
+                            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                        }
+                    }
+                }
+            }
+            Bootstrap::Static(members) => members.iter().map(|m| m.keycard.clone()).collect(),
+        }
+    }
+
+    /// Get the KeyCards registered in a shard of the Rendez-Vous server, such as the Nodes joining in a
+    /// new epoch. The shard has to be full. A static membership has no shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard.
+    ///
+    pub async fn shard(&self, shard: u32) -> Result<Vec<KeyCard>, String> {
+        match self {
+            Bootstrap::Rendezvous(addr, _, registry_port, _, _) => {
+                registry::get_shard(addr, *registry_port, shard).await
+            }
+            Bootstrap::Static(_) => Err(String::from(
+                "Nodes can only join through the Rendez-Vous server",
+            )),
+        }
+    }
+
+    /// Sender connecting to the members, through the Rendez-Vous server or directly to their address.
+    /// With an allowlist, the addresses the Rendez-Vous server serves for other Identities are never
+    /// connected to.
+    ///
+    /// # Arguments
+    ///
+    /// * `keychain` - The KeyChain used to authenticate the connections.
+    ///
+    pub fn sender(&self, keychain: KeyChain) -> Sender<SignedMessage> {
+        match self {
+            Bootstrap::Rendezvous(addr, port, _, _, allowlist) => {
+                let connector = Connector::new((addr.clone(), *port), keychain, Default::default());
+                match allowlist {
+                    Some(allowlist) => Sender::new(
+                        AllowedConnector {
+                            connector,
+                            allowlist: allowlist.clone(),
+                        },
+                        Default::default(),
+                    ),
+                    None => Sender::new(connector, Default::default()),
+                }
+            }
+            Bootstrap::Static(members) => {
+                let peers: HashMap<Identity, SocketAddr> = members
+                    .iter()
+                    .map(|m| (m.keycard.identity(), m.address))
+                    .collect();
+                Sender::new(StaticConnector { keychain, peers }, Default::default())
+            }
+        }
+    }
+
+    /// Receiver of the node, registered with the Rendez-Vous server or bound to the address of the
+    /// node in the static membership. Fails if the node is missing from the static membership or its
+    /// address cannot be bound.
+    ///
+    /// # Arguments
+    ///
+    /// * `keychain` - The KeyChain of the node.
+    ///
+    pub async fn receiver(&self, keychain: KeyChain) -> Result<Receiver<SignedMessage>, String> {
+        match self {
+            Bootstrap::Rendezvous(addr, port, _, _, _) => {
+                let listener =
+                    Listener::new((addr.clone(), *port), keychain, Default::default()).await;
+                Ok(Receiver::new(listener, Default::default()))
+            }
+            Bootstrap::Static(members) => {
+                let identity = keychain.keycard().identity();
+                let address = members
+                    .iter()
+                    .find(|m| m.keycard.identity() == identity)
+                    .ok_or(format!(
+                        "{:?} is missing from the membership file",
+                        identity
+                    ))?
+                    .address;
+                let listener = StaticListener::bind(keychain, address).await?;
+                Ok(Receiver::new(listener, Default::default()))
+            }
+        }
+    }
+}
+
+/// Connector through the Rendez-Vous server which only connects to the Identities of the allowlist. The
+/// connections it opens are authenticated, a Node outside the allowlist is never reached even if the
+/// server serves its address. Senders are not filtered by the listening side : the Node rejects the
+/// Messages of non-members itself, and the control Messages come from one-off KeyChains.
+pub struct AllowedConnector {
+    connector: Connector,
+    allowlist: Allowlist,
+}
+
+#[async_trait]
+impl NetConnector for AllowedConnector {
+    async fn connect(&self, remote: Identity) -> Result<SecureConnection, Top<ConnectError>> {
+        if !self.allowlist.identities.contains(&remote) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "identity not in the allowlist",
+            ))
+            .pot(ConnectError::AddressUnknown, here!());
+        }
+        self.connector.connect(remote).await
+    }
+}
+
+/// Connector opening direct connections to the addresses of the static membership. The connection
+/// is only used if the peer authenticates with the Identity it was opened to.
+pub struct StaticConnector {
+    keychain: KeyChain,
+    peers: HashMap<Identity, SocketAddr>,
+}
+
+#[async_trait]
+impl NetConnector for StaticConnector {
+    async fn connect(&self, remote: Identity) -> Result<SecureConnection, Top<ConnectError>> {
+        let address = match self.peers.get(&remote) {
+            Some(address) => *address,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "unknown member"))
+                    .pot(ConnectError::AddressUnknown, here!());
+            }
+        };
+        let stream = TcpStream::connect(address)
+            .await
+            .pot(ConnectError::ConnectFailed, here!())?;
+        let mut connection = PlainConnection::from(stream)
+            .secure()
+            .await
+            .pot(ConnectError::SecureFailed, here!())?;
+        let keycard = connection
+            .authenticate(&self.keychain)
+            .await
+            .pot(ConnectError::AuthenticateFailed, here!())?;
+        if keycard.identity() != remote {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "peer authenticated as another member",
+            ))
+            .pot(ConnectError::UnexpectedRemote, here!());
+        }
+        Ok(connection)
+    }
+}
+
+/// Listener accepting direct connections on the address of the node in the static membership. Senders
+/// are authenticated but not filtered, the Node rejects the Messages of non-members itself. Every
+/// connection completes its handshake in its own task, so that a slow peer delays no other one.
+pub struct StaticListener {
+    connections: mpsc::Receiver<(Identity, SecureConnection)>,
+}
+
+impl StaticListener {
+    pub async fn bind(keychain: KeyChain, address: SocketAddr) -> Result<Self, String> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| format!("bind {} : {}", address, e))?;
+        let (sender, connections) = mpsc::channel(64);
+        tokio::spawn(StaticListener::listen(keychain, listener, sender));
+        Ok(StaticListener { connections })
+    }
+
+    /// Accept the connections until the StaticListener is dropped, and hand over the authenticated
+    /// ones. Failed accepts, e.g. when out of file descriptors, are retried after a growing pause.
+    ///
+    /// # Arguments
+    ///
+    /// * `keychain` - The KeyChain of the node.
+    /// * `listener` - The bound listener.
+    /// * `sender` - Where the authenticated connections are handed over.
+    ///
+    async fn listen(
+        keychain: KeyChain,
+        listener: TcpListener,
+        sender: mpsc::Sender<(Identity, SecureConnection)>,
+    ) {
+        let mut backoff = ACCEPT_BACKOFF;
+        loop {
+            let accepted = tokio::select! {
+                _ = sender.closed() => break,
+                accepted = listener.accept() => accepted,
+            };
+            let stream = match accepted {
+                Ok((stream, _)) => {
+                    backoff = ACCEPT_BACKOFF;
+                    stream
+                }
+                Err(e) => {
+                    println!("ERROR : static accept : {}", e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let (keychain, sender) = (keychain.clone(), sender.clone());
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(stream, &keychain)).await {
+                    Ok(Ok(connection)) => {
+                        let _ = sender.send(connection).await;
+                    }
+                    Ok(Err(e)) => {
+                        println!("ERROR : static handshake : {:?}", e);
+                    }
+                    Err(_) => {
+                        println!("ERROR : static handshake timed out");
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Secure and authenticate an accepted connection.
+///
+/// # Arguments
+///
+/// * `stream` - The accepted stream.
+/// * `keychain` - The KeyChain of the node.
+///
+async fn handshake(
+    stream: TcpStream,
+    keychain: &KeyChain,
+) -> Result<(Identity, SecureConnection), Top<ListenError>> {
+    let mut connection = PlainConnection::from(stream)
+        .secure()
+        .await
+        .pot(ListenError::SecureFailed, here!())?;
+    let keycard = connection
+        .authenticate(keychain)
+        .await
+        .pot(ListenError::AuthenticateFailed, here!())?;
+    Ok((keycard.identity(), connection))
+}
+
+#[async_trait]
+impl NetListener for StaticListener {
+    async fn accept(&mut self) -> Result<(Identity, SecureConnection), Top<ListenError>> {
+        match self.connections.recv().await {
+            Some(connection) => Ok(connection),
+            None => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "static listener stopped",
+            ))
+            .pot(ListenError::AcceptFailed, here!()),
+        }
+    }
+}
+
+/// Read a static membership file : a JSON list of members with their KeyCard and address.
+///
+/// # Arguments
+///
+/// * `path` - The path of the membership file.
+///
+pub fn load_membership(path: &str) -> Result<Vec<StaticMember>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("read {} : {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("parse {} : {}", path, e))
+}
+
+/// Write a static membership file for the nodes 0 to count - 1, from their stored KeyChains. Node i
+/// listens on the configured address, on port `first_port + i`.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the Broadcast.
+/// * `count` - The number of nodes.
+/// * `first_port` - The port of node 0.
+///
+pub fn write_membership(config: &Config, count: usize, first_port: u16) -> Result<(), String> {
+    if config.membership_file.is_empty() {
+        return Err(String::from("membership_file is not configured"));
+    }
+    let mut members: Vec<StaticMember> = Vec::new();
+    for id in 0..count {
+        let keychain = load_keychain(&config.key_file(id))?;
+        let address: SocketAddr = format!("{}:{}", config.addr, first_port as usize + id)
+            .parse()
+            .map_err(|e| format!("address of node {} : {}", id, e))?;
+        members.push(StaticMember {
+            keycard: keychain.keycard(),
+            address,
+        });
+    }
+    let content = serde_json::to_string_pretty(&members).unwrap();
+    fs::write(&config.membership_file, content)
+        .map_err(|e| format!("write {} : {}", config.membership_file, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_address() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn silent_peers_do_not_block_the_static_listener() {
+        let address = free_address();
+        let keychain = KeyChain::random();
+        let identity = keychain.keycard().identity();
+        let mut listener = StaticListener::bind(keychain, address).await.unwrap();
+        // A peer which connects and never starts its handshake.
+        let _silent = TcpStream::connect(address).await.unwrap();
+
+        let peer = KeyChain::random();
+        let impostor = KeyChain::random().keycard().identity();
+        let connector = StaticConnector {
+            keychain: peer.clone(),
+            peers: vec![(identity, address), (impostor, address)]
+                .into_iter()
+                .collect(),
+        };
+        let (connected, accepted) = tokio::join!(
+            connector.connect(identity),
+            tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        );
+        assert!(connected.is_ok());
+        let (accepted, _) = accepted.unwrap().unwrap();
+        assert_eq!(accepted, peer.keycard().identity());
+        // The connection is only used if the peer is the expected one.
+        assert!(connector.connect(impostor).await.is_err());
+        assert!(connector
+            .connect(KeyChain::random().keycard().identity())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn dropped_static_listener_releases_its_address() {
+        let address = free_address();
+        let listener = StaticListener::bind(KeyChain::random(), address)
+            .await
+            .unwrap();
+        drop(listener);
+        // The accepting task exits without any new connection, and closes the socket.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(TcpStream::connect(address).await.is_err());
+        assert!(StaticListener::bind(KeyChain::random(), address)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_member_is_an_error() {
+        let bootstrap = Bootstrap::Static(vec![StaticMember {
+            keycard: KeyChain::random().keycard(),
+            address: free_address(),
+        }]);
+        assert!(bootstrap.receiver(KeyChain::random()).await.is_err());
+    }
+}
@@ -2,125 +2,392 @@
 
 #[macro_use]
 mod my_macros;
+mod bracha;
+mod causal;
+mod certificate;
+mod config;
 mod contagion;
+mod control;
+mod evidence;
+mod exclusion;
+mod fifo;
+mod instance;
+mod journal;
+mod keys;
+mod launcher;
+mod layer;
+mod limits;
+mod link;
+mod membership;
 mod message;
 mod message_headers;
 mod murmur;
 mod node;
+mod operator;
+mod pull;
+mod push;
+mod registry;
 mod sieve;
+mod store;
+mod tasks;
+mod transfer;
 mod utils;
 
-use crate::message::{Message, SignedMessage};
-use crate::message_headers::{Gossip, InitEcho, InitGossip, InitReady};
+use crate::config::Config;
+use crate::control::{NodeHandle, Phase};
+use crate::exclusion::load_exclusions;
+use crate::keys::{load_keycard, load_keychain, load_or_create_operator};
+use crate::link::{write_membership, Bootstrap};
+use crate::membership::Epoch;
+use crate::message::{Instance, Message, SignedMessage};
 use crate::node::Node;
+use crate::operator::Operator;
+use futures::future::join_all;
 use rand::prelude::*;
 use std::collections::HashMap;
-use std::{fs, io};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 use talk::crypto::{Identity, KeyCard, KeyChain};
-use talk::link::rendezvous::{Client, Connector, Listener};
-use talk::unicast::{Receiver, Sender};
+use talk::unicast::Sender;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 
 extern crate chrono;
 extern crate rand;
 
+const USAGE: &str = "Usage : sbr_broadcast [command]
+
+Commands :
+    (none)                    Run the spawn nodes of the configuration in this process
+    node <id> [--key <path>]  Run node <id> as its own process, from its stored KeyChain
+    keygen <count>            Create and store the KeyChains of nodes 0 to count - 1
+    launch [count]            Start count node processes locally, spawn by default
+    operator                  Serve the operator socket, which signs the control Messages of the
+                              node processes with the operator key
+    membership <count> <port> Write the static membership file of nodes 0 to count - 1, node i
+                              listening on port + i
+    allowlist <count>         Write the allowlist file with the identities of nodes 0 to count - 1
+    verify <certificate> [<membership>]
+                              Verify a delivery certificate against the members of the static
+                              membership file, membership_file by default, and the D and D_thr of
+                              the configuration";
+
+fn usage() -> ! {
+    println!("{}", USAGE);
+    process::exit(2);
+}
+
 #[tokio::main]
 async fn main() {
-    my_print!("Start");
-    // Read config files
-    let content = fs::read_to_string("broadcast.config").expect("Error reading config file");
-    let lines = content.split("\n");
-    // Default values, if not specified in config file.
-    let mut addr: String = String::from("127.0.0.1");
-    let mut port: u16 = 4446;
-    let mut spawn: usize = 1;
-    let mut g: usize = 10;
-    let mut e: usize = 40;
-    let mut e_thr: usize = 10;
-    let mut r: usize = 30;
-    let mut r_thr: usize = 10;
-    let mut d: usize = 25;
-    let mut d_thr: usize = 14;
-    for line in lines {
-        let mut elems = line.split("=");
-        match elems.next().unwrap() {
-            "addr" => {
-                addr = elems.next().unwrap().to_string();
-            }
-            "port" => {
-                port = elems.next().unwrap().parse().unwrap();
-            }
-            "spawn" => {
-                spawn = elems.next().unwrap().parse().unwrap();
-            }
-            "N" => {}
-            "G" => {
-                g = elems.next().unwrap().parse().unwrap();
-            }
-            "E" => {
-                e = elems.next().unwrap().parse().unwrap();
-            }
-            "E_thr" => {
-                e_thr = elems.next().unwrap().parse().unwrap();
-            }
-            "R" => {
-                r = elems.next().unwrap().parse().unwrap();
-            }
-            "R_thr" => {
-                r_thr = elems.next().unwrap().parse().unwrap();
-            }
-            "D" => {
-                d = elems.next().unwrap().parse().unwrap();
+    let config = Config::load("broadcast.config");
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.get(0).map(|a| a.as_str()) {
+        None => run_all(config).await,
+        Some("node") => {
+            let id: usize = match args.get(1).map(|a| a.parse()) {
+                Some(Ok(id)) => id,
+                _ => usage(),
+            };
+            let key_file = match (args.get(2).map(|a| a.as_str()), args.get(3)) {
+                (None, _) => config.key_file(id),
+                (Some("--key"), Some(path)) => path.clone(),
+                _ => usage(),
+            };
+            run_node(config, id, key_file).await
+        }
+        Some("operator") => {
+            let keychain = load_or_create_operator(&config.operator_key, &config.operator_card);
+            operator::serve(config.operator_socket.clone(), keychain).await
+        }
+        Some("keygen") => match args.get(1).map(|a| a.parse()) {
+            Some(Ok(count)) => launcher::keygen(&config, count),
+            _ => usage(),
+        },
+        Some("membership") => match (
+            args.get(1).map(|a| a.parse()),
+            args.get(2).map(|a| a.parse()),
+        ) {
+            (Some(Ok(count)), Some(Ok(port))) => {
+                launcher::keygen(&config, count);
+                if let Err(e) = write_membership(&config, count, port) {
+                    println!("ERROR : membership : {}", e);
+                    process::exit(1);
+                }
             }
-            "D_thr" => {
-                d_thr = elems.next().unwrap().parse().unwrap();
+            _ => usage(),
+        },
+        Some("allowlist") => match args.get(1).map(|a| a.parse()) {
+            Some(Ok(count)) => {
+                launcher::keygen(&config, count);
+                if let Err(e) = launcher::write_allowlist(&config, count) {
+                    println!("ERROR : allowlist : {}", e);
+                    process::exit(1);
+                }
             }
-            "" => {}
-            _ => {
-                println!("Unknown configuration : {}", line);
+            _ => usage(),
+        },
+        Some("verify") => match args.get(1) {
+            Some(path) => {
+                let members = args.get(2).unwrap_or(&config.membership_file);
+                match certificate::verify_file(path, members, config.d, config.d_thr) {
+                    Ok(message) => println!(
+                        "Certified delivery of instance {:?} : {}",
+                        message.instance, message.content
+                    ),
+                    Err(e) => {
+                        println!("ERROR : verify : {}", e);
+                        process::exit(1);
+                    }
+                }
             }
+            _ => usage(),
+        },
+        Some("launch") => {
+            let count: usize = match args.get(1).map(|a| a.parse()) {
+                None => config.spawn,
+                Some(Ok(count)) => count,
+                _ => usage(),
+            };
+            launcher::launch(&config, count).await
         }
+        _ => usage(),
     }
+}
+
+/// Run all the nodes of the configuration as tasks of this process, with new KeyChains.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the Broadcast.
+///
+async fn run_all(config: Config) {
+    my_print!("Start");
+    let spawn = config.spawn;
+    // Stdin broadcasts go to the first configured topic.
+    let topic = config.topics[0];
+
+    // Control Messages sent to the nodes are signed with the operator key, held by this process.
+    let operator = load_or_create_operator(&config.operator_key, &config.operator_card);
+
+    // With a static membership or an allowlist, the nodes use the stored KeyChains, whose Identities
+    // are listed in the membership or allowlist file.
+    let bootstrap = match Bootstrap::from_config(&config) {
+        Ok(bootstrap) => bootstrap,
+        Err(e) => {
+            println!("ERROR : membership : {}", e);
+            process::exit(1);
+        }
+    };
+    let stored_keys = bootstrap.is_static() || !config.allowlist_file.is_empty();
 
     // Setup N nodes.
-    let mut identities = vec![];
+    let mut handles: Vec<NodeHandle> = vec![];
     for i in 0..spawn {
-        let node_keychain = KeyChain::random();
+        let node_keychain = if stored_keys {
+            match load_keychain(&config.key_file(i)) {
+                Ok(keychain) => keychain,
+                Err(e) => {
+                    println!("ERROR : node key : {}", e);
+                    process::exit(1);
+                }
+            }
+        } else {
+            KeyChain::random()
+        };
         /*println!(
             "<{}> : KC : {:?}",
             i,
             node_keychain.keycard().identity().clone()
         );*/
-        identities.push(node_keychain.keycard().identity().clone());
+        let handle = NodeHandle::new(i, node_keychain.keycard().identity());
+        handles.push(handle.clone());
         tokio::spawn(setup_node(
             node_keychain.clone(),
-            addr.clone(),
-            port,
+            config.clone(),
+            bootstrap.clone(),
             i,
-            g,
-            e,
-            e_thr,
-            r,
-            r_thr,
-            d,
-            d_thr,
+            operator.keycard(),
+            handle,
         ));
     }
+    let handles = Arc::new(handles);
+    let operator = Operator::KeyChain(operator);
+
+    tokio::spawn(control::report_ready(handles.clone()));
+
+    let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
+    tokio::spawn(control::serve(
+        config.control_socket.clone(),
+        handles.clone(),
+        bootstrap.clone(),
+        operator.clone(),
+        shutdown_sender,
+    ));
+
+    // SIGINT and SIGTERM stop the Broadcast like the exit command.
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
 
+    // Stdin commands stay available alongside the control socket.
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
     loop {
-        let mut input: String = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        match input.as_str() {
-            "send\n" => {
-                let mut rng = rand::thread_rng();
-                let n = rng.gen_range(0..spawn);
-                trigger_send(addr.clone(), port, identities[n]).await;
+        tokio::select! {
+            line = lines.next_line(), if stdin_open => match line {
+                Ok(Some(input)) => {
+                    let mut words = input.splitn(3, ' ');
+                    match words.next() {
+                        // "send" broadcasts a test message from a random node, "send <node> <payload>"
+                        // broadcasts the payload from the given node.
+                        Some("send") => {
+                            let (n, payload) = match (words.next(), words.next()) {
+                                (Some(n), Some(payload)) => match n.parse::<usize>() {
+                                    Ok(n) if n < spawn => (n, String::from(payload)),
+                                    _ => {
+                                        println!("ERROR : unknown node {}", n);
+                                        continue;
+                                    }
+                                },
+                                _ => (
+                                    rand::thread_rng().gen_range(0..spawn),
+                                    String::from("Test message"),
+                                ),
+                            };
+                            if let Err(e) = control::can_broadcast(&handles, &handles[n]).await {
+                                println!("ERROR : {}", e);
+                                continue;
+                            }
+                            let instance = handles[n].next_instance(topic).await;
+                            my_print!(format!(
+                                "Broadcast from {} with sequence {}",
+                                n, instance.sequence
+                            ));
+                            let sent = trigger_send(
+                                bootstrap.clone(),
+                                operator.clone(),
+                                instance,
+                                topic,
+                                payload,
+                            )
+                            .await;
+                            if let Err(e) = sent {
+                                println!("ERROR : trigger : {}", e);
+                            }
+                        }
+                        Some("exit") => {
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {
+                    stdin_open = false;
+                }
+            },
+            _ = shutdown_receiver.recv() => {
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                break;
             }
-            "exit\n" => {
+            _ = terminate.recv() => {
                 break;
             }
-            _ => {}
         }
     }
+    stop_nodes(&handles, &config.control_socket).await;
+}
+
+/// Run a single node as its own process, from its stored KeyChain. The node is controlled through its
+/// own control socket, named after the configured one and the ID of the node.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the Broadcast.
+/// * `id` - The ID of the node.
+/// * `key_file` - The file in which the KeyChain of the node is stored.
+///
+async fn run_node(config: Config, id: usize, key_file: String) {
+    my_print!(format!("Start node {}", id));
+    let node_keychain = match load_keychain(&key_file) {
+        Ok(keychain) => keychain,
+        Err(e) => {
+            println!("ERROR : node key : {}", e);
+            process::exit(1);
+        }
+    };
+    // A node process only verifies the control Messages : they are signed on the operator socket.
+    let operator = match load_keycard(&config.operator_card) {
+        Ok(keycard) => keycard,
+        Err(e) => {
+            println!("ERROR : operator card : {}", e);
+            process::exit(1);
+        }
+    };
+
+    let bootstrap = match Bootstrap::from_config(&config) {
+        Ok(bootstrap) => bootstrap,
+        Err(e) => {
+            println!("ERROR : membership : {}", e);
+            process::exit(1);
+        }
+    };
+
+    let handle = NodeHandle::new(id, node_keychain.keycard().identity());
+    tokio::spawn(setup_node(
+        node_keychain,
+        config.clone(),
+        bootstrap.clone(),
+        id,
+        operator,
+        handle.clone(),
+    ));
+    let handles = Arc::new(vec![handle]);
+
+    tokio::spawn(control::report_ready(handles.clone()));
+
+    let control_socket = format!("{}.{}", config.control_socket, id);
+    let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
+    tokio::spawn(control::serve(
+        control_socket.clone(),
+        handles.clone(),
+        bootstrap,
+        Operator::Socket(config.operator_socket.clone()),
+        shutdown_sender,
+    ));
+
+    // The launcher stops its node processes with SIGTERM.
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = shutdown_receiver.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    stop_nodes(&handles, &control_socket).await;
+}
+
+/// Stop every node, which stops accepting Messages and cancels its in-flight pushes, then report.
+///
+/// # Arguments
+///
+/// * `handles` - The handles of the nodes of this process.
+/// * `control_socket` - The control socket to remove.
+///
+async fn stop_nodes(handles: &[NodeHandle], control_socket: &str) {
+    my_print!("Shutting down");
+    for handle in handles.iter() {
+        handle.stop.notify_one();
+    }
+    if !control::wait_stopped(handles, Duration::from_secs(5)).await {
+        println!("ERROR : some nodes did not stop in time");
+    }
+    control::print_summary(handles).await;
+    let _ = fs::remove_file(control_socket);
+    let _ = std::io::stdout().flush();
 }
 
 /// Setup and initialise a node with given parameters.
@@ -128,56 +395,39 @@ async fn main() {
 /// # Arguments
 ///
 /// * `node_keychain` - The KeyChain of the node to setup.
-/// * `addr` - The adresse of the Rendez-Vous server.
-/// * `port` - The port of the Rendez-Vous server.
+/// * `config` - The configuration of the Broadcast : Rendez-Vous server, set sizes, thresholds and
+/// policies.
+/// * `bootstrap` - How the node finds the other members.
 /// * `i` - The ID of the node.
-/// * `g` - The Gossip set size.
-/// * `e` - The Echo set size.
-/// * `e_thr` - The Echo threshold.
-/// * `r` - The Ready set size.
-/// * `r_thr` - The Ready threshold.
-/// * `d` - The Delivery set size.
-/// * `d_thr` - The Delivery threshold.
+/// * `operator` - The operator KeyCard, used to verify the control Messages.
+/// * `handle` - The handle through which the control socket follows the node.
 ///
 async fn setup_node(
     node_keychain: KeyChain,
-    addr: String,
-    port: u16,
+    config: Config,
+    bootstrap: Bootstrap,
     i: usize,
-    g: usize,
-    e: usize,
-    e_thr: usize,
-    r: usize,
-    r_thr: usize,
-    d: usize,
-    d_thr: usize,
+    operator: KeyCard,
+    handle: NodeHandle,
 ) {
-    let client = Client::new((addr.clone(), port), Default::default());
-
-    client
-        .publish_card(node_keychain.keycard(), Some(0))
-        .await
-        .unwrap();
-
-    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-
-    let keycards: Vec<KeyCard> = loop {
-        let kc = client.get_shard(0).await;
-        match kc {
-            Ok(kcs) => {
-                break kcs;
-            }
-            Err(_) => {
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-            }
-        }
+    let keycards: Vec<KeyCard> = bootstrap.membership(&node_keychain).await;
+    // A joining Node registers in its own shard, and waits for the operator to include it in an epoch.
+    let keycards = if config.join { Vec::new() } else { keycards };
+    // KeyCards registered by Identities which are not allowed never join the samples.
+    let settings = config.node_settings();
+    let keycards = match &settings.allowlist {
+        Some(allowlist) => allowlist.filter(i, keycards),
+        None => keycards,
     };
+    my_print!(format!("{} membership complete", i));
 
-    let other_keycards = keycards
+    let all_keycards: HashMap<Identity, KeyCard> = keycards
         .clone()
         .into_iter()
-        .filter(|keycard| *keycard != node_keychain.keycard())
-        .collect::<Vec<_>>();
+        .map(|keycard| (keycard.identity(), keycard))
+        .collect();
+    let exclusion_file = format!("{}/exclusions_{}.json", config.exclusion_dir, i);
+    let exclusions = load_exclusions(&config.exclusion_dir, &exclusion_file, &all_keycards);
 
     let map_keycards = keycards
         .clone()
@@ -196,164 +446,153 @@ async fn setup_node(
         .into_iter()
         .collect::<HashMap<Identity, KeyCard>>();
 
-    let connector = Connector::new(
-        (addr.clone(), port),
-        node_keychain.clone(),
-        Default::default(),
-    );
-
-    let sender = Sender::new(connector, Default::default());
+    let sender = bootstrap.sender(node_keychain.clone());
+    let mut receiver = match bootstrap.receiver(node_keychain.clone()).await {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            println!("ERROR : <{}> receiver : {}", i, e);
+            *handle.phase.lock().await = Phase::Stopped;
+            return;
+        }
+    };
 
-    let listener = Listener::new(
-        (addr.clone(), port),
+    let node: Node = Node::new(
         node_keychain.clone(),
-        Default::default(),
-    )
-    .await;
-    let mut receiver = Receiver::new(listener, Default::default());
-
-    let mut node: Node = Node::new(node_keychain.clone(), map_keycards, i, e_thr, r_thr, d_thr);
-    murmur::init(g, other_keycards.clone(), &node.gossip_peers).await;
-    sieve::init(
-        e,
-        other_keycards.clone(),
-        &node.echo_replies,
-        &mut node.duplicate_echo,
-    )
-    .await;
-    contagion::init(
-        r,
-        d,
-        other_keycards.clone(),
-        &node.ready_replies,
-        &node.delivery_replies,
-        &mut node.duplicate_ready,
-        &mut node.duplicate_delivery,
-    )
-    .await;
-    let kc: KeyChain = node_keychain.clone();
-    tokio::spawn(send_initialisation_signals(
-        addr.clone(),
-        port,
-        kc.keycard(),
+        map_keycards,
         i,
-    ));
+        exclusion_file,
+        exclusions,
+        operator,
+        settings,
+    );
+    // Every topic runs its own Node, with its own samples, on the same transport and KeyChain.
+    let mut topics: Vec<Node> = config.topics.iter().map(|t| node.in_topic(*t)).collect();
+    let mut subscriptions = Vec::new();
+    let mut member = !config.join;
+    for node in topics.iter_mut() {
+        // A restarted Node reuses the epoch and the samples of its journal, and continues the
+        // numbering of its instances.
+        let recovered = node.recover(&sender).await;
+        handle
+            .resume(
+                node.topic,
+                recovered.next_sequence(node_keychain.keycard().identity()),
+            )
+            .await;
+        member |= recovered.epoch.is_some();
+        // Excluded peers are never sampled, and only the layers up to the configured one sample.
+        if recovered.samples.is_none() {
+            node.draw_samples().await;
+        }
+        subscriptions.push(node.subscriptions(&sender).await);
+        handle.views.lock().await.insert(node.topic, node.view());
+    }
+    if member {
+        if let Some(node) = topics.first() {
+            *handle.membership.lock().await = Some(node.membership().await);
+        }
+    }
 
-    node.listen(sender, &mut receiver, kc.keycard().clone())
-        .await;
+    *handle.phase.lock().await = Phase::Subscribing;
+    let listen_sender = sender.clone();
+    let listen_handle = handle.clone();
+    let mut listening = tokio::spawn(async move {
+        Node::listen(topics, listen_handle, listen_sender, &mut receiver).await
+    });
+
+    // Subscriptions are pushed until every sampled peer acknowledged them, peers which are still in
+    // setup acknowledge them once they listen. Only the layers which run subscribe, in every topic,
+    // and Bracha has no subscriptions.
+    tokio::select! {
+        _ = join_all(subscriptions) => {
+            *handle.phase.lock().await = Phase::Ready;
+            my_print!(format!("{} ready", i));
+            let _ = listening.await;
+        }
+        _ = &mut listening => {}
+    }
+    *handle.phase.lock().await = Phase::Stopped;
 }
 
-/// Send signals to initialise the sets which require subscriptions.
+/// Announce a new epoch to the given Nodes, with a signal signed by the operator. The signal is sent
+/// a few times to the Nodes which cannot be reached, which may have stopped, then given up on.
 ///
 /// # Arguments
 ///
-/// * `addr` - The adresse of the Rendez-Vous server.
-/// * `port` - The port of the Rendez-Vous server.
-/// * `kc` - The KeyCard of the node to initialise.
-/// * `id` - The ID of the node.
+/// * `bootstrap` - How the Nodes are reached.
+/// * `operator` - The operator, which signs the signal.
+/// * `epoch` - The new epoch and its members.
+/// * `recipients` - The Nodes to which the signal is sent.
 ///
-async fn send_initialisation_signals(addr: String, port: u16, kc: KeyCard, id: usize) {
-    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-    let init_keychain = KeyChain::random();
-
-    let connector = Connector::new((addr, port), init_keychain.clone(), Default::default());
-
-    let sender = Sender::new(connector, Default::default());
-
-    let t_sender = sender.clone();
-    let t_kc = kc.clone();
-    let t_init_keychain = init_keychain.clone();
-    tokio::spawn(async move {
-        loop {
-            let gossip_init: Message = Message::new(6, String::from("Init Gossip Subscription"));
-            let signature = t_init_keychain
-                .sign(&InitGossip(gossip_init.clone()))
-                .unwrap();
-            let signed_msg: SignedMessage = SignedMessage::new(gossip_init, signature);
-            let r = t_sender.send(t_kc.identity(), signed_msg).await;
-            match r {
-                Ok(_) => {
-                    break;
-                }
-                Err(e) => {
-                    println!("ERROR : <{}> init gossip send : {}", id, e);
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                }
-            }
-        }
-    });
-    let t_sender = sender.clone();
-    let t_kc = kc.clone();
-    let t_init_keychain = init_keychain.clone();
-    tokio::spawn(async move {
-        loop {
-            let echo_init: Message = Message::new(7, String::from("Init Echo Subscription"));
-            let signature = t_init_keychain.sign(&InitEcho(echo_init.clone())).unwrap();
-            let signed_msg: SignedMessage = SignedMessage::new(echo_init, signature);
-            let r = t_sender.send(t_kc.identity(), signed_msg).await;
-            match r {
-                Ok(_) => {
-                    break;
-                }
-                Err(e) => {
-                    println!("ERROR : <{}> init sieve send : {}", id, e);
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                }
-            }
-        }
-    });
-    let t_sender = sender.clone();
-    let t_kc = kc.clone();
-    let t_init_keychain = init_keychain.clone();
-    tokio::spawn(async move {
-        loop {
-            let ready_init: Message = Message::new(8, String::from("Init Ready Subscription"));
-            let signature = t_init_keychain
-                .sign(&InitReady(ready_init.clone()))
-                .unwrap();
-            let signed_msg: SignedMessage = SignedMessage::new(ready_init, signature);
-            let r = t_sender.send(t_kc.identity(), signed_msg).await;
-            match r {
-                Ok(_) => {
-                    break;
-                }
-                Err(e) => {
-                    println!("ERROR : <{}> init contagion send : {}", id, e);
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+pub async fn announce_epoch(
+    bootstrap: Bootstrap,
+    operator: Operator,
+    epoch: &Epoch,
+    recipients: Vec<Identity>,
+) -> Result<(), String> {
+    my_print!(format!("Announce epoch {}", epoch.epoch));
+
+    let msg = Message::new(13, serde_json::to_string(epoch).unwrap());
+    let signed_msg = operator.sign(msg).await?;
+    // The signature of the operator authenticates the signal, not the link it is sent on.
+    let tmp_sender: Sender<SignedMessage> = bootstrap.sender(KeyChain::random());
+    join_all(recipients.into_iter().map(|recipient| {
+        let (tmp_sender, signed_msg) = (tmp_sender.clone(), signed_msg.clone());
+        async move {
+            for _ in 0..5 {
+                match tmp_sender.send(recipient, signed_msg.clone()).await {
+                    Ok(_) => {
+                        return;
+                    }
+                    Err(e) => {
+                        println!("ERROR : epoch send : {}", e);
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    }
                 }
             }
+            println!("ERROR : epoch {} not sent to {:?}", epoch.epoch, recipient);
         }
-    });
+    }))
+    .await;
+    Ok(())
 }
 
-/// Wait for system to finish setup and then send the signal to trigger the Broadcast.
+/// Send the signal to trigger the Broadcast of a payload by a Node, in the given instance. Fails if the
+/// Node cannot be reached after a few attempts.
 ///
 /// # Arguments
 ///
-/// * `addr` - The adresse of the Rendez-Vous server.
-/// * `port` - The port of the Rendez-Vous server.
-/// * `id` - The Identity of the Node which will receive the signal.
+/// * `bootstrap` - How the Node is reached.
+/// * `operator` - The operator, which signs the signal.
+/// * `instance` - The instance to broadcast, its source is the Node which will receive the signal.
+/// * `topic` - The topic of the instance.
+/// * `payload` - The content the Node will broadcast.
 ///
-async fn trigger_send(addr: String, port: u16, id: Identity) {
+async fn trigger_send(
+    bootstrap: Bootstrap,
+    operator: Operator,
+    instance: Instance,
+    topic: u32,
+    payload: String,
+) -> Result<(), String> {
     my_print!("Trigger send");
-    let sender_keychain = KeyChain::random();
 
-    let connector = Connector::new((addr, port), sender_keychain.clone(), Default::default());
-
-    let tmp_sender: Sender<SignedMessage> = Sender::new(connector, Default::default());
-    let msg = Message::new(9, String::from("Trigger send"));
-    let signature = sender_keychain.sign(&Gossip(msg.clone())).unwrap();
-    let signed_msg: SignedMessage = SignedMessage::new(msg, signature);
-    loop {
-        let r = tmp_sender.send(id, signed_msg.clone()).await;
-        match r {
+    let msg = Message::with_instance(9, instance, payload).in_topic(topic);
+    let signed_msg = operator.sign(msg).await?;
+    let tmp_sender: Sender<SignedMessage> = bootstrap.sender(KeyChain::random());
+    for _ in 0..5 {
+        match tmp_sender.send(instance.source, signed_msg.clone()).await {
             Ok(_) => {
-                break;
+                return Ok(());
             }
             Err(e) => {
-                println!("ERROR : echo_subscription send : {}", e);
+                println!("ERROR : trigger send : {}", e);
                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             }
         }
     }
+    Err(format!(
+        "trigger of sequence {} not sent to {:?}",
+        instance.sequence, instance.source
+    ))
 }
@@ -0,0 +1,181 @@
+use crate::registry;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Arc;
+use talk::crypto::{Identity, KeyCard};
+use tokio::sync::Mutex;
+
+/// Policy applied to the Messages received from Identities which are not members of the system.
+#[derive(Clone, Debug)]
+pub struct UnknownPolicy {
+    /// Log every rejected Message.
+    pub log: bool,
+    /// Number of rejected Messages after which the membership is refreshed. 0 never refreshes.
+    pub refresh_after: usize,
+}
+
+/// Members of the system during an epoch. The members of epoch 0 are fetched when the Nodes start, the
+/// operator announces every later epoch in a signed signal to the members of the previous and the new
+/// epoch.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Epoch {
+    pub epoch: u64,
+    pub members: Vec<KeyCard>,
+}
+
+impl Epoch {
+    /// Whether the Identity is a member of the epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `identity` - The Identity.
+    ///
+    pub fn contains(&self, identity: &Identity) -> bool {
+        self.members.iter().any(|m| m.identity() == *identity)
+    }
+
+    /// The Identities of the members, sorted as written in the control responses.
+    pub fn identities(&self) -> Vec<String> {
+        let mut identities: Vec<String> = self
+            .members
+            .iter()
+            .map(|m| format!("{:?}", m.identity()))
+            .collect();
+        identities.sort();
+        identities
+    }
+
+    /// The next epoch : the members which do not leave, and the joining ones which are not members yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `joining` - The KeyCards of the joining Nodes.
+    /// * `leaving` - The Identities of the leaving members.
+    ///
+    pub fn next(&self, joining: Vec<KeyCard>, leaving: &[Identity]) -> Epoch {
+        let mut members: Vec<KeyCard> = self
+            .members
+            .iter()
+            .filter(|m| !leaving.contains(&m.identity()))
+            .cloned()
+            .collect();
+        for keycard in joining.into_iter() {
+            let identity = keycard.identity();
+            if !leaving.contains(&identity) && !members.iter().any(|m| m.identity() == identity) {
+                members.push(keycard);
+            }
+        }
+        Epoch {
+            epoch: self.epoch + 1,
+            members,
+        }
+    }
+}
+
+/// Identities allowed to take part in the system. KeyCards of any other Identity found in a shard of
+/// the Rendez-Vous server are rejected, so that a process reaching the server cannot join the samples.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Allowlist {
+    pub identities: HashSet<Identity>,
+}
+
+impl Allowlist {
+    /// Read an allowlist file : a JSON list of Identities.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the allowlist file.
+    ///
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("read {} : {}", path, e))?;
+        let identities: Vec<Identity> =
+            serde_json::from_str(&content).map_err(|e| format!("parse {} : {}", path, e))?;
+        Ok(Allowlist {
+            identities: identities.into_iter().collect(),
+        })
+    }
+
+    /// Write an allowlist file with the given Identities.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the allowlist file.
+    /// * `identities` - The allowed Identities.
+    ///
+    pub fn store(path: &str, identities: &[Identity]) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(identities).unwrap();
+        fs::write(path, content).map_err(|e| format!("write {} : {}", path, e))
+    }
+
+    /// Keep the allowed KeyCards, and log every rejected one.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the running Node, used for debug purpose.
+    /// * `keycards` - The KeyCards to filter.
+    ///
+    pub fn filter(&self, id: usize, keycards: Vec<KeyCard>) -> Vec<KeyCard> {
+        keycards
+            .into_iter()
+            .filter(|keycard| {
+                let allowed = self.identities.contains(&keycard.identity());
+                if !allowed {
+                    println!(
+                        "ERROR : <{}> rejected unauthorized KeyCard {:?}",
+                        id,
+                        keycard.identity()
+                    );
+                }
+                allowed
+            })
+            .collect()
+    }
+}
+
+/// Fetch the KeyCards of the system from the Rendez-Vous server again, and add the ones which are not
+/// known yet. With an allowlist, the KeyCards of Identities which are not allowed are rejected.
+///
+/// # Arguments
+///
+/// * `id` - The id of the running Node, used for debug purpose.
+/// * `addr` - The adresse of the Rendez-Vous server.
+/// * `port` - The port of the registry of the Rendez-Vous server.
+/// * `shard` - The shard of the Broadcast group of the Node.
+/// * `own` - The Identity of the running Node, which is never added.
+/// * `keycards` - The Atomic Reference Counter to the known KeyCards to update.
+/// * `allowlist` - The Identities allowed in the system, if restricted.
+///
+pub async fn refresh_keycards(
+    id: usize,
+    addr: String,
+    port: u16,
+    shard: u32,
+    own: Identity,
+    keycards: Arc<Mutex<HashMap<Identity, KeyCard>>>,
+    allowlist: Option<Allowlist>,
+) {
+    match registry::get_shard(&addr, port, shard).await {
+        Ok(shard) => {
+            let shard = match &allowlist {
+                Some(allowlist) => allowlist.filter(id, shard),
+                None => shard,
+            };
+            let mut locked_keycards = keycards.lock().await;
+            for keycard in shard.into_iter() {
+                if keycard.identity() != own {
+                    locked_keycards.entry(keycard.identity()).or_insert(keycard);
+                }
+            }
+            my_print!(format!(
+                "{} refreshed membership : {} members",
+                id,
+                locked_keycards.len()
+            ));
+            drop(locked_keycards);
+        }
+        Err(e) => {
+            println!("ERROR : <{}> membership refresh : {}", id, e);
+        }
+    }
+}
@@ -1,17 +1,128 @@
+use crate::message_headers::Payload;
 use serde::{Deserialize, Serialize};
 use talk::crypto::primitives::sign::Signature as SignSignature;
+use talk::crypto::{Identity, KeyCard, KeyChain};
 
+/// Identifier of a Broadcast instance : the Node which broadcasts and the sequence number it chose.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Instance {
+    pub source: Identity,
+    pub sequence: u64,
+}
+
+/// Message of the protocols. Its topic tells which of the independent broadcast groups of the Nodes it
+/// belongs to, topic 0 being the default one. Its epoch tells under which membership it was sent. The
+/// Messages of an SBR instance carry the signature of its source, kept as they are relayed. The
+/// subscriptions carry the incarnation of the Node which sent them, 0 for a first start.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Message {
     pub message_type: u32,
+    pub instance: Option<Instance>,
     pub content: String,
+    #[serde(default)]
+    pub topic: u32,
+    #[serde(default)]
+    pub epoch: u64,
+    #[serde(default)]
+    pub incarnation: u64,
+    #[serde(default)]
+    pub source_signature: Option<SignSignature>,
 }
 
 impl Message {
     pub fn new(message_type: u32, content: String) -> Self {
         Message {
             message_type,
+            instance: None,
             content,
+            topic: 0,
+            epoch: 0,
+            incarnation: 0,
+            source_signature: None,
+        }
+    }
+
+    pub fn with_instance(message_type: u32, instance: Instance, content: String) -> Self {
+        Message {
+            message_type,
+            instance: Some(instance),
+            content,
+            topic: 0,
+            epoch: 0,
+            incarnation: 0,
+            source_signature: None,
+        }
+    }
+
+    /// The Message, in the given topic.
+    pub fn in_topic(mut self, topic: u32) -> Self {
+        self.topic = topic;
+        self
+    }
+
+    /// The Message, in the given epoch.
+    pub fn in_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// The Message, sent by the given incarnation of its Node.
+    pub fn in_incarnation(mut self, incarnation: u64) -> Self {
+        self.incarnation = incarnation;
+        self
+    }
+
+    /// Copy of the Message with another type, keeping its instance, content, topic, epoch, incarnation
+    /// and the signature of its source.
+    pub fn with_type(&self, message_type: u32) -> Self {
+        Message {
+            message_type,
+            instance: self.instance,
+            content: self.content.clone(),
+            topic: self.topic,
+            epoch: self.epoch,
+            incarnation: self.incarnation,
+            source_signature: self.source_signature,
+        }
+    }
+
+    /// What the source of the instance of the Message signs.
+    fn payload(&self) -> Option<Payload> {
+        Some(Payload {
+            instance: self.instance?,
+            topic: self.topic,
+            epoch: self.epoch,
+            content: self.content.clone(),
+        })
+    }
+
+    /// The Message, signed by the source of its instance, so that the Nodes which relay it cannot
+    /// change its content, topic or epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `keychain` - The KeyChain of the source.
+    ///
+    pub fn signed_by_source(mut self, keychain: &KeyChain) -> Self {
+        self.source_signature = self
+            .payload()
+            .map(|payload| keychain.sign(&payload).unwrap());
+        self
+    }
+
+    /// Check that the Message carries the signature of the source of its instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The KeyCard of the source of the instance.
+    ///
+    pub fn verify_source(&self, source: &KeyCard) -> bool {
+        match (self.payload(), &self.source_signature) {
+            (Some(payload), Some(signature)) => {
+                source.identity() == payload.instance.source
+                    && signature.verify(source, &payload).is_ok()
+            }
+            _ => false,
         }
     }
 
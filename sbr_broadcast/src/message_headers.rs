@@ -1,4 +1,5 @@
-use crate::message::Message;
+use crate::message::{Instance, Message};
+use crate::transfer::Transfer;
 use serde::{Deserialize, Serialize};
 use talk::crypto::Statement;
 
@@ -10,9 +11,14 @@ pub enum Header {
     GossipSubscription,
     EchoSubscription,
     ReadySubscription,
-    InitGossip,
-    InitEcho,
-    InitReady,
+    Trigger,
+    BrachaSend,
+    BrachaEcho,
+    BrachaReady,
+    NewEpoch,
+    PullSummary,
+    Payload,
+    Transfer,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -34,13 +40,31 @@ pub struct EchoSubscription(pub Message);
 pub struct ReadySubscription(pub Message);
 
 #[derive(Serialize, Deserialize)]
-pub struct InitGossip(pub Message);
+pub struct Trigger(pub Message);
 
 #[derive(Serialize, Deserialize)]
-pub struct InitEcho(pub Message);
+pub struct BrachaSend(pub Message);
 
 #[derive(Serialize, Deserialize)]
-pub struct InitReady(pub Message);
+pub struct BrachaEcho(pub Message);
+
+#[derive(Serialize, Deserialize)]
+pub struct BrachaReady(pub Message);
+
+#[derive(Serialize, Deserialize)]
+pub struct NewEpoch(pub Message);
+
+#[derive(Serialize, Deserialize)]
+pub struct PullSummary(pub Message);
+
+/// What the source of an instance signs : the Nodes relaying its Messages cannot change them.
+#[derive(Serialize, Deserialize)]
+pub struct Payload {
+    pub instance: Instance,
+    pub topic: u32,
+    pub epoch: u64,
+    pub content: String,
+}
 
 impl Statement for Gossip {
     type Header = Header;
@@ -72,17 +96,42 @@ impl Statement for ReadySubscription {
     const HEADER: Header = Header::ReadySubscription;
 }
 
-impl Statement for InitGossip {
+impl Statement for Trigger {
+    type Header = Header;
+    const HEADER: Header = Header::Trigger;
+}
+
+impl Statement for BrachaSend {
+    type Header = Header;
+    const HEADER: Header = Header::BrachaSend;
+}
+
+impl Statement for BrachaEcho {
+    type Header = Header;
+    const HEADER: Header = Header::BrachaEcho;
+}
+
+impl Statement for BrachaReady {
+    type Header = Header;
+    const HEADER: Header = Header::BrachaReady;
+}
+
+impl Statement for NewEpoch {
+    type Header = Header;
+    const HEADER: Header = Header::NewEpoch;
+}
+
+impl Statement for PullSummary {
     type Header = Header;
-    const HEADER: Header = Header::InitGossip;
+    const HEADER: Header = Header::PullSummary;
 }
 
-impl Statement for InitEcho {
+impl Statement for Payload {
     type Header = Header;
-    const HEADER: Header = Header::InitEcho;
+    const HEADER: Header = Header::Payload;
 }
 
-impl Statement for InitReady {
+impl Statement for Transfer {
     type Header = Header;
-    const HEADER: Header = Header::InitReady;
+    const HEADER: Header = Header::Transfer;
 }
@@ -1,13 +1,12 @@
+use crate::layer::BroadcastLayer;
 use crate::message::{Message, SignedMessage};
 use crate::message_headers::{Gossip, GossipSubscription};
-use crate::sieve;
+use crate::push::Pusher;
+use async_trait::async_trait;
 use rand::prelude::*;
 use std::sync::Arc;
-use std::time::Duration;
-use talk::broadcast::{BestEffort, BestEffortSettings};
 use talk::crypto::{Identity, KeyCard, KeyChain};
-use talk::time::sleep_schedules::Constant;
-use talk::unicast::{Acknowledgement, PushSettings, Sender};
+use talk::unicast::Sender;
 use tokio::sync::Mutex;
 
 /// Initialises the Gossip set used in the Murmur algorithm. Randomly chooses peers.
@@ -38,112 +37,101 @@ pub async fn init(g: usize, system: Vec<KeyCard>, gossip_peers: &Arc<Mutex<Vec<I
 ///
 /// * `keychain` - KeyChain used to sign the Message.
 /// * `node_sender` - The Node's Sender used to send Messages.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `gossip_peers` - The Gossip peers.
+/// * `topic` - The topic the peers are sampled for.
+/// * `epoch` - The epoch the peers are sampled in.
+/// * `incarnation` - The number of times the Node restarted, which lets the peers accept the
+///   subscriptions of a restarted Node.
 ///
 pub async fn gossip_subscribe(
     keychain: KeyChain,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     gossip_peers: Vec<Identity>,
+    topic: u32,
+    epoch: u64,
+    incarnation: u64,
 ) {
-    let push_settings = PushSettings {
-        stop_condition: Acknowledgement::Strong,
-        retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-    };
-    let settings: BestEffortSettings = BestEffortSettings { push_settings };
-    let msg = Message::new(3, String::from("GossipSubscription"));
+    let msg = Message::new(3, String::from("GossipSubscription"))
+        .in_topic(topic)
+        .in_epoch(epoch)
+        .in_incarnation(incarnation);
     let signature = keychain.sign(&GossipSubscription(msg.clone())).unwrap();
     let signed_msg = SignedMessage::new(msg, signature);
-    let best_effort = BestEffort::new(
-        node_sender.clone(),
-        gossip_peers.clone(),
-        signed_msg.clone(),
-        settings,
-    );
-    best_effort.complete().await;
+    pusher
+        .best_effort("gossip_subscribe", node_sender, gossip_peers, signed_msg)
+        .await;
     my_print!("Finished Murmur Subscriptions");
 }
 
-/// Deliver a Gossip type Message. Dispatch a verified Message to its Gossip peers.
-///
-/// # Arguments
-///
-/// * `keychain` - KeyChain used to sign the Message.
-/// * `signed_msg` - The signed Message to deliver.
-/// * `node_sender` - The Node's Sender used to send Messages.
-/// * `gossip_peers` - The Gossip peers.
-/// * `delivered_gossip` - The Atomic Reference Counter to the status of the delivered Gossip Message.
-/// * `echo` - The Atomic Reference Counter to the status of the Echo Message (used by Sieve).
-/// * `echo_peers` - The Echo peers (used by Sieve).
-///
-pub async fn deliver_gossip(
-    keychain: KeyChain,
-    signed_msg: SignedMessage,
-    node_sender: Sender<SignedMessage>,
-    gossip_peers: Vec<Identity>,
-    delivered_gossip: Arc<Mutex<Option<Message>>>,
-    echo: Arc<Mutex<Option<Message>>>,
-    echo_peers: Vec<Identity>,
-) {
-    dispatch(
-        keychain,
-        signed_msg,
-        node_sender,
-        gossip_peers,
-        delivered_gossip,
-        echo,
-        echo_peers,
-    )
-    .await;
+/// Murmur, the Probabilistic Broadcast layer of an instance : a Message is dispatched once to the Gossip
+/// peers, and then delivered to the layer above.
+pub struct MurmurLayer {
+    pub keychain: KeyChain,
+    pub node_sender: Sender<SignedMessage>,
+    pub pusher: Pusher,
+    pub gossip_peers: Vec<Identity>,
+    pub delivered_gossip: Arc<Mutex<Option<Message>>>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+#[async_trait]
+impl BroadcastLayer for MurmurLayer {
+    /// Dispatch a Gossip received, or broadcast by the source of the instance.
+    async fn deliver(&self, message: Message) {
+        dispatch(
+            self.keychain.clone(),
+            message,
+            self.node_sender.clone(),
+            self.pusher.clone(),
+            self.gossip_peers.clone(),
+            self.delivered_gossip.clone(),
+            self.upper.clone(),
+        )
+        .await;
+    }
 }
 
 /// Dispatch a Message to the Gossip peers. If no Gossip Message has yet been delivered, send a Gossip
-/// Message to the given peers, and then Probabilistic Broadcast deliver the Message.
+/// Message to the given peers, and then Probabilistic Broadcast deliver the Message to the layer above.
+/// The delivered Gossip is checked and set under one lock, so that concurrent Gossips dispatch once.
 ///
 /// # Arguments
 ///
 /// * `keychain` - KeyChain used to sign the Message.
-/// * `signed_msg` - The signed Message to deliver.
+/// * `message` - The Message to deliver.
 /// * `node_sender` - The Node's Sender used to send Messages.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `peers` - The peers to which the Gossip will be spread.
 /// * `delivered_gossip` - The Atomic Reference Counter to the status of the delivered Gossip Message.
-/// * `echo` - The Atomic Reference Counter to the status of the Echo Message (used by Sieve).
-/// * `echo_peers` - The Echo peers (used by Sieve).
+/// * `upper` - The layer above Murmur, Sieve or the application.
 ///
 pub async fn dispatch(
     keychain: KeyChain,
-    signed_msg: SignedMessage,
+    message: Message,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     peers: Vec<Identity>,
     delivered_gossip: Arc<Mutex<Option<Message>>>,
-    echo: Arc<Mutex<Option<Message>>>,
-    echo_peers: Vec<Identity>,
+    upper: Arc<dyn BroadcastLayer>,
 ) {
-    if delivered_gossip.lock().await.is_none() {
-        let mut locked_delivered = delivered_gossip.lock().await;
-        *locked_delivered = Some(signed_msg.clone().get_message());
-        drop(locked_delivered);
-        let push_settings = PushSettings {
-            stop_condition: Acknowledgement::Strong,
-            retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-        };
-        let signature = keychain
-            .sign(&Gossip(signed_msg.clone().get_message()))
-            .unwrap();
-        let signed_broadcast = SignedMessage::new(signed_msg.clone().get_message(), signature);
-        let settings: BestEffortSettings = BestEffortSettings { push_settings };
-        let best_effort = BestEffort::new(
-            node_sender.clone(),
-            peers,
-            signed_broadcast.clone(),
-            settings,
-        );
-        best_effort.complete().await;
-        sieve::deliver(keychain, signed_msg, node_sender, echo, echo_peers).await;
+    let mut locked_delivered = delivered_gossip.lock().await;
+    if locked_delivered.is_some() {
+        return;
     }
+    *locked_delivered = Some(message.clone());
+    drop(locked_delivered);
+    let signature = keychain.sign(&Gossip(message.clone())).unwrap();
+    let signed_broadcast = SignedMessage::new(message.clone(), signature);
+    pusher
+        .best_effort("gossip", node_sender, peers, signed_broadcast)
+        .await;
+    upper.deliver(message).await;
 }
 
-/// Deliver a GossipSubscription type Message. If a Gossip Message has already been delivered, send it to
-/// the subscribing peer. Add the peer to the Gossip peers.
+/// Deliver a GossipSubscription type Message. Send the Gossip Messages already delivered, one per
+/// instance, to the subscribing peer. Add the peer to the Gossip peers.
 ///
 /// # Arguments
 ///
@@ -151,16 +139,16 @@ pub async fn dispatch(
 /// * `node_sender` - The Node's Sender used to send Messages.
 /// * `from` - The Identity of the Node subscribing.
 /// * `gossip_peers` - The Atomic Reference Counter to the Gossip peers.
-/// * `delivered_gossip` - The status of the delivered Gossip Message.
+/// * `delivered_gossips` - The Gossip Messages delivered so far.
 ///
 pub async fn gossip_subscription(
     keychain: KeyChain,
     node_sender: Sender<SignedMessage>,
     from: Identity,
     gossip_peers: Arc<Mutex<Vec<Identity>>>,
-    delivered_gossip: Option<Message>,
+    delivered_gossips: Vec<Message>,
 ) {
-    if let Some(delivered_msg) = delivered_gossip {
+    for delivered_msg in delivered_gossips.into_iter() {
         let signature = keychain.sign(&Gossip(delivered_msg.clone())).unwrap();
         let signed_msg = SignedMessage::new(delivered_msg, signature);
         let r = node_sender.send(from, signed_msg.clone()).await;
@@ -172,6 +160,8 @@ pub async fn gossip_subscription(
         }
     }
     let mut locked_gossip_peers = gossip_peers.lock().await;
-    locked_gossip_peers.push(from);
+    if !locked_gossip_peers.contains(&from) {
+        locked_gossip_peers.push(from);
+    }
     drop(locked_gossip_peers);
 }
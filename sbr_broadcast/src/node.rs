@@ -1,19 +1,50 @@
-use crate::contagion::{deliver_ready, ready_subscribe, ready_subscription};
-use crate::message::{Message, SignedMessage};
+use crate::bracha::{BrachaLayer, Quorums};
+use crate::causal::{CausalBuffer, CausalLayer};
+use crate::config::{FifoSettings, NodeSettings, Protocol, PullSettings, SampleSizes};
+use crate::contagion::{self, ready_subscribe, ready_subscription, ContagionLayer};
+use crate::control::{NodeHandle, NodeView};
+use crate::evidence::{check_equivocation, Evidence};
+use crate::exclusion::{
+    is_verified, remove_peer, replace_in_contagion_sample, replace_in_sieve_sample, replace_weight,
+    report, resample, ExclusionList, Misbehaviour,
+};
+use crate::fifo::{FifoBuffer, FifoLayer};
+use crate::instance::{InstanceState, SourceInstances};
+use crate::journal::{journal_file, record, Journal, JournalLayer, Record, Recovered, Samples};
+use crate::layer::{Application, BroadcastLayer, Layer};
+use crate::limits::{CapCounters, Limits};
+use crate::membership::{refresh_keycards, Allowlist, Epoch, UnknownPolicy};
+use crate::message::{Instance, Message, SignedMessage};
 use crate::message_headers::{
-    Echo, EchoSubscription, Gossip, GossipSubscription, Ready, ReadySubscription,
+    BrachaEcho, BrachaReady, BrachaSend, Echo, EchoSubscription, Gossip, GossipSubscription,
+    NewEpoch, PullSummary, Ready, ReadySubscription, Trigger,
 };
-use crate::murmur::{deliver_gossip, dispatch, gossip_subscribe, gossip_subscription};
-use crate::sieve::{deliver_echo, echo_subscribe, echo_subscription};
-use std::collections::HashMap;
+use crate::murmur::{self, gossip_subscribe, gossip_subscription, MurmurLayer};
+use crate::pull::{missing, pull_reply, Summary};
+use crate::push::Pusher;
+use crate::sieve::{self, echo_subscribe, echo_subscription, SieveLayer};
+use crate::store::{Store, StoreLayer};
+use crate::tasks::Tasks;
+use crate::transfer::{Ledger, SignedTransfer, Transfer, TransferLayer, TRANSFER_TOPIC};
+use futures::future::BoxFuture;
+use rand::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 use talk::crypto::{Identity, KeyCard, KeyChain};
 use talk::unicast::{Receiver, Sender};
 use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
+
+/// Time between two checks for the past epochs whose instances have finished.
+const RETIRE_PERIOD: Duration = Duration::from_secs(1);
 
+#[derive(Clone)]
 pub struct Node {
     kc: KeyChain,
-    keycards: HashMap<Identity, KeyCard>,
+    keycards: Arc<Mutex<HashMap<Identity, KeyCard>>>,
     pub id: usize,
     pub gossip_peers: Arc<Mutex<Vec<Identity>>>,
     pub echo_subscribers: Arc<Mutex<Vec<Identity>>>,
@@ -21,31 +52,109 @@ pub struct Node {
     echo_threshold: usize,
     ready_threshold: usize,
     delivery_threshold: usize,
-    pub echo: Arc<Mutex<Option<Message>>>,
+    sizes: SampleSizes,
+    pull: PullSettings,
+    /// When the Summary of every peer was last answered.
+    pulls: Arc<Mutex<HashMap<Identity, Instant>>>,
+    pub pusher: Pusher,
     pub echo_replies: Arc<Mutex<HashMap<Identity, Option<Message>>>>,
-    pub duplicate_echo: HashMap<Identity, usize>,
+    pub duplicate_echo: Arc<Mutex<HashMap<Identity, usize>>>,
     pub ready_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
-    pub duplicate_ready: HashMap<Identity, usize>,
-    ready_messages: Arc<Mutex<Vec<Message>>>,
+    pub duplicate_ready: Arc<Mutex<HashMap<Identity, usize>>>,
     pub delivery_replies: Arc<Mutex<HashMap<Identity, Vec<Message>>>>,
-    pub duplicate_delivery: HashMap<Identity, usize>,
-    pub delivered_gossip: Arc<Mutex<Option<Message>>>,
-    delivered_echo: Arc<Mutex<Option<Message>>>,
-    delivered_msg: Arc<Mutex<Option<Message>>>,
+    pub duplicate_delivery: Arc<Mutex<HashMap<Identity, usize>>>,
+    pub instances: Arc<Mutex<HashMap<Instance, InstanceState>>>,
+    /// The first signed Echo of every peer, per open instance.
+    first_signed: Arc<Mutex<HashMap<Instance, HashMap<(Identity, u32), SignedMessage>>>>,
+    /// The instances kept for every source, shared by every epoch of the topic.
+    sources: Arc<Mutex<SourceInstances>>,
+    pub evidence: Arc<Mutex<Vec<Evidence>>>,
+    subscriptions: Arc<Mutex<HashMap<(Identity, u32), (u64, usize)>>>,
+    faults: Arc<Mutex<HashMap<Identity, usize>>>,
+    fault_threshold: usize,
+    exclusion_file: String,
+    /// Held while the exclusion list is written.
+    exclusion_writes: Arc<Mutex<()>>,
+    pub exclusions: Arc<Mutex<ExclusionList>>,
+    limits: Limits,
+    pub cap_counters: Arc<Mutex<CapCounters>>,
+    rendezvous: (String, u16, u32),
+    allowlist: Option<Allowlist>,
+    unknown_policy: UnknownPolicy,
+    pub unknown_senders: Arc<Mutex<HashMap<Identity, usize>>>,
+    unknown_since_refresh: Arc<Mutex<usize>>,
+    operator: KeyCard,
+    pub unauthorized_control: Arc<Mutex<usize>>,
+    tasks: Tasks,
+    protocol: Protocol,
+    top: Layer,
+    quorums: Quorums,
+    pub fifo: Option<Arc<Mutex<FifoBuffer>>>,
+    pub causal: Option<Arc<Mutex<CausalBuffer>>>,
+    pub ledger: Option<Arc<Mutex<Ledger>>>,
+    pub store: Option<Arc<Mutex<Store>>>,
+    pub received: Arc<Mutex<usize>>,
+    fifo_settings: Option<FifoSettings>,
+    causal_capacity: Option<usize>,
+    pub topic: u32,
+    pub epoch: u64,
+    pub subscribed: Arc<AtomicBool>,
+    state_dir: Option<String>,
+    pub journal: Option<Arc<Journal>>,
+    pub incarnation: u64,
+    /// How long an undelivered instance keeps the Node of a past epoch running.
+    epoch_timeout: Duration,
 }
 
 impl Node {
+    /// Create the Node of topic 0, in epoch 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `kc` - The KeyChain of the Node.
+    /// * `keycards` - The KeyCards of the other members.
+    /// * `id` - The ID of the Node.
+    /// * `exclusion_file` - The file in which the Node persists its exclusion list.
+    /// * `exclusions` - The exclusions loaded when the Node starts.
+    /// * `operator` - The operator KeyCard, used to verify the control Messages.
+    /// * `settings` - The settings of the Node, taken from the configuration.
+    ///
     pub fn new(
         kc: KeyChain,
         keycards: HashMap<Identity, KeyCard>,
         id: usize,
-        echo_threshold: usize,
-        ready_threshold: usize,
-        delivery_threshold: usize,
+        exclusion_file: String,
+        exclusions: ExclusionList,
+        operator: KeyCard,
+        settings: NodeSettings,
     ) -> Self {
+        let NodeSettings {
+            echo_threshold,
+            ready_threshold,
+            delivery_threshold,
+            sizes,
+            pull,
+            retry,
+            fault_threshold,
+            limits,
+            rendezvous,
+            allowlist,
+            unknown_policy,
+            protocol,
+            top,
+            fifo: fifo_settings,
+            causal: causal_capacity,
+            initial_balance,
+            store,
+            state_dir,
+            epoch_timeout,
+        } = settings;
+        // The Node is not part of its own KeyCards.
+        let quorums = Quorums::new(keycards.len() + 1);
+        let account = format!("{:?}", kc.keycard().identity());
         Node {
             kc,
-            keycards,
+            keycards: Arc::new(Mutex::new(keycards)),
             id,
             gossip_peers: Arc::new(Mutex::new(Vec::new())),
             echo_subscribers: Arc::new(Mutex::new(Vec::new())),
@@ -53,238 +162,2419 @@ impl Node {
             echo_threshold,
             ready_threshold,
             delivery_threshold,
-            echo: Arc::new(Mutex::new(None)),
+            sizes,
+            pull,
+            pulls: Arc::new(Mutex::new(HashMap::new())),
+            pusher: Pusher::new(retry),
+            echo_replies: Arc::new(Mutex::new(HashMap::new())),
+            duplicate_echo: Arc::new(Mutex::new(HashMap::new())),
+            ready_replies: Arc::new(Mutex::new(HashMap::new())),
+            duplicate_ready: Arc::new(Mutex::new(HashMap::new())),
+            delivery_replies: Arc::new(Mutex::new(HashMap::new())),
+            duplicate_delivery: Arc::new(Mutex::new(HashMap::new())),
+            instances: Arc::new(Mutex::new(HashMap::new())),
+            first_signed: Arc::new(Mutex::new(HashMap::new())),
+            sources: Arc::new(Mutex::new(SourceInstances::default())),
+            evidence: Arc::new(Mutex::new(Vec::new())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            faults: Arc::new(Mutex::new(HashMap::new())),
+            fault_threshold,
+            exclusion_file,
+            exclusion_writes: Arc::new(Mutex::new(())),
+            exclusions: Arc::new(Mutex::new(exclusions)),
+            limits,
+            cap_counters: Arc::new(Mutex::new(CapCounters::default())),
+            rendezvous,
+            allowlist,
+            unknown_policy,
+            unknown_senders: Arc::new(Mutex::new(HashMap::new())),
+            unknown_since_refresh: Arc::new(Mutex::new(0)),
+            operator,
+            unauthorized_control: Arc::new(Mutex::new(0)),
+            tasks: Tasks::default(),
+            protocol,
+            top,
+            quorums,
+            fifo: fifo_settings.map(|settings| Arc::new(Mutex::new(FifoBuffer::new(settings)))),
+            causal: causal_capacity
+                .map(|capacity| Arc::new(Mutex::new(CausalBuffer::new(capacity)))),
+            ledger: initial_balance
+                .map(|initial| Arc::new(Mutex::new(Ledger::new(account, initial)))),
+            store: if store {
+                Some(Arc::new(Mutex::new(Store::new())))
+            } else {
+                None
+            },
+            received: Arc::new(Mutex::new(0)),
+            fifo_settings,
+            causal_capacity,
+            topic: 0,
+            epoch: 0,
+            subscribed: Arc::new(AtomicBool::new(true)),
+            journal: state_dir
+                .as_ref()
+                .map(|dir| Arc::new(Journal::new(journal_file(dir, id, 0)))),
+            state_dir,
+            incarnation: 0,
+            epoch_timeout: Duration::from_millis(epoch_timeout),
+        }
+    }
+
+    /// The Node of another topic, on the same transport and KeyChain. Its samples, instances and
+    /// delivery stream are its own, the membership, exclusions, fault counters and accounts are shared
+    /// with every topic of the Node.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic of the new Node.
+    ///
+    pub fn in_topic(&self, topic: u32) -> Node {
+        Node {
+            gossip_peers: Arc::new(Mutex::new(Vec::new())),
+            echo_subscribers: Arc::new(Mutex::new(Vec::new())),
+            ready_subscribers: Arc::new(Mutex::new(Vec::new())),
+            pulls: Arc::new(Mutex::new(HashMap::new())),
             echo_replies: Arc::new(Mutex::new(HashMap::new())),
-            duplicate_echo: HashMap::new(),
+            duplicate_echo: Arc::new(Mutex::new(HashMap::new())),
             ready_replies: Arc::new(Mutex::new(HashMap::new())),
-            duplicate_ready: HashMap::new(),
-            ready_messages: Arc::new(Mutex::new(Vec::new())),
+            duplicate_ready: Arc::new(Mutex::new(HashMap::new())),
             delivery_replies: Arc::new(Mutex::new(HashMap::new())),
-            duplicate_delivery: HashMap::new(),
-            delivered_gossip: Arc::new(Mutex::new(None)),
-            delivered_echo: Arc::new(Mutex::new(None)),
-            delivered_msg: Arc::new(Mutex::new(None)),
+            duplicate_delivery: Arc::new(Mutex::new(HashMap::new())),
+            instances: Arc::new(Mutex::new(HashMap::new())),
+            first_signed: Arc::new(Mutex::new(HashMap::new())),
+            sources: Arc::new(Mutex::new(SourceInstances::default())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            fifo: self
+                .fifo_settings
+                .map(|settings| Arc::new(Mutex::new(FifoBuffer::new(settings)))),
+            causal: self
+                .causal_capacity
+                .map(|capacity| Arc::new(Mutex::new(CausalBuffer::new(capacity)))),
+            store: self
+                .store
+                .as_ref()
+                .map(|_| Arc::new(Mutex::new(Store::new()))),
+            topic,
+            subscribed: Arc::new(AtomicBool::new(true)),
+            journal: self
+                .state_dir
+                .as_ref()
+                .map(|dir| Arc::new(Journal::new(journal_file(dir, self.id, topic)))),
+            incarnation: 0,
+            ..self.clone()
+        }
+    }
+
+    /// The Node of a new epoch of the topic, with the given members. Its samples, subscribers and quorums
+    /// are its own, so that the instances started in the previous epochs finish with theirs, while the
+    /// instances, the delivery stream and the journal are shared by every epoch of the topic. The new
+    /// samples are drawn among the members, and subscribed to.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to subscribe to the new samples.
+    /// * `epoch` - The new epoch and its members.
+    /// * `keycards` - The KeyCards of the other members, shared by every topic of the Node.
+    ///
+    pub async fn enter_epoch(
+        &self,
+        sender: &Sender<SignedMessage>,
+        epoch: &Epoch,
+        keycards: Arc<Mutex<HashMap<Identity, KeyCard>>>,
+    ) -> Node {
+        let members = keycards.lock().await.len() + 1;
+        let mut node = Node {
+            keycards,
+            gossip_peers: Arc::new(Mutex::new(Vec::new())),
+            echo_subscribers: Arc::new(Mutex::new(Vec::new())),
+            ready_subscribers: Arc::new(Mutex::new(Vec::new())),
+            echo_replies: Arc::new(Mutex::new(HashMap::new())),
+            duplicate_echo: Arc::new(Mutex::new(HashMap::new())),
+            ready_replies: Arc::new(Mutex::new(HashMap::new())),
+            duplicate_ready: Arc::new(Mutex::new(HashMap::new())),
+            delivery_replies: Arc::new(Mutex::new(HashMap::new())),
+            duplicate_delivery: Arc::new(Mutex::new(HashMap::new())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            pulls: Arc::new(Mutex::new(HashMap::new())),
+            quorums: Quorums::new(members),
+            epoch: epoch.epoch,
+            ..self.clone()
+        };
+        let record_epoch = Record::Epoch {
+            epoch: epoch.clone(),
+        };
+        record(&node.journal, record_epoch).await;
+        node.draw_samples().await;
+        let subscriptions = node.subscriptions(sender).await;
+        node.spawn(subscriptions).await;
+        node
+    }
+
+    /// The members of the epoch of the Node, itself included.
+    pub async fn membership(&self) -> Epoch {
+        let mut members: Vec<KeyCard> = self.keycards.lock().await.values().cloned().collect();
+        members.push(self.kc.keycard());
+        Epoch {
+            epoch: self.epoch,
+            members,
+        }
+    }
+
+    /// Draw the samples of the Node among the members which are not excluded, for the layers up to the
+    /// configured one, and journal them. Bracha sends to every member and draws no sample.
+    pub async fn draw_samples(&mut self) {
+        if self.protocol == Protocol::Bracha {
+            return;
+        }
+        let excluded = self.exclusions.lock().await.excluded.clone();
+        let system: Vec<KeyCard> = self
+            .keycards
+            .lock()
+            .await
+            .values()
+            .filter(|keycard| !excluded.contains(&keycard.identity()))
+            .cloned()
+            .collect();
+        if system.is_empty() {
+            return;
+        }
+        murmur::init(self.sizes.gossip, system.clone(), &self.gossip_peers).await;
+        if self.top >= Layer::Sieve {
+            sieve::init(
+                self.sizes.echo,
+                system.clone(),
+                &self.echo_replies,
+                &mut *self.duplicate_echo.lock().await,
+            )
+            .await;
+        }
+        if self.top >= Layer::Contagion {
+            contagion::init(
+                self.sizes.ready,
+                self.sizes.delivery,
+                system,
+                &self.ready_replies,
+                &self.delivery_replies,
+                &mut *self.duplicate_ready.lock().await,
+                &mut *self.duplicate_delivery.lock().await,
+            )
+            .await;
+        }
+        self.journal_samples().await;
+    }
+
+    /// The subscriptions of the Node to its samples, for the layers which run, which complete once every
+    /// sampled peer acknowledged them. Bracha has no subscriptions.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to subscribe.
+    ///
+    pub async fn subscriptions(&self, sender: &Sender<SignedMessage>) -> BoxFuture<'static, ()> {
+        let (keychain, sender, pusher) = (self.kc.clone(), sender.clone(), self.pusher.clone());
+        let (protocol, top) = (self.protocol, self.top);
+        let (topic, epoch, incarnation) = (self.topic, self.epoch, self.incarnation);
+        let gossip_peers = self.gossip_peers.lock().await.clone();
+        let echo_replies = self.echo_replies.lock().await.clone();
+        let ready_replies = self.ready_replies.lock().await.clone();
+        let delivery_replies = self.delivery_replies.lock().await.clone();
+        Box::pin(async move {
+            if protocol == Protocol::Bracha {
+                return;
+            }
+            tokio::join!(
+                gossip_subscribe(
+                    keychain.clone(),
+                    sender.clone(),
+                    pusher.clone(),
+                    gossip_peers,
+                    topic,
+                    epoch,
+                    incarnation
+                ),
+                async {
+                    if top >= Layer::Sieve {
+                        echo_subscribe(
+                            keychain.clone(),
+                            sender.clone(),
+                            pusher.clone(),
+                            echo_replies,
+                            topic,
+                            epoch,
+                            incarnation,
+                        )
+                        .await;
+                    }
+                },
+                async {
+                    if top >= Layer::Contagion {
+                        ready_subscribe(
+                            keychain.clone(),
+                            sender.clone(),
+                            pusher.clone(),
+                            ready_replies,
+                            delivery_replies,
+                            topic,
+                            epoch,
+                            incarnation,
+                        )
+                        .await;
+                    }
+                },
+            );
+        })
+    }
+
+    /// Send the Summary of the instances of the epoch of the Node to random peers which are not
+    /// excluded, which answer with the Messages it missed. Bracha has no Gossip to pull.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send the Summary.
+    ///
+    pub async fn pull(&self, sender: &Sender<SignedMessage>) {
+        if self.protocol == Protocol::Bracha {
+            return;
+        }
+        let excluded = self.exclusions.lock().await.excluded.clone();
+        let mut peers: Vec<Identity> = self
+            .keycards
+            .lock()
+            .await
+            .keys()
+            .filter(|peer| !excluded.contains(peer))
+            .cloned()
+            .collect();
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(self.pull.peers);
+        let summary = Summary::of(&self.instance_entries().await).await;
+        let msg = Message::new(14, serde_json::to_string(&summary).unwrap())
+            .in_topic(self.topic)
+            .in_epoch(self.epoch);
+        let signature = self.kc.sign(&PullSummary(msg.clone())).unwrap();
+        let signed_msg = SignedMessage::new(msg, signature);
+        for peer in peers.into_iter() {
+            let (s, signed_msg) = (sender.clone(), signed_msg.clone());
+            self.spawn(async move {
+                if let Err(e) = s.send(peer, signed_msg).await {
+                    println!("ERROR : pull send : {}", e);
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Whether a peer sends its Summaries more often than the pulls are configured, in which case
+    /// they are not answered.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The Identity of the peer which sent a Summary.
+    ///
+    async fn is_pulling_early(&self, from: Identity) -> bool {
+        let now = Instant::now();
+        let mut locked_pulls = self.pulls.lock().await;
+        if let Some(last) = locked_pulls.get(&from) {
+            if now < *last + Duration::from_millis(self.pull.interval / 2) {
+                drop(locked_pulls);
+                self.cap_counters.lock().await.early_pull += 1;
+                return true;
+            }
+        }
+        locked_pulls.insert(from, now);
+        false
+    }
+
+    /// References to the state of the Node, used by the control socket once the Node is listening.
+    pub fn view(&self) -> NodeView {
+        NodeView {
+            gossip_peers: self.gossip_peers.clone(),
+            echo_subscribers: self.echo_subscribers.clone(),
+            ready_subscribers: self.ready_subscribers.clone(),
+            instances: self.instances.clone(),
+            evidence: self.evidence.clone(),
+            exclusions: self.exclusions.clone(),
+            cap_counters: self.cap_counters.clone(),
+            push_counters: self.pusher.counters.clone(),
+            unknown_senders: self.unknown_senders.clone(),
+            unauthorized_control: self.unauthorized_control.clone(),
+            received: self.received.clone(),
+            fifo: self.fifo.clone(),
+            causal: self.causal.clone(),
+            ledger: self.ledger.clone(),
+            store: self.store.clone(),
+            topic: self.topic,
+            epoch: self.epoch,
+            subscribed: self.subscribed.clone(),
+        }
+    }
+
+    /// Journal the current samples of the Node, which a restarted Node reuses instead of sampling
+    /// again.
+    pub async fn journal_samples(&self) {
+        if self.journal.is_none() {
+            return;
+        }
+        fn weighted(
+            peers: Vec<Identity>,
+            duplicates: &HashMap<Identity, usize>,
+        ) -> Vec<(Identity, usize)> {
+            peers
+                .into_iter()
+                .map(|peer| (peer, *duplicates.get(&peer).unwrap_or(&1)))
+                .collect()
+        }
+        let samples = Samples {
+            gossip: self.gossip_peers.lock().await.clone(),
+            echo: weighted(
+                self.echo_replies.lock().await.keys().cloned().collect(),
+                &*self.duplicate_echo.lock().await,
+            ),
+            ready: weighted(
+                self.ready_replies.lock().await.keys().cloned().collect(),
+                &*self.duplicate_ready.lock().await,
+            ),
+            delivery: weighted(
+                self.delivery_replies.lock().await.keys().cloned().collect(),
+                &*self.duplicate_delivery.lock().await,
+            ),
+        };
+        record(&self.journal, Record::Samples { samples }).await;
+    }
+
+    /// Recover the state journaled by the previous runs of the Node : its last epoch and its members, its
+    /// samples, its subscribers, and what it sent, echoed, readied and delivered in every instance, so that it never sends a
+    /// conflicting Echo or Ready and never delivers an instance twice. The deliveries are replayed to
+    /// rebuild the state of the application, writing only the output the application never
+    /// acknowledged, and the subscribers are sent
+    /// the Gossip, Echo and Ready Messages of the Node again. Nodes without journal recover nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send Messages.
+    ///
+    pub async fn recover(&mut self, sender: &Sender<SignedMessage>) -> Recovered {
+        let recovered = match &self.journal {
+            Some(journal) => journal.recover().await,
+            None => {
+                return Recovered::default();
+            }
+        };
+        self.incarnation = recovered.incarnation;
+        let own = self.kc.keycard().identity();
+        if let Some(epoch) = &recovered.epoch {
+            let keycards: HashMap<Identity, KeyCard> = epoch
+                .members
+                .iter()
+                .filter(|keycard| keycard.identity() != own)
+                .map(|keycard| (keycard.identity(), keycard.clone()))
+                .collect();
+            self.quorums = Quorums::new(keycards.len() + 1);
+            *self.keycards.lock().await = keycards;
+            self.epoch = epoch.epoch;
+        }
+        if let Some(samples) = &recovered.samples {
+            *self.gossip_peers.lock().await = samples.gossip.clone();
+            for (peer, weight) in samples.echo.iter() {
+                self.echo_replies.lock().await.insert(*peer, None);
+                self.duplicate_echo.lock().await.insert(*peer, *weight);
+            }
+            for (peer, weight) in samples.ready.iter() {
+                self.ready_replies.lock().await.insert(*peer, Vec::new());
+                self.duplicate_ready.lock().await.insert(*peer, *weight);
+            }
+            for (peer, weight) in samples.delivery.iter() {
+                self.delivery_replies.lock().await.insert(*peer, Vec::new());
+                self.duplicate_delivery.lock().await.insert(*peer, *weight);
+            }
+        }
+
+        // The journaled Messages keep the epoch and the source signature they were sent with.
+        let (topic, epoch) = (self.topic, self.epoch);
+        for (instance, progress) in recovered.instances.iter() {
+            let started = progress.epoch().unwrap_or(epoch);
+            let state = match self.open_instance(*instance, started).await {
+                Some(state) => state,
+                None => {
+                    continue;
+                }
+            };
+            let mut bracha = state.bracha.lock().await;
+            if let Some(message) = progress.echo.as_ref().or(progress.sent.as_ref()) {
+                *state.delivered_gossip.lock().await = Some(message.with_type(0));
+            }
+            if let Some(message) = &progress.echo {
+                *state.echo.lock().await = Some(message.with_type(0));
+                bracha.echoed = true;
+                bracha.echoes.insert(own, message.content.clone());
+            }
+            for message in progress.readies.iter() {
+                state.ready_messages.lock().await.push(message.with_type(2));
+                bracha.readied = true;
+                bracha.readies.entry(own).or_insert(message.content.clone());
+            }
+            if let Some(message) = &progress.delivered {
+                *state.delivered_msg.lock().await = Some(message.with_type(2));
+                let mut delivered_gossip = state.delivered_gossip.lock().await;
+                if self.top == Layer::Murmur && delivered_gossip.is_none() {
+                    *delivered_gossip = Some(message.with_type(0));
+                }
+                drop(delivered_gossip);
+                if self.top == Layer::Sieve {
+                    *state.delivered_echo.lock().await = Some(message.with_type(1));
+                }
+                bracha.delivered = true;
+            }
+            drop(bracha);
+        }
+
+        // The deliveries rebuild the state of the application, and write the output of the ones which
+        // were never acknowledged.
+        let acknowledged = Arc::new(recovered.acknowledged.clone());
+        let application = self.ordered_application(Some(acknowledged));
+        for instance in recovered.deliveries.iter() {
+            if let Some(message) = &recovered.instances[instance].delivered {
+                application.deliver(message.clone()).await;
+            }
+        }
+        my_print!(format!(
+            "{} recovered incarnation {} of topic {} in epoch {} : {} instances, {} deliveries, {} subscribers",
+            self.id,
+            self.incarnation,
+            topic,
+            epoch,
+            recovered.instances.len(),
+            recovered.deliveries.len(),
+            recovered.subscribers.len()
+        ));
+
+        let mut dg = Vec::new();
+        let mut ec = Vec::new();
+        let mut rm = Vec::new();
+        for state in self.instance_states().await {
+            dg.extend(state.delivered_gossip.lock().await.clone());
+            ec.extend(state.echo.lock().await.clone());
+            rm.extend(state.ready_messages.lock().await.clone());
+        }
+        for (subscription, peer) in recovered.subscribers.iter() {
+            let (s, keychain, peer) = (sender.clone(), self.kc.clone(), *peer);
+            match subscription {
+                3 => {
+                    let (gp, dg) = (self.gossip_peers.clone(), dg.clone());
+                    self.spawn(gossip_subscription(keychain, s, peer, gp, dg))
+                        .await;
+                }
+                4 => {
+                    let (ep, ec) = (self.echo_subscribers.clone(), ec.clone());
+                    let pusher = self.pusher.clone();
+                    self.spawn(echo_subscription(keychain, s, pusher, peer, ec, ep))
+                        .await;
+                }
+                _ => {
+                    let (rp, rm) = (self.ready_subscribers.clone(), rm.clone());
+                    self.spawn(ready_subscription(keychain, self.id, s, peer, rm, rp))
+                        .await;
+                }
+            }
+        }
+        recovered
+    }
+
+    /// Spawn a task of the Node. The task is cancelled if it is still running when the Node stops.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The task to spawn.
+    ///
+    async fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task).await;
+    }
+
+    /// Cancel the tasks of the Node which are still running, such as pushes waiting for an
+    /// acknowledgement. Returns the number of cancelled tasks.
+    async fn cancel_tasks(&self) -> usize {
+        self.tasks.cancel().await
+    }
+
+    /// State of the given instance, if it is open.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - The instance the state is looked up for.
+    ///
+    async fn instance(&self, instance: Instance) -> Option<InstanceState> {
+        self.instances.lock().await.get(&instance).cloned()
+    }
+
+    /// State of the given instance, opened if needed. A new instance starts in the given epoch, with
+    /// empty replies from the samples of the Node. Only the Triggers of the Node, the Gossips and Bracha
+    /// Sends of the source and the journal open instances. When the source has too many instances, its
+    /// oldest one is pruned. Returns None if the instance itself is pruned.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - The instance to open.
+    /// * `epoch` - The epoch the instance started in.
+    ///
+    async fn open_instance(&self, instance: Instance, epoch: u64) -> Option<InstanceState> {
+        let mut locked_instances = self.instances.lock().await;
+        if let Some(state) = locked_instances.get(&instance) {
+            return Some(state.clone());
+        }
+        let mut locked_sources = self.sources.lock().await;
+        if locked_sources.is_pruned(&instance) {
+            return None;
+        }
+        let pruned = locked_sources.open(instance, self.limits.instances_per_source);
+        drop(locked_sources);
+        let state = InstanceState::new(
+            epoch,
+            self.echo_replies.lock().await.keys().cloned().collect(),
+            self.ready_replies.lock().await.keys().cloned().collect(),
+            self.delivery_replies.lock().await.keys().cloned().collect(),
+        );
+        if pruned != Some(instance) {
+            locked_instances.insert(instance, state.clone());
+        }
+        if let Some(pruned) = pruned {
+            locked_instances.remove(&pruned);
+            drop(locked_instances);
+            self.first_signed.lock().await.remove(&pruned);
+            self.cap_counters.lock().await.source_cap += 1;
+            my_print!(format!(
+                "{} pruned instance {} of {:?}",
+                self.id, pruned.sequence, pruned.source
+            ));
+            if pruned == instance {
+                return None;
+            }
+        }
+        Some(state)
+    }
+
+    /// Check that a Message carries the signature of the source of its instance. Returns None if the
+    /// Message has no instance or its source is not a member.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The Message received.
+    ///
+    async fn is_signed_by_source(&self, message: &Message) -> Option<bool> {
+        let source = message.instance?.source;
+        let keycard = if source == self.kc.keycard().identity() {
+            Some(self.kc.keycard())
+        } else {
+            self.keycards.lock().await.get(&source).cloned()
+        };
+        keycard.map(|keycard| message.verify_source(&keycard))
+    }
+
+    /// State of the open instance a protocol Message belongs to. Messages without instance, Messages for
+    /// an instance which is not open, and Messages for an instance which started in another epoch, are
+    /// rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The verified signed Message received.
+    ///
+    async fn instance_of(&self, message: &SignedMessage) -> Option<InstanceState> {
+        match message.clone().get_message().instance {
+            Some(instance) => {
+                let state = self.instance(instance).await?;
+                if state.epoch != self.epoch {
+                    my_print!(format!(
+                        "{} rejected Message of type {} in epoch {} for an instance of epoch {}",
+                        self.id,
+                        message.clone().get_type(),
+                        self.epoch,
+                        state.epoch
+                    ));
+                    return None;
+                }
+                Some(state)
+            }
+            None => {
+                my_print!(format!(
+                    "{} rejected Message of type {} without instance",
+                    self.id,
+                    message.clone().get_type()
+                ));
+                None
+            }
+        }
+    }
+
+    /// Snapshot of the instances known so far in the epoch of the Node, with their states.
+    async fn instance_entries(&self) -> Vec<(Instance, InstanceState)> {
+        self.instances
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, state)| state.epoch == self.epoch)
+            .map(|(instance, state)| (*instance, state.clone()))
+            .collect()
+    }
+
+    /// Snapshot of the states of every instance known so far in the epoch of the Node.
+    async fn instance_states(&self) -> Vec<InstanceState> {
+        self.instances
+            .lock()
+            .await
+            .values()
+            .filter(|state| state.epoch == self.epoch)
+            .cloned()
+            .collect()
+    }
+
+    /// The application, top of the stack of every instance, behind the journal of the deliveries,
+    /// the replicated store, the asset transfers in the transfer topic and the FIFO and causal
+    /// orderings if enabled.
+    fn application(&self) -> Arc<dyn BroadcastLayer> {
+        let application = self.ordered_application(None);
+        match &self.journal {
+            Some(journal) => Arc::new(JournalLayer {
+                journal: journal.clone(),
+                upper: application,
+            }),
+            None => application,
+        }
+    }
+
+    /// The application behind the replicated store, the asset transfers and the FIFO and causal
+    /// orderings if enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `replayed` - The acknowledged deliveries, if the deliveries are replayed from the journal.
+    ///
+    fn ordered_application(
+        &self,
+        replayed: Option<Arc<HashSet<Instance>>>,
+    ) -> Arc<dyn BroadcastLayer> {
+        let mut application: Arc<dyn BroadcastLayer> = Arc::new(Application {
+            id: self.id,
+            instances: self.instances.clone(),
+            subscribed: self.subscribed.clone(),
+            replayed,
+            journal: self.journal.clone(),
+        });
+        if let Some(store) = &self.store {
+            application = Arc::new(StoreLayer {
+                id: self.id,
+                store: store.clone(),
+                upper: application,
+            });
+        }
+        // The accounts are shared by every topic, but only the transfer topic applies transfers.
+        match &self.ledger {
+            Some(ledger) if self.topic == TRANSFER_TOPIC => {
+                application = Arc::new(TransferLayer {
+                    id: self.id,
+                    ledger: ledger.clone(),
+                    upper: application,
+                });
+            }
+            _ => {}
+        }
+        if let Some(buffer) = &self.causal {
+            application = Arc::new(CausalLayer {
+                id: self.id,
+                buffer: buffer.clone(),
+                upper: application,
+            });
+        }
+        if let Some(buffer) = &self.fifo {
+            application = Arc::new(FifoLayer {
+                id: self.id,
+                own: self.kc.keycard(),
+                keycards: self.keycards.clone(),
+                buffer: buffer.clone(),
+                causal: self.causal.clone(),
+                upper: application,
+            });
+        }
+        application
+    }
+
+    /// The Contagion layer of an instance, delivering to the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send Messages.
+    /// * `state` - The state of the instance.
+    ///
+    async fn contagion_layer(
+        &self,
+        sender: &Sender<SignedMessage>,
+        state: &InstanceState,
+    ) -> ContagionLayer {
+        ContagionLayer {
+            keychain: self.kc.clone(),
+            id: self.id,
+            node_sender: sender.clone(),
+            pusher: self.pusher.clone(),
+            ready_subscribers: self.ready_subscribers.lock().await.clone(),
+            ready_messages: state.ready_messages.clone(),
+            ready_replies: state.ready_replies.clone(),
+            duplicate_ready: self.duplicate_ready.lock().await.clone(),
+            delivery_replies: state.delivery_replies.clone(),
+            duplicate_delivery: self.duplicate_delivery.lock().await.clone(),
+            r_thr: self.ready_threshold,
+            d_thr: self.delivery_threshold,
+            delivered: state.delivered_msg.clone(),
+            ready_signatures: state.ready_signatures.clone(),
+            certificate: state.certificate.clone(),
+            limits: self.limits,
+            cap_counters: self.cap_counters.clone(),
+            journal: self.journal.clone(),
+            tasks: self.tasks.clone(),
+            upper: self.application(),
+        }
+    }
+
+    /// The Sieve layer of an instance, delivering to Contagion if it runs, to the application otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send Messages.
+    /// * `state` - The state of the instance.
+    ///
+    async fn sieve_layer(
+        &self,
+        sender: &Sender<SignedMessage>,
+        state: &InstanceState,
+    ) -> SieveLayer {
+        let upper: Arc<dyn BroadcastLayer> = if self.top >= Layer::Contagion {
+            Arc::new(self.contagion_layer(sender, state).await)
+        } else {
+            self.application()
+        };
+        SieveLayer {
+            keychain: self.kc.clone(),
+            node_sender: sender.clone(),
+            pusher: self.pusher.clone(),
+            echo_subscribers: self.echo_subscribers.lock().await.clone(),
+            echo: state.echo.clone(),
+            echo_replies: state.echo_replies.clone(),
+            duplicate_echo: self.duplicate_echo.lock().await.clone(),
+            delivered_echo: state.delivered_echo.clone(),
+            e_thr: self.echo_threshold,
+            journal: self.journal.clone(),
+            upper,
+        }
+    }
+
+    /// The Murmur layer of an instance, delivering to Sieve if it runs, to the application otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send Messages.
+    /// * `state` - The state of the instance.
+    ///
+    async fn murmur_layer(
+        &self,
+        sender: &Sender<SignedMessage>,
+        state: &InstanceState,
+    ) -> MurmurLayer {
+        let upper: Arc<dyn BroadcastLayer> = if self.top >= Layer::Sieve {
+            Arc::new(self.sieve_layer(sender, state).await)
+        } else {
+            self.application()
+        };
+        MurmurLayer {
+            keychain: self.kc.clone(),
+            node_sender: sender.clone(),
+            pusher: self.pusher.clone(),
+            gossip_peers: self.gossip_peers.lock().await.clone(),
+            delivered_gossip: state.delivered_gossip.clone(),
+            upper,
+        }
+    }
+
+    /// The Bracha layer of an instance, which sends its Messages to every member of the system which is
+    /// not excluded, and delivers to the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send Messages.
+    /// * `state` - The state of the instance.
+    ///
+    async fn bracha_layer(
+        &self,
+        sender: &Sender<SignedMessage>,
+        state: &InstanceState,
+    ) -> BrachaLayer {
+        let excluded = self.exclusions.lock().await.excluded.clone();
+        let peers = self
+            .keycards
+            .lock()
+            .await
+            .keys()
+            .filter(|identity| !excluded.contains(identity))
+            .cloned()
+            .collect();
+        BrachaLayer {
+            keychain: self.kc.clone(),
+            node_sender: sender.clone(),
+            pusher: self.pusher.clone(),
+            peers,
+            quorums: self.quorums,
+            state: state.bracha.clone(),
+            journal: self.journal.clone(),
+            application: self.application(),
+        }
+    }
+
+    /// Check that a control Message is signed by the operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The signed control Message received.
+    ///
+    fn is_authorized(&self, message: &SignedMessage) -> bool {
+        let msg = message.clone().get_message();
+        let signature = message.clone().get_signature();
+        let correct = match msg.message_type {
+            9 => signature.verify(&self.operator, &Trigger(msg)),
+            13 => signature.verify(&self.operator, &NewEpoch(msg)),
+            _ => {
+                return false;
+            }
+        };
+        correct.is_ok()
+    }
+
+    /// Reject a Message received from an Identity which is not a member of the system. The rejection
+    /// is counted, and in epoch 0 the membership is refreshed once enough Messages have been rejected,
+    /// in case the sender joined after this Node fetched the membership. The members of the later
+    /// epochs are only set by the operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The unknown Identity.
+    /// * `msg_type` - The type of the rejected Message.
+    ///
+    async fn reject_unknown(&self, from: Identity, msg_type: u32) {
+        *self.unknown_senders.lock().await.entry(from).or_insert(0) += 1;
+        if self.unknown_policy.log {
+            my_print!(format!(
+                "{} rejected Message of type {} from unknown {:?}",
+                self.id, msg_type, from
+            ));
+        }
+        if self.unknown_policy.refresh_after == 0 || self.epoch > 0 {
+            return;
+        }
+        let mut locked_since_refresh = self.unknown_since_refresh.lock().await;
+        *locked_since_refresh += 1;
+        if *locked_since_refresh >= self.unknown_policy.refresh_after {
+            *locked_since_refresh = 0;
+            let (addr, port, shard) = self.rendezvous.clone();
+            self.spawn(refresh_keycards(
+                self.id,
+                addr,
+                port,
+                shard,
+                self.kc.keycard().identity(),
+                self.keycards.clone(),
+                self.allowlist.clone(),
+            ))
+            .await;
+        }
+    }
+
+    /// Report a misbehaviour of a peer. If the peer has to be excluded, persist the exclusion and
+    /// replace the peer in every sample and set of subscribers it belongs to.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to subscribe to the replacing peers.
+    /// * `culprit` - The Identity of the misbehaving peer.
+    /// * `misbehaviour` - The kind of misbehaviour.
+    ///
+    async fn punish(
+        &self,
+        sender: &Sender<SignedMessage>,
+        culprit: Identity,
+        misbehaviour: Misbehaviour,
+    ) {
+        // Only Evidence which anyone holding the KeyCard of the culprit can check excludes it at once.
+        let evidence = match misbehaviour {
+            Misbehaviour::Equivocation => {
+                let keycards = self.keycards.lock().await.clone();
+                let locked_evidence = self.evidence.lock().await;
+                locked_evidence
+                    .iter()
+                    .find(|e| e.culprit == culprit && is_verified(e, &keycards))
+                    .cloned()
+            }
+            _ => None,
+        };
+        let exclude = report(
+            self.id,
+            culprit,
+            misbehaviour,
+            evidence.is_some(),
+            self.faults.clone(),
+            self.fault_threshold,
+        )
+        .await;
+        if !exclude {
+            return;
+        }
+        // Writes are serialized, and each one takes the list once the previous one is written, so that
+        // an older list never overwrites a newer one.
+        let locked_writes = self.exclusion_writes.lock().await;
+        let mut locked_exclusions = self.exclusions.lock().await;
+        if locked_exclusions.excluded.contains(&culprit) {
+            return;
+        }
+        locked_exclusions.excluded.push(culprit);
+        if let Some(evidence) = evidence {
+            locked_exclusions.evidence.push(evidence);
+        }
+        let list = locked_exclusions.clone();
+        drop(locked_exclusions);
+        if let Err(e) = list.store(&self.exclusion_file).await {
+            println!("ERROR : <{}> {}", self.id, e);
+        }
+        drop(locked_writes);
+        let excluded = list.excluded;
+        my_print!(format!("{} excluded {:?}", self.id, culprit));
+
+        let system: Vec<KeyCard> = self.keycards.lock().await.values().cloned().collect();
+        remove_peer(culprit, &self.echo_subscribers).await;
+        remove_peer(culprit, &self.ready_subscribers).await;
+        // Replacing peers are never already part of the sample, otherwise they would receive a second
+        // subscription from this Node and see it as flooding.
+        if remove_peer(culprit, &self.gossip_peers).await {
+            let mut avoid = excluded.clone();
+            avoid.extend(self.gossip_peers.lock().await.iter().cloned());
+            if let Some(new_peer) = resample(&system, &avoid) {
+                self.gossip_peers.lock().await.push(new_peer);
+                self.spawn(gossip_subscribe(
+                    self.kc.clone(),
+                    sender.clone(),
+                    self.pusher.clone(),
+                    vec![new_peer],
+                    self.topic,
+                    self.epoch,
+                    self.incarnation,
+                ))
+                .await;
+            }
+        }
+        // The culprit leaves the samples, and its replies are dropped from every instance of the epoch,
+        // even when no peer is left to replace it. The replacing peer takes its weight.
+        let states = self.instance_states().await;
+        let mut avoid = excluded.clone();
+        avoid.extend(self.echo_replies.lock().await.keys().cloned());
+        let new_peer = resample(&system, &avoid);
+        if replace_in_sieve_sample(culprit, new_peer, &self.echo_replies).await {
+            replace_weight(culprit, new_peer, &self.duplicate_echo).await;
+            for state in states.iter() {
+                replace_in_sieve_sample(culprit, new_peer, &state.echo_replies).await;
+            }
+            if let Some(new_peer) = new_peer {
+                self.spawn(echo_subscribe(
+                    self.kc.clone(),
+                    sender.clone(),
+                    self.pusher.clone(),
+                    HashMap::from([(new_peer, None)]),
+                    self.topic,
+                    self.epoch,
+                    self.incarnation,
+                ))
+                .await;
+            }
+        }
+        let mut avoid = excluded.clone();
+        avoid.extend(self.ready_replies.lock().await.keys().cloned());
+        avoid.extend(self.delivery_replies.lock().await.keys().cloned());
+        let new_peer = resample(&system, &avoid);
+        let in_ready = replace_in_contagion_sample(culprit, new_peer, &self.ready_replies).await;
+        let in_delivery =
+            replace_in_contagion_sample(culprit, new_peer, &self.delivery_replies).await;
+        if in_ready {
+            replace_weight(culprit, new_peer, &self.duplicate_ready).await;
+        }
+        if in_delivery {
+            replace_weight(culprit, new_peer, &self.duplicate_delivery).await;
+        }
+        for state in states.iter() {
+            replace_in_contagion_sample(culprit, new_peer, &state.ready_replies).await;
+            replace_in_contagion_sample(culprit, new_peer, &state.delivery_replies).await;
+        }
+        if let Some(new_peer) = new_peer.filter(|_| in_ready || in_delivery) {
+            self.spawn(ready_subscribe(
+                self.kc.clone(),
+                sender.clone(),
+                self.pusher.clone(),
+                HashMap::from([(new_peer, Vec::new())]),
+                HashMap::new(),
+                self.topic,
+                self.epoch,
+                self.incarnation,
+            ))
+            .await;
+        }
+        self.journal_samples().await;
+    }
+
+    /// Record a subscription of a peer. Returns true if the peer already subscribed for the same type
+    /// in the same incarnation : a restarted peer subscribes again with a higher incarnation.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The Identity of the subscribing peer.
+    /// * `subscription` - The signed subscription received.
+    ///
+    async fn is_flooding(&self, from: Identity, subscription: &SignedMessage) -> bool {
+        let msg = subscription.clone().get_message();
+        let incarnation = msg.incarnation;
+        let mut locked_subscriptions = self.subscriptions.lock().await;
+        let entry = locked_subscriptions
+            .entry((from, msg.message_type))
+            .or_insert((incarnation, 0));
+        if incarnation > entry.0 {
+            *entry = (incarnation, 0);
+        }
+        entry.1 += 1;
+        if entry.1 > 1 || incarnation < entry.0 {
+            self.cap_counters.lock().await.duplicate_subscription += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Journal a subscription of a peer. Returns false if it cannot be journaled : the subscription is
+    /// then forgotten and not acknowledged, so that the peer subscribes again.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The Identity of the subscribing peer.
+    /// * `subscription` - The type of the subscription.
+    ///
+    async fn journal_subscriber(&self, from: Identity, subscription: u32) -> bool {
+        let journaled = record(
+            &self.journal,
+            Record::Subscriber {
+                subscription,
+                peer: from,
+            },
+        )
+        .await;
+        if !journaled {
+            self.subscriptions
+                .lock()
+                .await
+                .remove(&(from, subscription));
         }
+        journaled
     }
 
+    /// Enter the epoch announced by a signal of the operator in every topic, if it is newer than the
+    /// current one. The Nodes of the previous epochs keep running, so that their instances finish. A
+    /// Node which is not a member of the new epoch leaves : it starts no new instance. Returns the
+    /// current epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - The Nodes of every topic and epoch, updated.
+    /// * `current` - The current epoch.
+    /// * `handle` - The handle through which the control socket follows the Node.
+    /// * `sender` - The Nodes' Sender used to send Messages.
+    /// * `signal` - The signed Epoch signal received.
+    ///
+    async fn next_epoch(
+        nodes: &mut HashMap<(u32, u64), Node>,
+        current: u64,
+        handle: &NodeHandle,
+        sender: &Sender<SignedMessage>,
+        signal: SignedMessage,
+    ) -> u64 {
+        // The latest Node of every topic, from which the Node of the new epoch is made.
+        let mut latest: HashMap<u32, Node> = HashMap::new();
+        for ((topic, epoch), node) in nodes.iter() {
+            if latest.get(topic).map_or(true, |n| n.epoch < *epoch) {
+                latest.insert(*topic, node.clone());
+            }
+        }
+        let node = match latest.values().next() {
+            Some(node) => node.clone(),
+            None => {
+                return current;
+            }
+        };
+        if !node.is_authorized(&signal) {
+            *node.unauthorized_control.lock().await += 1;
+            my_print!(format!("{} rejected unauthorized Epoch signal", node.id));
+            return current;
+        }
+        let mut next: Epoch = match serde_json::from_str(&signal.get_message().content) {
+            Ok(next) => next,
+            Err(e) => {
+                println!("ERROR : <{}> invalid Epoch signal : {}", node.id, e);
+                return current;
+            }
+        };
+        if next.epoch <= current {
+            my_print!(format!(
+                "{} ignored epoch {}, already in epoch {}",
+                node.id, next.epoch, current
+            ));
+            return current;
+        }
+        // KeyCards of Identities which are not allowed never join the samples.
+        if let Some(allowlist) = &node.allowlist {
+            next.members = allowlist.filter(node.id, next.members);
+        }
+        *handle.membership.lock().await = Some(next.clone());
+        let own = node.kc.keycard().identity();
+        if !next.contains(&own) {
+            my_print!(format!(
+                "{} left the membership in epoch {}",
+                node.id, next.epoch
+            ));
+            return next.epoch;
+        }
+        let keycards: HashMap<Identity, KeyCard> = next
+            .members
+            .iter()
+            .filter(|keycard| keycard.identity() != own)
+            .map(|keycard| (keycard.identity(), keycard.clone()))
+            .collect();
+        let keycards = Arc::new(Mutex::new(keycards));
+        for (topic, node) in latest.into_iter() {
+            let entered = node.enter_epoch(sender, &next, keycards.clone()).await;
+            handle.views.lock().await.insert(topic, entered.view());
+            nodes.insert((topic, next.epoch), entered);
+        }
+        my_print!(format!(
+            "{} entered epoch {} : {} members",
+            node.id,
+            next.epoch,
+            next.members.len()
+        ));
+        next.epoch
+    }
+
+    /// Whether every instance started in the epoch of the Node has delivered, or was opened longer than
+    /// the epoch timeout ago.
+    async fn is_finished(&self) -> bool {
+        for (_, state) in self.instance_entries().await.into_iter() {
+            if state.delivered_msg.lock().await.is_none()
+                && state.opened.elapsed() < self.epoch_timeout
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Retire the Nodes of the past epochs whose instances have all finished : they handle no more
+    /// Messages, and the Messages of their epoch are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - The Nodes of every topic and epoch, updated.
+    /// * `current` - The current epoch.
+    ///
+    async fn retire_epochs(nodes: &mut HashMap<(u32, u64), Node>, current: u64) {
+        let mut finished = Vec::new();
+        for (key, node) in nodes.iter() {
+            if node.epoch < current && node.is_finished().await {
+                finished.push(*key);
+            }
+        }
+        for (topic, epoch) in finished.into_iter() {
+            if let Some(node) = nodes.remove(&(topic, epoch)) {
+                my_print!(format!(
+                    "{} retired epoch {} of topic {}",
+                    node.id, epoch, topic
+                ));
+            }
+        }
+    }
+
+    /// Listen for incoming Messages and hand them to the Node of their topic and epoch, acknowledging
+    /// the ones which are accepted, until the Nodes are stopped. The signals of the operator go to the
+    /// current epoch. Messages of a topic or an epoch the Nodes do not run are dropped. If pulls are
+    /// enabled, the Nodes of the current epoch pull the Messages they missed at every interval. The
+    /// Nodes of the past epochs are retired once their instances finish.
+    ///
+    /// # Arguments
+    ///
+    /// * `topics` - The Nodes of every topic, on the same transport and KeyChain, in the same epoch.
+    /// * `handle` - The handle through which the control socket follows the Nodes, and stops them.
+    /// * `sender` - The Nodes' Sender used to send Messages.
+    /// * `receiver` - The Nodes' Receiver on which Messages are received.
+    ///
     pub async fn listen(
-        self,
+        topics: Vec<Node>,
+        handle: NodeHandle,
         sender: Sender<SignedMessage>,
         receiver: &mut Receiver<SignedMessage>,
-        dummy_kc: KeyCard,
     ) {
+        let mut epoch = topics.first().map(|node| node.epoch).unwrap_or(0);
+        let interval = topics.first().map(|node| node.pull.interval).unwrap_or(0);
+        let period = Duration::from_millis(interval.max(1));
+        let mut pulls = time::interval_at(Instant::now() + period, period);
+        pulls.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut retirements = time::interval_at(Instant::now() + RETIRE_PERIOD, RETIRE_PERIOD);
+        retirements.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        // The tasks are shared by the topics and epochs, retired or not.
+        let tasks = topics.first().cloned();
+        let mut nodes: HashMap<(u32, u64), Node> = topics
+            .into_iter()
+            .map(|n| ((n.topic, n.epoch), n))
+            .collect();
         loop {
-            let (identity, raw_message, acknowledger) = receiver.receive().await;
-            let message: SignedMessage = raw_message;
-
-            let msg_type = message.clone().get_type();
-            let mut kc = dummy_kc.clone();
-            if msg_type < 6 {
-                kc = self.keycards[&identity].clone();
-            }
-            match msg_type {
-                // Gossip
-                0 => {
-                    let correct = message
-                        .clone()
-                        .get_signature()
-                        .verify(&kc, &Gossip(message.clone().get_message()));
-                    if correct.is_ok() {
-                        acknowledger.strong();
-                        let gp = self.gossip_peers.lock().await.clone();
-                        let dg = self.delivered_gossip.clone();
-                        let ec = self.echo.clone();
-                        let ep = self.echo_subscribers.lock().await.clone();
-                        let m = message.clone();
-                        let s = sender.clone();
-                        let keychain = self.kc.clone();
-                        tokio::spawn(async move {
-                            deliver_gossip(keychain, m, s, gp, dg, ec, ep).await
-                        });
+            tokio::select! {
+                (identity, raw_message, acknowledger) = receiver.receive() => {
+                    let msg = raw_message.clone().get_message();
+                    let key = match msg.message_type {
+                        9 => (msg.topic, epoch),
+                        _ => (msg.topic, msg.epoch),
+                    };
+                    if msg.message_type == 13 {
+                        epoch = Node::next_epoch(&mut nodes, epoch, &handle, &sender, raw_message)
+                            .await;
+                    } else if let Some(node) = nodes.get(&key) {
+                        if node.handle(&sender, identity, raw_message).await {
+                            acknowledger.strong();
+                        }
                     } else {
-                        my_print!(format!("Problem with Gossip : {:?}", correct));
+                        my_print!(format!(
+                            "Dropped Message of topic {} in epoch {} from {:?}",
+                            key.0, key.1, identity
+                        ));
                     }
                 }
-                // Echo
-                1 => {
-                    let kc = self.keycards[&identity].clone();
-                    let correct = message
-                        .clone()
-                        .get_signature()
-                        .verify(&kc, &Echo(message.clone().get_message()));
-                    if correct.is_ok() {
-                        acknowledger.strong();
-                        let rp = self.ready_subscribers.lock().await.clone();
-                        let de = self.delivered_echo.clone();
-                        let er = self.echo_replies.clone();
-                        let duplicates = self.duplicate_echo.clone();
-                        let echo = self.echo.clone();
-                        let ethr = self.echo_threshold.clone();
-                        let m = message.clone().get_message();
-                        let s = sender.clone();
-                        let keychain = self.kc.clone();
-                        let rm = self.ready_messages.clone();
-                        tokio::spawn(async move {
-                            deliver_echo(
-                                keychain, m, identity, echo, er, duplicates, s, de, ethr, rp, rm,
-                            )
-                            .await
-                        });
-                    } else {
-                        my_print!(format!("Problem with Echo : {:?}", correct));
+                // Every topic pulls the instances of the current epoch.
+                _ = pulls.tick(), if interval > 0 => {
+                    for node in nodes.values().filter(|node| node.epoch == epoch) {
+                        node.pull(&sender).await;
                     }
                 }
-                // Ready
-                2 => {
-                    let correct = message
-                        .clone()
-                        .get_signature()
-                        .verify(&kc, &Ready(message.clone().get_message()));
-                    if correct.is_ok() {
-                        acknowledger.strong();
-                        let rp = self.ready_subscribers.lock().await.clone();
-                        let rr = self.ready_replies.clone();
-                        let duplicate_ready = self.duplicate_delivery.clone();
-                        let dr = self.delivery_replies.clone();
-                        let duplicate_delivery = self.duplicate_delivery.clone();
-                        let rm = self.ready_messages.clone();
-                        let dm = self.delivered_msg.clone();
-                        let rthr = self.ready_threshold.clone();
-                        let dthr = self.delivery_threshold.clone();
-                        let m = message.clone();
-                        let s = sender.clone();
-                        let id = self.id.clone();
-                        let keychain = self.kc.clone();
-                        tokio::spawn(async move {
-                            deliver_ready(
-                                keychain,
-                                id,
-                                m,
-                                identity,
-                                rp,
-                                rr,
-                                duplicate_ready,
-                                dr,
-                                duplicate_delivery,
-                                s,
-                                rm,
-                                rthr,
-                                dthr,
-                                dm,
-                            )
-                            .await
-                        });
-                    } else {
-                        my_print!(format!("Problem with Ready : {:?}", correct));
-                    }
+                _ = retirements.tick(), if nodes.keys().any(|(_, e)| *e < epoch) => {
+                    Node::retire_epochs(&mut nodes, epoch).await;
                 }
-                // GossipSubscription
-                3 => {
-                    let correct = message
-                        .clone()
-                        .get_signature()
-                        .verify(&kc, &GossipSubscription(message.clone().get_message()));
-                    if correct.is_ok() {
-                        acknowledger.strong();
-                        let gp = self.gossip_peers.clone();
-                        let dm = self.delivered_msg.lock().await.clone();
-                        let s = sender.clone();
-                        let keychain = self.kc.clone();
-                        tokio::spawn(async move {
-                            gossip_subscription(keychain, s, identity, gp, dm).await
-                        });
-                    } else {
-                        my_print!(format!("Problem with Gossip Subscription : {:?}", correct));
+                _ = handle.stop.notified() => {
+                    break;
+                }
+            }
+        }
+        if let Some(node) = tasks {
+            let cancelled = node.cancel_tasks().await;
+            my_print!(format!(
+                "{} stopped, {} tasks cancelled",
+                node.id, cancelled
+            ));
+        }
+    }
+
+    /// Handle a Message received from a peer or from the operator. Returns true if the Message has to
+    /// be strongly acknowledged.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The Node's Sender used to send Messages.
+    /// * `identity` - The Identity of the Node which sent the Message.
+    /// * `message` - The signed Message received.
+    ///
+    pub async fn handle(
+        &self,
+        sender: &Sender<SignedMessage>,
+        identity: Identity,
+        message: SignedMessage,
+    ) -> bool {
+        if self.exclusions.lock().await.excluded.contains(&identity) {
+            return false;
+        }
+
+        let msg_type = message.clone().get_type();
+        let mut kc = self.operator.clone();
+        if msg_type != 9 {
+            let known = self.keycards.lock().await.get(&identity).cloned();
+            match known {
+                Some(keycard) => {
+                    kc = keycard;
+                }
+                None => {
+                    self.reject_unknown(identity, msg_type).await;
+                    return false;
+                }
+            }
+            *self.received.lock().await += 1;
+        } else if !self.is_authorized(&message) {
+            *self.unauthorized_control.lock().await += 1;
+            my_print!(format!(
+                "{} rejected unauthorized control Message of type {} from {:?}",
+                self.id, msg_type, identity
+            ));
+            return false;
+        }
+        match msg_type {
+            // Gossip
+            0 => {
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &Gossip(message.clone().get_message()));
+                if correct.is_ok() {
+                    let m = message.clone().get_message();
+                    match self.is_signed_by_source(&m).await {
+                        Some(true) => {}
+                        Some(false) => {
+                            my_print!(format!("Problem with Gossip : invalid source signature"));
+                            self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                                .await;
+                            return false;
+                        }
+                        None => {
+                            return false;
+                        }
+                    }
+                    // Only a Gossip signed by its source opens an instance, a pruned one is dropped.
+                    if let Some(instance) = m.instance {
+                        if self.open_instance(instance, self.epoch).await.is_none() {
+                            return true;
+                        }
                     }
+                    let state = match self.instance_of(&message).await {
+                        Some(state) => state,
+                        None => {
+                            return false;
+                        }
+                    };
+                    let murmur = self.murmur_layer(sender, &state).await;
+                    self.spawn(async move { murmur.deliver(m).await }).await;
+                    true
+                } else {
+                    my_print!(format!("Problem with Gossip : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    false
                 }
-                // EchoSubscription
-                4 => {
-                    let correct = message
-                        .clone()
-                        .get_signature()
-                        .verify(&kc, &EchoSubscription(message.clone().get_message()));
-                    if correct.is_ok() {
-                        acknowledger.strong();
-                        let s = sender.clone();
-                        let ec = self.echo.lock().await.clone();
-                        let ep = self.echo_subscribers.clone();
-                        let keychain = self.kc.clone();
-
-                        tokio::spawn(async move {
-                            echo_subscription(keychain, s, identity, ec, ep).await
-                        });
-                    } else {
-                        my_print!(format!("Problem with Echo Subscription : {:?}", correct));
+            }
+            // Echo, only if Sieve runs
+            1 if self.top >= Layer::Sieve => {
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &Echo(message.clone().get_message()));
+                if correct.is_ok() {
+                    // Echoes and Readies only count for the instances opened by a Gossip.
+                    let state = match self.instance_of(&message).await {
+                        Some(state) => state,
+                        None => {
+                            return false;
+                        }
+                    };
+                    let m = message.clone().get_message();
+                    match self.is_signed_by_source(&m).await {
+                        Some(true) => {}
+                        Some(false) => {
+                            my_print!(format!("Problem with Echo : invalid source signature"));
+                            self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                                .await;
+                            return false;
+                        }
+                        None => {
+                            return false;
+                        }
                     }
+                    let equivocation = check_equivocation(
+                        self.id,
+                        identity,
+                        message.clone(),
+                        self.first_signed.clone(),
+                        self.evidence.clone(),
+                    )
+                    .await;
+                    // The conflicting Message is only kept as evidence.
+                    if equivocation {
+                        self.punish(sender, identity, Misbehaviour::Equivocation)
+                            .await;
+                        return false;
+                    }
+                    let sieve = self.sieve_layer(sender, &state).await;
+                    self.spawn(async move { sieve.echo_received(m, identity).await })
+                        .await;
+                    true
+                } else {
+                    my_print!(format!("Problem with Echo : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    false
                 }
-                // ReadySubscription
-                5 => {
-                    let correct = message
-                        .clone()
-                        .get_signature()
-                        .verify(&kc, &ReadySubscription(message.clone().get_message()));
-                    if correct.is_ok() {
-                        acknowledger.strong();
-                        let s = sender.clone();
-                        let rm = self.ready_messages.lock().await.clone();
-                        let rp = self.ready_subscribers.clone();
-                        let id = self.id.clone();
-                        let keychain = self.kc.clone();
-                        tokio::spawn(async move {
-                            ready_subscription(keychain, id, s, identity, rm, rp).await
-                        });
-                    } else {
-                        my_print!(format!("Problem with Ready Subscription : {:?}", correct));
+            }
+            // Ready, only if Contagion runs
+            2 if self.top >= Layer::Contagion => {
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &Ready(message.clone().get_message()));
+                if correct.is_ok() {
+                    // Echoes and Readies only count for the instances opened by a Gossip.
+                    let state = match self.instance_of(&message).await {
+                        Some(state) => state,
+                        None => {
+                            return false;
+                        }
+                    };
+                    let m = message.clone().get_message();
+                    match self.is_signed_by_source(&m).await {
+                        Some(true) => {}
+                        Some(false) => {
+                            my_print!(format!("Problem with Ready : invalid source signature"));
+                            self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                                .await;
+                            return false;
+                        }
+                        None => {
+                            return false;
+                        }
                     }
+                    // A correct peer sends a Ready for every content reaching the Ready threshold, so
+                    // Readies of several contents are no equivocation.
+                    let contagion = self.contagion_layer(sender, &state).await;
+                    let signed = message.clone();
+                    self.spawn(async move { contagion.ready_received(signed, identity).await })
+                        .await;
+                    true
+                } else {
+                    my_print!(format!("Problem with Ready : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    false
                 }
-                // Send Gossip Subscriptions
-                6 => {
-                    let tokio_sender = sender.clone();
-                    let peers = self.gossip_peers.lock().await.clone();
+            }
+            // GossipSubscription
+            3 => {
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &GossipSubscription(message.clone().get_message()));
+                if correct.is_ok() {
+                    if self.is_flooding(identity, &message).await {
+                        self.punish(sender, identity, Misbehaviour::SubscriptionFlooding)
+                            .await;
+                        return true;
+                    }
+                    if !self.journal_subscriber(identity, msg_type).await {
+                        return false;
+                    }
+                    let gp = self.gossip_peers.clone();
+                    let mut dg = Vec::new();
+                    for state in self.instance_states().await {
+                        dg.extend(state.delivered_gossip.lock().await.clone());
+                    }
+                    let s = sender.clone();
                     let keychain = self.kc.clone();
-                    tokio::spawn(async move {
-                        gossip_subscribe(keychain, tokio_sender, peers).await;
-                    });
+                    self.spawn(
+                        async move { gossip_subscription(keychain, s, identity, gp, dg).await },
+                    )
+                    .await;
+                    true
+                } else {
+                    my_print!(format!("Problem with Gossip Subscription : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    false
                 }
-                // Send Echo Subscriptions
-                7 => {
-                    let tokio_sender = sender.clone();
-                    let replies = self.echo_replies.lock().await.clone();
-                    let keychain = self.kc.clone();
-                    tokio::spawn(async move {
-                        echo_subscribe(keychain, tokio_sender, replies).await;
-                    });
+            }
+            // EchoSubscription
+            4 => {
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &EchoSubscription(message.clone().get_message()));
+                if correct.is_ok() {
+                    if self.is_flooding(identity, &message).await {
+                        self.punish(sender, identity, Misbehaviour::SubscriptionFlooding)
+                            .await;
+                        return true;
+                    }
+                    if !self.journal_subscriber(identity, msg_type).await {
+                        return false;
+                    }
+                    let s = sender.clone();
+                    let mut ec = Vec::new();
+                    for state in self.instance_states().await {
+                        ec.extend(state.echo.lock().await.clone());
+                    }
+                    let ep = self.echo_subscribers.clone();
+                    let (keychain, pusher) = (self.kc.clone(), self.pusher.clone());
+                    self.spawn(async move {
+                        echo_subscription(keychain, s, pusher, identity, ec, ep).await
+                    })
+                    .await;
+                    true
+                } else {
+                    my_print!(format!("Problem with Echo Subscription : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    false
                 }
-                // Send Ready Subscriptions
-                8 => {
-                    let tokio_sender = sender.clone();
-                    let r_replies = self.ready_replies.lock().await.clone();
-                    let d_replies = self.delivery_replies.lock().await.clone();
+            }
+            // ReadySubscription
+            5 => {
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &ReadySubscription(message.clone().get_message()));
+                if correct.is_ok() {
+                    if self.is_flooding(identity, &message).await {
+                        self.punish(sender, identity, Misbehaviour::SubscriptionFlooding)
+                            .await;
+                        return true;
+                    }
+                    if !self.journal_subscriber(identity, msg_type).await {
+                        return false;
+                    }
+                    let s = sender.clone();
+                    let mut rm = Vec::new();
+                    for state in self.instance_states().await {
+                        rm.extend(state.ready_messages.lock().await.clone());
+                    }
+                    let rp = self.ready_subscribers.clone();
+                    let id = self.id.clone();
                     let keychain = self.kc.clone();
-                    tokio::spawn(async move {
-                        ready_subscribe(keychain, tokio_sender, r_replies, d_replies).await;
-                    });
+                    self.spawn(async move {
+                        ready_subscription(keychain, id, s, identity, rm, rp).await
+                    })
+                    .await;
+                    true
+                } else {
+                    my_print!(format!("Problem with Ready Subscription : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    false
                 }
-                // Trigger sender
-                9 => {
-                    let tokio_sender = sender.clone();
-                    let keychain = self.kc.clone();
-                    let msg = Message::new(0, String::from("Test message"));
-                    let signature = keychain.sign(&Gossip(msg.clone())).unwrap();
-                    let signed_msg: SignedMessage = SignedMessage::new(msg, signature);
-                    let peers = self.gossip_peers.lock().await.clone();
-                    let dg = self.delivered_gossip.clone();
-                    let ec = self.echo.clone();
-                    let ep = self.echo_subscribers.lock().await.clone();
-                    tokio::spawn(async move {
-                        dispatch(keychain, signed_msg, tokio_sender, peers, dg, ec, ep).await
-                    });
+            }
+            // Trigger sender, the content of the signal is the payload to broadcast in a new instance
+            9 => {
+                let mut trigger = message.clone().get_message();
+                let instance = match trigger.instance {
+                    Some(instance) if instance.source == self.kc.keycard().identity() => instance,
+                    _ => {
+                        my_print!(format!("{} ignored Trigger for another source", self.id));
+                        return false;
+                    }
+                };
+                if self.instances.lock().await.contains_key(&instance) {
+                    my_print!(format!(
+                        "{} ignored Trigger reusing sequence {}",
+                        self.id, instance.sequence
+                    ));
+                    return false;
                 }
-                // Not valid
-                _ => {
-                    println!("Not a valid message type!");
+                let state = match self.open_instance(instance, self.epoch).await {
+                    Some(state) => state,
+                    None => {
+                        my_print!(format!(
+                            "{} ignored Trigger for pruned sequence {}",
+                            self.id, instance.sequence
+                        ));
+                        return false;
+                    }
+                };
+                // The Node signs the transfers of its account, as their owner.
+                if self.topic == TRANSFER_TOPIC && self.ledger.is_some() {
+                    if let Ok(transfer) = serde_json::from_str::<Transfer>(&trigger.content) {
+                        let signed = SignedTransfer::new(transfer, &self.kc);
+                        trigger.content = serde_json::to_string(&signed).unwrap();
+                    }
+                }
+                // The causal ordering attaches the clock of the Node to the payload.
+                if let Some(buffer) = &self.causal {
+                    trigger.content = buffer.lock().await.stamp(instance, trigger.content);
                 }
+                let msg = Message::with_instance(0, instance, trigger.content)
+                    .in_topic(self.topic)
+                    .in_epoch(self.epoch)
+                    .signed_by_source(&self.kc);
+                // A restarted Node never broadcasts another content in an instance it already used.
+                let message = msg.clone();
+                if !record(&self.journal, Record::Sent { message }).await {
+                    return false;
+                }
+                if self.protocol == Protocol::Bracha {
+                    let bracha = self.bracha_layer(sender, &state).await;
+                    self.spawn(async move { bracha.broadcast(msg).await }).await;
+                    return false;
+                }
+                let murmur = self.murmur_layer(sender, &state).await;
+                self.spawn(async move { murmur.deliver(msg).await }).await;
+                false
+            }
+            // Bracha Send, Echo and Ready
+            10..=12 if self.protocol == Protocol::Bracha => {
+                let msg = message.clone().get_message();
+                let signature = message.clone().get_signature();
+                let correct = match msg_type {
+                    10 => signature.verify(&kc, &BrachaSend(msg)),
+                    11 => signature.verify(&kc, &BrachaEcho(msg)),
+                    _ => signature.verify(&kc, &BrachaReady(msg)),
+                };
+                if correct.is_err() {
+                    my_print!(format!("Problem with Bracha : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    return false;
+                }
+                let m = message.clone().get_message();
+                // Only the source of an instance sends it, and its Send opens the instance.
+                if msg_type == 10 {
+                    match m.instance {
+                        Some(instance) if instance.source == identity => {
+                            if self.open_instance(instance, self.epoch).await.is_none() {
+                                return true;
+                            }
+                        }
+                        _ => {
+                            my_print!(format!(
+                                "{} ignored Bracha Send from another Node than the source",
+                                self.id
+                            ));
+                            return false;
+                        }
+                    }
+                }
+                let state = match self.instance_of(&message).await {
+                    Some(state) => state,
+                    None => {
+                        return false;
+                    }
+                };
+                let bracha = self.bracha_layer(sender, &state).await;
+                self.spawn(async move { bracha.deliver(m, identity).await })
+                    .await;
+                true
+            }
+            // Pull Summary, answered with the Messages the peer missed, if the Node pulls as well
+            14 if self.pull.interval > 0 => {
+                let msg = message.clone().get_message();
+                let correct = message
+                    .clone()
+                    .get_signature()
+                    .verify(&kc, &PullSummary(msg.clone()));
+                if correct.is_err() {
+                    my_print!(format!("Problem with Pull Summary : {:?}", correct));
+                    self.punish(sender, identity, Misbehaviour::InvalidSignature)
+                        .await;
+                    return false;
+                }
+                if self.is_pulling_early(identity).await {
+                    return false;
+                }
+                let summary: Summary = match serde_json::from_str(&msg.content) {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        my_print!(format!("{} ignored invalid Pull Summary : {}", self.id, e));
+                        return false;
+                    }
+                };
+                // Echoes and Readies only count for the peers which subscribed to them.
+                let echo = self.echo_subscribers.lock().await.contains(&identity);
+                let ready = self.ready_subscribers.lock().await.contains(&identity);
+                let entries = self.instance_entries().await;
+                let (keychain, s, batch) = (self.kc.clone(), sender.clone(), self.pull.batch);
+                self.spawn(async move {
+                    let messages = missing(&summary, &entries, echo, ready, batch).await;
+                    pull_reply(keychain, s, identity, messages).await
+                })
+                .await;
+                false
+            }
+            // Not valid
+            _ => {
+                println!("Not a valid message type!");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::push::{Backoff, RetrySettings};
+    use crate::store::Put;
+    use std::sync::atomic::Ordering;
+    use talk::link::rendezvous::Connector;
+
+    fn test_node(
+        keycards: HashMap<Identity, KeyCard>,
+        refresh_after: usize,
+        operator: &KeyChain,
+    ) -> Node {
+        let settings = NodeSettings {
+            echo_threshold: 1,
+            ready_threshold: 1,
+            delivery_threshold: 1,
+            sizes: SampleSizes {
+                gossip: 1,
+                echo: 1,
+                ready: 1,
+                delivery: 1,
+            },
+            pull: PullSettings {
+                interval: 0,
+                peers: 1,
+                batch: 64,
+            },
+            retry: RetrySettings {
+                backoff: Backoff::Constant,
+                base: 100,
+                cap: 100,
+                jitter: 0.0,
+                timeout: 0,
+            },
+            fault_threshold: 3,
+            limits: Limits {
+                ready_per_peer: 4,
+                ready_per_instance: 16,
+                instances_per_source: 1024,
+            },
+            rendezvous: (String::from("127.0.0.1"), 4447, 0),
+            allowlist: None,
+            unknown_policy: UnknownPolicy {
+                log: false,
+                refresh_after,
+            },
+            protocol: Protocol::Sbr,
+            top: Layer::Contagion,
+            fifo: None,
+            causal: None,
+            initial_balance: None,
+            store: false,
+            state_dir: None,
+            epoch_timeout: 60000,
+        };
+        Node::new(
+            KeyChain::random(),
+            keycards,
+            0,
+            std::env::temp_dir()
+                .join(format!("exclusions_{}.json", rand::random::<u64>()))
+                .to_str()
+                .unwrap()
+                .to_string(),
+            ExclusionList::default(),
+            operator.keycard(),
+            settings,
+        )
+    }
+
+    fn test_sender() -> Sender<SignedMessage> {
+        let connector = Connector::new(
+            (String::from("127.0.0.1"), 4446),
+            KeyChain::random(),
+            Default::default(),
+        );
+        Sender::new(connector, Default::default())
+    }
+
+    fn signed_echo(keychain: &KeyChain, content: &str) -> SignedMessage {
+        let msg = Message::new(1, String::from(content));
+        let signature = keychain.sign(&Echo(msg.clone())).unwrap();
+        SignedMessage::new(msg, signature)
+    }
+
+    #[tokio::test]
+    async fn unknown_sender_is_rejected() {
+        let node = test_node(HashMap::new(), 0, &KeyChain::random());
+        let sender = test_sender();
+        let stranger = KeyChain::random();
+        let from = stranger.keycard().identity();
+        for _ in 0..2 {
+            let message = signed_echo(&stranger, "Test1");
+            assert!(!node.handle(&sender, from, message).await);
+        }
+        assert_eq!(node.unknown_senders.lock().await.get(&from), Some(&2));
+        assert!(node.instances.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_sender_triggers_refresh() {
+        let node = test_node(HashMap::new(), 2, &KeyChain::random());
+        let sender = test_sender();
+        let stranger = KeyChain::random();
+        let from = stranger.keycard().identity();
+        let message = signed_echo(&stranger, "Test1");
+        node.handle(&sender, from, message.clone()).await;
+        assert_eq!(*node.unknown_since_refresh.lock().await, 1);
+        node.handle(&sender, from, message).await;
+        assert_eq!(*node.unknown_since_refresh.lock().await, 0);
+    }
+
+    #[tokio::test]
+    async fn known_sender_with_invalid_signature() {
+        let member = KeyChain::random();
+        let from = member.keycard().identity();
+        let keycards = HashMap::from([(from, member.keycard())]);
+        let node = test_node(keycards, 0, &KeyChain::random());
+        let sender = test_sender();
+        let forged = signed_echo(&KeyChain::random(), "Test1");
+        assert!(!node.handle(&sender, from, forged).await);
+        assert!(node.unknown_senders.lock().await.is_empty());
+        assert_eq!(node.faults.lock().await.get(&from), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn control_requires_operator_signature() {
+        let operator = KeyChain::random();
+        let node = test_node(HashMap::new(), 0, &operator);
+        let sender = test_sender();
+        let from = KeyChain::random().keycard().identity();
+        let msg = Message::new(9, String::from("Test1"));
+        let forged = KeyChain::random().sign(&Trigger(msg.clone())).unwrap();
+        let forged = SignedMessage::new(msg.clone(), forged);
+        node.handle(&sender, from, forged).await;
+        assert_eq!(*node.unauthorized_control.lock().await, 1);
+        let signature = operator.sign(&Trigger(msg.clone())).unwrap();
+        let authorized = SignedMessage::new(msg, signature);
+        node.handle(&sender, from, authorized).await;
+        assert_eq!(*node.unauthorized_control.lock().await, 1);
+        assert!(node.unknown_senders.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trigger_opens_a_new_instance() {
+        let operator = KeyChain::random();
+        let node = test_node(HashMap::new(), 0, &operator);
+        let sender = test_sender();
+        let from = operator.keycard().identity();
+        let own = node.kc.keycard().identity();
+        let trigger = |source: Identity, sequence: u64| {
+            let instance = Instance { source, sequence };
+            let msg = Message::with_instance(9, instance, String::from("Test1"));
+            let signature = operator.sign(&Trigger(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        node.handle(&sender, from, trigger(own, 0)).await;
+        // A reused sequence and an instance of another source are ignored.
+        node.handle(&sender, from, trigger(own, 0)).await;
+        node.handle(&sender, from, trigger(from, 1)).await;
+        node.handle(&sender, from, trigger(own, 1)).await;
+        assert_eq!(node.instances.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn restarted_peer_subscribes_again() {
+        let member = KeyChain::random();
+        let from = member.keycard().identity();
+        let keycards = HashMap::from([(from, member.keycard())]);
+        let node = test_node(keycards, 0, &KeyChain::random());
+        let sender = test_sender();
+        let subscription = |incarnation: u64| {
+            let content = String::from("GossipSubscription");
+            let msg = Message::new(3, content).in_incarnation(incarnation);
+            let signature = member.sign(&GossipSubscription(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        node.handle(&sender, from, subscription(0)).await;
+        node.handle(&sender, from, subscription(0)).await;
+        assert_eq!(node.cap_counters.lock().await.duplicate_subscription, 1);
+        node.handle(&sender, from, subscription(1)).await;
+        assert_eq!(node.cap_counters.lock().await.duplicate_subscription, 1);
+        node.handle(&sender, from, subscription(0)).await;
+        assert_eq!(node.cap_counters.lock().await.duplicate_subscription, 2);
+        // The incarnation is signed : a relayed subscription cannot claim a later one.
+        let replayed = subscription(1);
+        let forged = replayed.clone().get_message().in_incarnation(2);
+        let forged = SignedMessage::new(forged, replayed.get_signature());
+        assert!(!node.handle(&sender, from, forged).await);
+        assert_eq!(node.cap_counters.lock().await.duplicate_subscription, 2);
+        assert_eq!(node.subscriptions.lock().await[&(from, 3)].0, 1);
+    }
+
+    #[tokio::test]
+    async fn duplicate_subscriptions_are_ignored() {
+        let member = KeyChain::random();
+        let from = member.keycard().identity();
+        let keycards = HashMap::from([(from, member.keycard())]);
+        let node = test_node(keycards, 0, &KeyChain::random());
+        let sender = test_sender();
+        let echo = || {
+            let msg = Message::new(4, String::from("EchoSubscription"));
+            let signature = member.sign(&EchoSubscription(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        let ready = || {
+            let msg = Message::new(5, String::from("ReadySubscription"));
+            let signature = member.sign(&ReadySubscription(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        for _ in 0..2 {
+            assert!(node.handle(&sender, from, echo()).await);
+            assert!(node.handle(&sender, from, ready()).await);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(node.cap_counters.lock().await.duplicate_subscription, 2);
+        assert_eq!(*node.echo_subscribers.lock().await, vec![from]);
+        assert_eq!(*node.ready_subscribers.lock().await, vec![from]);
+        // A duplicate is a fault, not yet enough to exclude the peer.
+        assert!(!node.exclusions.lock().await.excluded.contains(&from));
+    }
+
+    #[tokio::test]
+    async fn restarted_node_keeps_its_instances() {
+        let operator = KeyChain::random();
+        let path = std::env::temp_dir().join(format!("node_{}.log", rand::random::<u64>()));
+        let path = path.to_str().unwrap().to_string();
+        let mut node = test_node(HashMap::new(), 0, &operator);
+        node.journal = Some(Arc::new(Journal::new(path.clone())));
+        let sender = test_sender();
+        let from = operator.keycard().identity();
+        let own = node.kc.keycard().identity();
+        let trigger = |sequence: u64, content: &str| {
+            let instance = Instance {
+                source: own,
+                sequence,
+            };
+            let msg = Message::with_instance(9, instance, String::from(content));
+            let signature = operator.sign(&Trigger(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        assert_eq!(node.recover(&sender).await.incarnation, 0);
+        node.handle(&sender, from, trigger(0, "Test1")).await;
+
+        // The restarted Node has the same KeyChain, and a fresh state.
+        let mut restarted = node.in_topic(0);
+        restarted.journal = Some(Arc::new(Journal::new(path.clone())));
+        let recovered = restarted.recover(&sender).await;
+        assert_eq!(restarted.incarnation, 1);
+        assert_eq!(recovered.next_sequence(own), 1);
+        // The instance is not broadcast again with another content.
+        restarted.handle(&sender, from, trigger(0, "Test2")).await;
+        let instance = Instance {
+            source: own,
+            sequence: 0,
+        };
+        let state = restarted.instance(instance).await.unwrap();
+        let gossip = state.delivered_gossip.lock().await.clone().unwrap();
+        assert_eq!(gossip.content, "Test1");
+        // The Gossip sent again still carries the signature of the source.
+        assert!(gossip.verify_source(&node.kc.keycard()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn instances_finish_in_their_epoch() {
+        let member = KeyChain::random();
+        let from = member.keycard().identity();
+        let keycards = HashMap::from([(from, member.keycard())]);
+        let node = test_node(keycards.clone(), 0, &KeyChain::random());
+        let sender = test_sender();
+        let gossip = |sequence: u64, epoch: u64| {
+            let instance = Instance {
+                source: from,
+                sequence,
+            };
+            let msg = Message::with_instance(0, instance, String::from("Test1"))
+                .in_epoch(epoch)
+                .signed_by_source(&member);
+            let signature = member.sign(&Gossip(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        assert!(node.handle(&sender, from, gossip(0, 0)).await);
+
+        let epoch = Epoch {
+            epoch: 1,
+            members: vec![member.keycard(), node.kc.keycard()],
+        };
+        let next = node
+            .enter_epoch(&sender, &epoch, Arc::new(Mutex::new(keycards)))
+            .await;
+        assert_eq!(*next.gossip_peers.lock().await, vec![from]);
+        assert_eq!(next.membership().await.members.len(), 2);
+        // The instance of epoch 0 does not move to epoch 1, new instances start in epoch 1.
+        assert!(!next.handle(&sender, from, gossip(0, 1)).await);
+        assert!(next.handle(&sender, from, gossip(1, 1)).await);
+        assert_eq!(node.instance_states().await.len(), 1);
+        assert_eq!(next.instance_states().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn past_epochs_retire_once_their_instances_finish() {
+        let past = test_node(HashMap::new(), 0, &KeyChain::random());
+        let mut current = past.clone();
+        current.epoch = 1;
+        let instance = Instance {
+            source: KeyChain::random().keycard().identity(),
+            sequence: 0,
+        };
+        let state = past.open_instance(instance, 0).await.unwrap();
+        let mut nodes = HashMap::from([((0, 0), past.clone()), ((0, 1), current)]);
+        Node::retire_epochs(&mut nodes, 1).await;
+        assert!(nodes.contains_key(&(0, 0)));
+        *state.delivered_msg.lock().await =
+            Some(Message::with_instance(2, instance, String::new()));
+        Node::retire_epochs(&mut nodes, 1).await;
+        assert!(!nodes.contains_key(&(0, 0)));
+        assert!(nodes.contains_key(&(0, 1)));
+
+        // An instance which never delivers only keeps its epoch until the timeout.
+        let mut stalled = test_node(HashMap::new(), 0, &KeyChain::random());
+        stalled.epoch_timeout = Duration::from_millis(50);
+        stalled.open_instance(instance, 0).await.unwrap();
+        let mut nodes = HashMap::from([((0, 0), stalled)]);
+        Node::retire_epochs(&mut nodes, 1).await;
+        assert_eq!(nodes.len(), 1);
+        time::sleep(Duration::from_millis(60)).await;
+        Node::retire_epochs(&mut nodes, 1).await;
+        assert!(nodes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn topics_are_isolated() {
+        let operator = KeyChain::random();
+        let mut node = test_node(HashMap::new(), 0, &operator);
+        let own = node.kc.keycard().identity();
+        node.ledger = Some(Arc::new(Mutex::new(Ledger::new(format!("{:?}", own), 10))));
+        let (first, second) = (node.in_topic(0), node.in_topic(1));
+        let sender = test_sender();
+        let from = operator.keycard().identity();
+        let instance = Instance {
+            source: own,
+            sequence: 0,
+        };
+        let msg = Message::with_instance(9, instance, String::from("Test1")).in_topic(1);
+        let signature = operator.sign(&Trigger(msg.clone())).unwrap();
+        second
+            .handle(&sender, from, SignedMessage::new(msg, signature))
+            .await;
+        // The instance only exists in its topic, which numbers its instances on its own.
+        assert!(first.instances.lock().await.is_empty());
+        assert!(second.instances.lock().await.contains_key(&instance));
+        // The accounts and the exclusions are shared by the topics, the samples are not.
+        let (first_ledger, second_ledger) = (first.ledger.unwrap(), second.ledger.unwrap());
+        assert!(Arc::ptr_eq(&first_ledger, &second_ledger));
+        assert!(Arc::ptr_eq(&first.exclusions, &second.exclusions));
+        assert!(!Arc::ptr_eq(&first.gossip_peers, &second.gossip_peers));
+    }
+
+    #[tokio::test]
+    async fn unsubscribed_topics_only_record_deliveries() {
+        let node = test_node(HashMap::new(), 0, &KeyChain::random());
+        let (first, second) = (node.in_topic(0), node.in_topic(1));
+        second.subscribed.store(false, Ordering::SeqCst);
+        assert!(first.subscribed.load(Ordering::SeqCst));
+        let instance = Instance {
+            source: KeyChain::random().keycard().identity(),
+            sequence: 0,
+        };
+        let state = second.open_instance(instance, 0).await.unwrap();
+        let msg = Message::with_instance(2, instance, String::from("Test1")).in_topic(1);
+        second.application().deliver(msg).await;
+        assert!(state.delivered_msg.lock().await.is_some());
+        assert!(first.instances.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_gossips_signed_by_their_source_open_instances() {
+        let (source, relay) = (KeyChain::random(), KeyChain::random());
+        let from = relay.keycard().identity();
+        let keycards = HashMap::from([
+            (source.keycard().identity(), source.keycard()),
+            (from, relay.keycard()),
+        ]);
+        let node = test_node(keycards, 0, &KeyChain::random());
+        let sender = test_sender();
+        let instance = Instance {
+            source: source.keycard().identity(),
+            sequence: 0,
+        };
+        let relayed = |msg: Message| {
+            let signature = relay.sign(&Gossip(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        let gossip = Message::with_instance(0, instance, String::from("Test1"));
+
+        // An Echo does not open an instance.
+        let echo = gossip.with_type(1).signed_by_source(&source);
+        let signature = relay.sign(&Echo(echo.clone())).unwrap();
+        assert!(
+            !node
+                .handle(&sender, from, SignedMessage::new(echo, signature))
+                .await
+        );
+        assert!(node.instances.lock().await.is_empty());
+        // The relay cannot broadcast in the name of the source.
+        let forged = gossip.clone().signed_by_source(&relay);
+        assert!(!node.handle(&sender, from, relayed(forged)).await);
+        assert!(!node.handle(&sender, from, relayed(gossip.clone())).await);
+        assert!(node.instances.lock().await.is_empty());
+        assert_eq!(node.faults.lock().await.get(&from), Some(&2));
+
+        let signed = gossip.signed_by_source(&source);
+        let mut changed = signed.clone();
+        changed.content = String::from("Test2");
+        assert!(!changed.verify_source(&source.keycard()));
+        assert!(node.handle(&sender, from, relayed(signed)).await);
+        assert!(node.instances.lock().await.contains_key(&instance));
+    }
+
+    #[tokio::test]
+    async fn store_only_logs_the_sbr_deliveries_of_their_source() {
+        let (source, byzantine) = (KeyChain::random(), KeyChain::random());
+        let from = byzantine.keycard().identity();
+        let keycards = HashMap::from([
+            (source.keycard().identity(), source.keycard()),
+            (from, byzantine.keycard()),
+        ]);
+        let mut node = test_node(keycards, 0, &KeyChain::random());
+        let store = Arc::new(Mutex::new(Store::new()));
+        node.store = Some(store.clone());
+        node.subscribed.store(false, Ordering::SeqCst);
+        // The Byzantine member is the delivery sample of the Node.
+        node.delivery_replies.lock().await.insert(from, Vec::new());
+        let sender = test_sender();
+        let put = |value: &str| {
+            serde_json::to_string(&Put {
+                key: String::from("k"),
+                value: String::from(value),
+            })
+            .unwrap()
+        };
+        let gossip = |msg: &Message| {
+            let signature = byzantine.sign(&Gossip(msg.clone())).unwrap();
+            SignedMessage::new(msg.clone(), signature)
+        };
+        let ready = |msg: &Message| {
+            let msg = msg.with_type(2);
+            let signature = byzantine.sign(&Ready(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        let instance = |source: Identity, sequence: u64| Instance { source, sequence };
+
+        // The Byzantine member writes in the name of the source.
+        let forged = Message::with_instance(0, instance(source.keycard().identity(), 0), put("f"))
+            .signed_by_source(&byzantine);
+        assert!(!node.handle(&sender, from, gossip(&forged)).await);
+        assert!(!node.handle(&sender, from, ready(&forged)).await);
+        // It claims the highest sequence to pin the key.
+        let pinned = Message::with_instance(0, instance(from, u64::MAX), put("p"))
+            .signed_by_source(&byzantine);
+        assert!(node.handle(&sender, from, gossip(&pinned)).await);
+        assert!(node.handle(&sender, from, ready(&pinned)).await);
+        let genuine = Message::with_instance(0, instance(source.keycard().identity(), 0), put("g"))
+            .signed_by_source(&source);
+        assert!(node.handle(&sender, from, gossip(&genuine)).await);
+        assert!(node.handle(&sender, from, ready(&genuine)).await);
+
+        let source = format!("{:?}", source.keycard().identity());
+        for _ in 0..50 {
+            if store.lock().await.log().contains_key(&source) {
+                break;
             }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        let log = store.lock().await.log();
+        assert_eq!(log[&source].len(), 1);
+        assert!(log
+            .get(&format!("{:?}", from))
+            .map_or(true, |entries| entries.is_empty()));
+        assert_eq!(store.lock().await.get("k"), Some(String::from("g")));
+    }
+
+    #[tokio::test]
+    async fn instances_are_bounded_per_source() {
+        let source = KeyChain::random();
+        let from = source.keycard().identity();
+        let keycards = HashMap::from([(from, source.keycard())]);
+        let mut node = test_node(keycards, 0, &KeyChain::random());
+        node.limits.instances_per_source = 2;
+        let sender = test_sender();
+        let gossip = |sequence: u64| {
+            let instance = Instance {
+                source: from,
+                sequence,
+            };
+            let msg = Message::with_instance(0, instance, String::from("Test1"))
+                .signed_by_source(&source);
+            let signature = source.sign(&Gossip(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        for sequence in 0..4 {
+            assert!(node.handle(&sender, from, gossip(sequence)).await);
+        }
+        assert_eq!(node.instances.lock().await.len(), 2);
+        assert_eq!(node.cap_counters.lock().await.source_cap, 2);
+        // A pruned instance is acknowledged, but never opened again.
+        assert!(node.handle(&sender, from, gossip(1)).await);
+        let instance = Instance {
+            source: from,
+            sequence: 1,
+        };
+        assert!(node.instance(instance).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn excluded_peers_leave_every_instance() {
+        let (culprit_chain, other) = (KeyChain::random(), KeyChain::random());
+        let (culprit, other) = (culprit_chain.keycard(), other.keycard());
+        let keycards = HashMap::from([
+            (culprit.identity(), culprit.clone()),
+            (other.identity(), other.clone()),
+        ]);
+        let node = test_node(keycards, 0, &KeyChain::random());
+        let culprit = culprit.identity();
+        node.echo_replies.lock().await.insert(culprit, None);
+        node.duplicate_echo.lock().await.insert(culprit, 2);
+        node.ready_replies.lock().await.insert(culprit, Vec::new());
+        let instance = Instance {
+            source: other.identity(),
+            sequence: 0,
+        };
+        let state = node.open_instance(instance, 0).await.unwrap();
+        let echo = Message::with_instance(0, instance, String::from("Test1"));
+        state.echo_replies.lock().await.insert(culprit, Some(echo));
+
+        let conflicting = |content: &str| {
+            let msg = Message::with_instance(1, instance, String::from(content));
+            let signature = culprit_chain.sign(&Echo(msg.clone())).unwrap();
+            SignedMessage::new(msg, signature)
+        };
+        node.evidence.lock().await.push(Evidence {
+            culprit,
+            first: conflicting("Test1"),
+            second: conflicting("Test2"),
+        });
+        node.punish(&test_sender(), culprit, Misbehaviour::Equivocation)
+            .await;
+        assert_eq!(node.exclusions.lock().await.evidence.len(), 1);
+        let replies = state.echo_replies.lock().await.clone();
+        assert_eq!(replies.len(), 1);
+        assert!(replies[&other.identity()].is_none());
+        let duplicates = node.duplicate_echo.lock().await.clone();
+        assert_eq!(duplicates, HashMap::from([(other.identity(), 2)]));
+        let ready = state.ready_replies.lock().await.clone();
+        assert_eq!(ready.keys().collect::<Vec<_>>(), vec![&other.identity()]);
+        let _ = std::fs::remove_file(&node.exclusion_file);
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_deliveries_are_replayed() {
+        let path = std::env::temp_dir().join(format!("node_{}.log", rand::random::<u64>()));
+        let path = path.to_str().unwrap().to_string();
+        let mut node = test_node(HashMap::new(), 0, &KeyChain::random());
+        node.journal = Some(Arc::new(Journal::new(path.clone())));
+        node.subscribed.store(false, Ordering::SeqCst);
+        let source = KeyChain::random().keycard().identity();
+        let (acknowledged, crashed) = (
+            Instance {
+                source,
+                sequence: 0,
+            },
+            Instance {
+                source,
+                sequence: 1,
+            },
+        );
+        let sender = test_sender();
+        node.recover(&sender).await;
+        node.open_instance(acknowledged, 0).await.unwrap();
+        let msg = Message::with_instance(2, acknowledged, String::from("Test1"));
+        node.application().deliver(msg).await;
+        // A crash between the delivery and the output of the application.
+        let msg = Message::with_instance(2, crashed, String::from("Test2"));
+        let record = Record::Delivered { message: msg };
+        node.journal
+            .as_ref()
+            .unwrap()
+            .append(&record)
+            .await
+            .unwrap();
+
+        let mut restarted = node.in_topic(0);
+        restarted.journal = Some(Arc::new(Journal::new(path.clone())));
+        restarted.subscribed.store(false, Ordering::SeqCst);
+        let recovered = restarted.recover(&sender).await;
+        assert_eq!(recovered.deliveries, vec![acknowledged, crashed]);
+        assert!(!recovered.acknowledged.contains(&crashed));
+        // The output of the unacknowledged delivery is written, and acknowledged, on recovery.
+        let recovered = Journal::new(path.clone()).recover().await;
+        assert!(recovered.acknowledged.contains(&crashed));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn echo_is_never_overwritten() {
+        let node = test_node(HashMap::new(), 0, &KeyChain::random());
+        let instance = Instance {
+            source: KeyChain::random().keycard().identity(),
+            sequence: 0,
+        };
+        let state = node.open_instance(instance, 0).await.unwrap();
+        for content in ["Test1", "Test2"] {
+            sieve::deliver(
+                node.kc.clone(),
+                Message::with_instance(0, instance, String::from(content)),
+                test_sender(),
+                node.pusher.clone(),
+                state.echo.clone(),
+                Vec::new(),
+                None,
+            )
+            .await;
+        }
+        let echo = state.echo.lock().await.clone().unwrap();
+        assert_eq!(echo.content, "Test1");
+    }
+
+    #[tokio::test]
+    async fn two_payload_sources_get_no_correct_peer_excluded() {
+        let (source, correct) = (KeyChain::random(), KeyChain::random());
+        let from = correct.keycard().identity();
+        let keycards = HashMap::from([
+            (source.keycard().identity(), source.keycard()),
+            (from, correct.keycard()),
+        ]);
+        let node = test_node(keycards, 0, &KeyChain::random());
+        let sender = test_sender();
+        let instance = Instance {
+            source: source.keycard().identity(),
+            sequence: 0,
+        };
+        // The source signs two payloads for the same instance.
+        let payloads: Vec<Message> = ["Test1", "Test2"]
+            .iter()
+            .map(|content| {
+                Message::with_instance(0, instance, String::from(*content))
+                    .signed_by_source(&source)
+            })
+            .collect();
+        let gossip = payloads[0].clone();
+        let signature = correct.sign(&Gossip(gossip.clone())).unwrap();
+        assert!(
+            node.handle(&sender, from, SignedMessage::new(gossip, signature))
+                .await
+        );
+        let echo = payloads[0].with_type(1);
+        let signature = correct.sign(&Echo(echo.clone())).unwrap();
+        assert!(
+            node.handle(&sender, from, SignedMessage::new(echo, signature))
+                .await
+        );
+        // The correct peer sends a Ready for both payloads once both reach the Ready threshold.
+        for payload in payloads.iter() {
+            let ready = payload.with_type(2);
+            let signature = correct.sign(&Ready(ready.clone())).unwrap();
+            assert!(
+                node.handle(&sender, from, SignedMessage::new(ready, signature))
+                    .await
+            );
         }
+        assert!(node.evidence.lock().await.is_empty());
+        assert!(node.faults.lock().await.get(&from).is_none());
+        assert!(node.exclusions.lock().await.excluded.is_empty());
     }
 }
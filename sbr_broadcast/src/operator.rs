@@ -0,0 +1,138 @@
+use crate::message::{Message, SignedMessage};
+use crate::message_headers::{NewEpoch, Trigger};
+use std::fs;
+use talk::crypto::KeyChain;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Signer of the control Messages sent to the Nodes. Only the launcher, the operator process and a
+/// process running every node hold the operator KeyChain : a node process asks the operator socket.
+#[derive(Clone)]
+pub enum Operator {
+    KeyChain(KeyChain),
+    Socket(String),
+}
+
+impl Operator {
+    /// Sign a control Message, Trigger or NewEpoch, as the operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The control Message to sign.
+    ///
+    pub async fn sign(&self, message: Message) -> Result<SignedMessage, String> {
+        match self {
+            Operator::KeyChain(keychain) => sign_control(keychain, message),
+            Operator::Socket(path) => {
+                let stream = UnixStream::connect(path)
+                    .await
+                    .map_err(|e| format!("operator socket {} : {}", path, e))?;
+                let (read_half, mut write_half) = stream.into_split();
+                let mut request = serde_json::to_string(&message).unwrap();
+                request.push('\n');
+                write_half
+                    .write_all(request.as_bytes())
+                    .await
+                    .map_err(|e| format!("operator socket {} write : {}", path, e))?;
+                let mut lines = BufReader::new(read_half).lines();
+                match lines.next_line().await {
+                    Ok(Some(line)) => serde_json::from_str::<Result<SignedMessage, String>>(&line)
+                        .map_err(|e| format!("operator socket {} reply : {}", path, e))?,
+                    _ => Err(format!("operator socket {} closed", path)),
+                }
+            }
+        }
+    }
+}
+
+/// Sign a control Message with the operator KeyChain. Any other type of Message is refused.
+///
+/// # Arguments
+///
+/// * `keychain` - The operator KeyChain.
+/// * `message` - The control Message to sign.
+///
+fn sign_control(keychain: &KeyChain, message: Message) -> Result<SignedMessage, String> {
+    let signature = match message.message_type {
+        9 => keychain.sign(&Trigger(message.clone())).unwrap(),
+        13 => keychain.sign(&NewEpoch(message.clone())).unwrap(),
+        other => {
+            return Err(format!("Messages of type {} are not signed", other));
+        }
+    };
+    Ok(SignedMessage::new(message, signature))
+}
+
+/// Serve the operator socket, on which the node processes get their control Messages signed. Every
+/// connection can send several Messages, each answered on its own line.
+///
+/// # Arguments
+///
+/// * `path` - The path of the Unix domain socket.
+/// * `keychain` - The operator KeyChain.
+///
+pub async fn serve(path: String, keychain: KeyChain) {
+    let _ = fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("ERROR : operator socket {} bind : {}", path, e);
+            return;
+        }
+    };
+    my_print!(format!("Operator socket listening on {}", path));
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_client(stream, keychain.clone()));
+            }
+            Err(e) => {
+                println!("ERROR : operator socket accept : {}", e);
+            }
+        }
+    }
+}
+
+async fn serve_client(stream: UnixStream, keychain: KeyChain) {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let signed = match serde_json::from_str::<Message>(&line) {
+            Ok(message) => sign_control(&keychain, message),
+            Err(e) => Err(format!("Invalid Message : {}", e)),
+        };
+        let mut output = serde_json::to_string(&signed).unwrap();
+        output.push('\n');
+        if write_half.write_all(output.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Instance;
+
+    #[tokio::test]
+    async fn node_processes_sign_through_the_operator_socket() {
+        let keychain = KeyChain::random();
+        let path = std::env::temp_dir().join(format!("operator_{}.sock", rand::random::<u64>()));
+        let path = path.to_str().unwrap().to_string();
+        tokio::spawn(serve(path.clone(), keychain.clone()));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let instance = Instance {
+            source: KeyChain::random().keycard().identity(),
+            sequence: 0,
+        };
+        let trigger = Message::with_instance(9, instance, String::from("Test1"));
+        let signed = Operator::Socket(path.clone()).sign(trigger).await.unwrap();
+        let (msg, signature) = (signed.clone().get_message(), signed.get_signature());
+        assert!(signature.verify(&keychain.keycard(), &Trigger(msg)).is_ok());
+        // Only control Messages are signed.
+        let echo = Message::with_instance(1, instance, String::from("Test1"));
+        assert!(Operator::Socket(path.clone()).sign(echo).await.is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::instance::InstanceState;
+use crate::message::{Instance, Message, SignedMessage};
+use crate::message_headers::{Echo, Gossip, Ready};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use talk::crypto::{Identity, KeyChain};
+use talk::unicast::Sender;
+
+/// Sequences of a source, as sorted and disjoint inclusive ranges.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Ranges(pub Vec<(u64, u64)>);
+
+impl Ranges {
+    /// The ranges of the given sorted sequences.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequences` - The sequences, sorted and without duplicates.
+    ///
+    fn of(sequences: &[u64]) -> Self {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for sequence in sequences.iter() {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == *sequence => *last = *sequence,
+                _ => ranges.push((*sequence, *sequence)),
+            }
+        }
+        Ranges(ranges)
+    }
+
+    pub fn contains(&self, sequence: u64) -> bool {
+        let position = self.0.partition_point(|(_, last)| *last < sequence);
+        self.0
+            .get(position)
+            .map_or(false, |(first, _)| *first <= sequence)
+    }
+}
+
+/// The instances of a source a Node has seen, that is received the Gossip of, and delivered.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Progress {
+    pub source: Identity,
+    pub seen: Ranges,
+    pub delivered: Ranges,
+}
+
+/// Summary of the instances of an epoch a Node has seen and delivered, as ranges of sequences per
+/// source, so that its size follows the gaps of the Node rather than its history. It is sent to random
+/// peers, which answer with the Messages the Node misses.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Summary {
+    pub sources: Vec<Progress>,
+}
+
+impl Summary {
+    /// Summarize the given instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The instances of the epoch and their states.
+    ///
+    pub async fn of(entries: &[(Instance, InstanceState)]) -> Self {
+        let mut sources: BTreeMap<String, (Identity, Vec<u64>, Vec<u64>)> = BTreeMap::new();
+        for (instance, state) in sorted(entries).into_iter() {
+            let (_, seen, delivered) = sources.entry(format!("{:?}", instance.source)).or_insert((
+                instance.source,
+                Vec::new(),
+                Vec::new(),
+            ));
+            if state.delivered_gossip.lock().await.is_some() {
+                seen.push(instance.sequence);
+            }
+            if state.delivered_msg.lock().await.is_some() {
+                delivered.push(instance.sequence);
+            }
+        }
+        Summary {
+            sources: sources
+                .into_values()
+                .filter(|(_, seen, delivered)| !seen.is_empty() || !delivered.is_empty())
+                .map(|(source, seen, delivered)| Progress {
+                    source,
+                    seen: Ranges::of(&seen),
+                    delivered: Ranges::of(&delivered),
+                })
+                .collect(),
+        }
+    }
+
+    /// The progress of the Node for every source it summarized.
+    fn by_source(&self) -> HashMap<Identity, &Progress> {
+        self.sources
+            .iter()
+            .map(|progress| (progress.source, progress))
+            .collect()
+    }
+}
+
+/// The instances, oldest sequence first.
+///
+/// # Arguments
+///
+/// * `entries` - The instances of the epoch and their states.
+///
+fn sorted(entries: &[(Instance, InstanceState)]) -> Vec<&(Instance, InstanceState)> {
+    let mut sorted: Vec<&(Instance, InstanceState)> = entries.iter().collect();
+    sorted.sort_by_key(|(instance, _)| instance.sequence);
+    sorted
+}
+
+/// The Messages a peer misses according to its Summary : the Gossip of the instances it has not seen,
+/// and for the instances it has not delivered, the Echo and the Ready Messages of the Node if the peer
+/// subscribed to them. At most `batch` Messages are returned, for the oldest sequences first : the
+/// peer gets the next ones once it summarizes them.
+///
+/// # Arguments
+///
+/// * `summary` - The Summary of the peer.
+/// * `entries` - The instances of the epoch and their states.
+/// * `echo` - Whether the peer subscribed to the Echoes of the Node.
+/// * `ready` - Whether the peer subscribed to the Ready Messages of the Node.
+/// * `batch` - The maximum number of Messages.
+///
+pub async fn missing(
+    summary: &Summary,
+    entries: &[(Instance, InstanceState)],
+    echo: bool,
+    ready: bool,
+    batch: usize,
+) -> Vec<Message> {
+    let progress = summary.by_source();
+    let mut messages = Vec::new();
+    for (instance, state) in sorted(entries).into_iter() {
+        if messages.len() >= batch {
+            break;
+        }
+        let progress = progress.get(&instance.source);
+        if !progress.map_or(false, |p| p.seen.contains(instance.sequence)) {
+            messages.extend(state.delivered_gossip.lock().await.clone());
+        }
+        if progress.map_or(false, |p| p.delivered.contains(instance.sequence)) {
+            continue;
+        }
+        if echo {
+            if let Some(echo) = state.echo.lock().await.clone() {
+                messages.push(echo.with_type(1));
+            }
+        }
+        if ready {
+            messages.extend(state.ready_messages.lock().await.clone());
+        }
+    }
+    messages.truncate(batch);
+    messages
+}
+
+/// Answer a Summary : send the Messages the peer misses, each signed for its type. A peer which cannot
+/// be reached pulls again later.
+///
+/// # Arguments
+///
+/// * `keychain` - KeyChain used to sign the Messages.
+/// * `node_sender` - The Node's Sender used to send Messages.
+/// * `to` - The Identity of the Node which sent the Summary.
+/// * `messages` - The Gossip, Echo and Ready Messages it misses.
+///
+pub async fn pull_reply(
+    keychain: KeyChain,
+    node_sender: Sender<SignedMessage>,
+    to: Identity,
+    messages: Vec<Message>,
+) {
+    for msg in messages.into_iter() {
+        let signature = match msg.message_type {
+            0 => keychain.sign(&Gossip(msg.clone())),
+            1 => keychain.sign(&Echo(msg.clone())),
+            _ => keychain.sign(&Ready(msg.clone())),
+        }
+        .unwrap();
+        let signed_msg = SignedMessage::new(msg, signature);
+        if let Err(e) = node_sender.send(to, signed_msg).await {
+            println!("ERROR : pull_reply send : {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(
+        source: Identity,
+        sequence: u64,
+        gossip: bool,
+        delivered: bool,
+    ) -> (Instance, InstanceState) {
+        let instance = Instance { source, sequence };
+        let state = InstanceState::new(0, Vec::new(), Vec::new(), Vec::new());
+        let content = sequence.to_string();
+        if gossip {
+            *state.delivered_gossip.try_lock().unwrap() =
+                Some(Message::with_instance(0, instance, content.clone()));
+            *state.echo.try_lock().unwrap() =
+                Some(Message::with_instance(0, instance, content.clone()));
+            *state.ready_messages.try_lock().unwrap() =
+                vec![Message::with_instance(2, instance, content.clone())];
+        }
+        if delivered {
+            *state.delivered_msg.try_lock().unwrap() =
+                Some(Message::with_instance(2, instance, content));
+        }
+        (instance, state)
+    }
+
+    fn types(messages: &[Message]) -> Vec<(u64, u32)> {
+        messages
+            .iter()
+            .map(|m| (m.instance.unwrap().sequence, m.message_type))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn peers_get_what_they_miss() {
+        let source = KeyChain::random().keycard().identity();
+        let entries = vec![
+            entry(source, 0, true, true),
+            entry(source, 1, true, false),
+            entry(source, 2, false, false),
+        ];
+        let summary = Summary::of(&entries).await;
+        assert_eq!(summary.sources.len(), 1);
+        assert_eq!(summary.sources[0].seen, Ranges(vec![(0, 1)]));
+        assert_eq!(summary.sources[0].delivered, Ranges(vec![(0, 0)]));
+
+        // A peer which saw nothing gets every Gossip, and the Echoes and Readies it subscribed to.
+        let nothing = Summary::default();
+        let messages = missing(&nothing, &entries, true, false, 64).await;
+        assert_eq!(types(&messages), vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+        let messages = missing(&nothing, &entries, false, true, 64).await;
+        assert_eq!(types(&messages), vec![(0, 0), (0, 2), (1, 0), (1, 2)]);
+
+        // A peer with the same progress only misses the replies for the instances it did not deliver.
+        let messages = missing(&summary, &entries, true, true, 64).await;
+        assert_eq!(types(&messages), vec![(1, 1), (1, 2)]);
+        let mut seen = summary.clone();
+        seen.sources[0].delivered = Ranges::default();
+        let messages = missing(&seen, &entries, true, true, 64).await;
+        assert_eq!(types(&messages), vec![(0, 1), (0, 2), (1, 1), (1, 2)]);
+    }
+
+    #[tokio::test]
+    async fn summaries_are_ranges_and_replies_are_capped() {
+        let (a, b) = (
+            KeyChain::random().keycard().identity(),
+            KeyChain::random().keycard().identity(),
+        );
+        let mut entries: Vec<(Instance, InstanceState)> = (0..100)
+            .map(|sequence| entry(a, sequence, true, true))
+            .collect();
+        entries.push(entry(a, 101, true, false));
+        entries.push(entry(b, 0, false, false));
+        let summary = Summary::of(&entries).await;
+        assert_eq!(summary.sources.len(), 1);
+        assert_eq!(summary.sources[0].seen, Ranges(vec![(0, 99), (101, 101)]));
+        assert_eq!(summary.sources[0].delivered, Ranges(vec![(0, 99)]));
+        assert!(summary.sources[0].seen.contains(101));
+        assert!(!summary.sources[0].seen.contains(100));
+
+        // An empty Summary only gets a batch of Messages, the oldest first.
+        let messages = missing(&Summary::default(), &entries, true, true, 8).await;
+        assert_eq!(messages.len(), 8);
+        assert!(messages.iter().all(|m| m.instance.unwrap().sequence < 3));
+        let messages = missing(&summary, &entries, false, false, 8).await;
+        assert!(messages.is_empty());
+    }
+}
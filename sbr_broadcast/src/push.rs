@@ -0,0 +1,254 @@
+use crate::message::SignedMessage;
+use async_trait::async_trait;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use talk::broadcast::{BestEffort, BestEffortSettings};
+use talk::crypto::Identity;
+use talk::time::{SleepAgent, SleepSchedule};
+use talk::unicast::{Acknowledgement, PushSettings, Sender};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// How the delay between two attempts of a push grows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backoff {
+    /// The same delay between every attempt.
+    Constant,
+    /// The delay doubles after every attempt, up to the cap.
+    Exponential,
+}
+
+impl Backoff {
+    /// Parse the name of a backoff, as written in the configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backoff.
+    ///
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "constant" => Some(Backoff::Constant),
+            "exponential" => Some(Backoff::Exponential),
+            _ => None,
+        }
+    }
+}
+
+/// Retry schedule and deadline of the pushes of a Node.
+#[derive(Clone, Copy, Debug)]
+pub struct RetrySettings {
+    pub backoff: Backoff,
+    /// Delay before the first retry, in milliseconds.
+    pub base: u64,
+    /// Maximum delay between two attempts, in milliseconds.
+    pub cap: u64,
+    /// Fraction of every delay drawn at random, so that the retries of the Nodes spread out.
+    pub jitter: f64,
+    /// Milliseconds after which a push is abandoned, 0 never abandoning it.
+    pub timeout: u64,
+}
+
+impl RetrySettings {
+    /// The delay before a retry, drawn between the delay of the schedule minus its jitter, and the
+    /// delay of the schedule.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of retries so far.
+    ///
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Constant => self.base,
+            Backoff::Exponential => self
+                .base
+                .saturating_mul(1 << attempt.min(32))
+                .min(self.cap.max(self.base)),
+        };
+        let low = delay - (delay as f64 * self.jitter.clamp(0.0, 1.0)) as u64;
+        if low < delay {
+            Duration::from_millis(rand::thread_rng().gen_range(low..=delay))
+        } else {
+            Duration::from_millis(delay)
+        }
+    }
+}
+
+/// The RetrySettings are the schedule of the BestEffort pushes, every push following its own agent.
+impl SleepSchedule for RetrySettings {
+    fn agent(&self) -> Box<dyn SleepAgent> {
+        Box::new(RetryAgent {
+            settings: *self,
+            attempt: 0,
+        })
+    }
+}
+
+/// The retries of a single BestEffort push : the delay of every retry is drawn again, jitter included.
+struct RetryAgent {
+    settings: RetrySettings,
+    attempt: u32,
+}
+
+#[async_trait]
+impl SleepAgent for RetryAgent {
+    async fn step(&mut self) {
+        tokio::time::sleep(self.settings.delay(self.attempt)).await;
+        self.attempt = self.attempt.saturating_add(1);
+    }
+}
+
+/// Counters of the pushes abandoned by a Node once their timeout expired, per kind of Message,
+/// reported on the control socket.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PushCounters {
+    pub abandoned: BTreeMap<String, usize>,
+}
+
+/// Pushes of a Node : they are retried following its RetrySettings, until every peer acknowledged them
+/// or their timeout expires. The counters are shared by every topic and epoch of the Node.
+#[derive(Clone)]
+pub struct Pusher {
+    pub settings: RetrySettings,
+    pub counters: Arc<Mutex<PushCounters>>,
+}
+
+impl Pusher {
+    pub fn new(settings: RetrySettings) -> Self {
+        Pusher {
+            settings,
+            counters: Arc::new(Mutex::new(PushCounters::default())),
+        }
+    }
+
+    /// Push a signed Message to the given peers, until every one strongly acknowledged it. Returns false
+    /// if the push was abandoned.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of the Message, under which an abandoned push is counted.
+    /// * `node_sender` - The Node's Sender used to send the Message.
+    /// * `peers` - The peers to push the Message to.
+    /// * `message` - The signed Message to push.
+    ///
+    pub async fn best_effort(
+        &self,
+        kind: &str,
+        node_sender: Sender<SignedMessage>,
+        peers: Vec<Identity>,
+        message: SignedMessage,
+    ) -> bool {
+        let push_settings = PushSettings {
+            stop_condition: Acknowledgement::Strong,
+            retry_schedule: Arc::new(self.settings),
+        };
+        let settings: BestEffortSettings = BestEffortSettings { push_settings };
+        let best_effort = BestEffort::new(node_sender, peers, message, settings);
+        if self.settings.timeout == 0 {
+            best_effort.complete().await;
+            return true;
+        }
+        let timeout = Duration::from_millis(self.settings.timeout);
+        if tokio::time::timeout(timeout, best_effort.complete())
+            .await
+            .is_ok()
+        {
+            return true;
+        }
+        self.abandon(kind).await;
+        false
+    }
+
+    /// Send a signed Message to a single peer, retrying until it is sent. Returns false if the send was
+    /// abandoned.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of the Message, under which an abandoned send is counted.
+    /// * `node_sender` - The Node's Sender used to send the Message.
+    /// * `to` - The peer to send the Message to.
+    /// * `message` - The signed Message to send.
+    ///
+    pub async fn send(
+        &self,
+        kind: &str,
+        node_sender: &Sender<SignedMessage>,
+        to: Identity,
+        message: SignedMessage,
+    ) -> bool {
+        let start = Instant::now();
+        let timeout = Duration::from_millis(self.settings.timeout);
+        let mut attempt = 0;
+        loop {
+            match node_sender.send(to, message.clone()).await {
+                Ok(_) => {
+                    return true;
+                }
+                Err(e) => {
+                    println!("ERROR : {} send : {}", kind, e);
+                }
+            }
+            let delay = self.settings.delay(attempt);
+            if self.settings.timeout > 0 && start.elapsed() + delay >= timeout {
+                self.abandon(kind).await;
+                return false;
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn abandon(&self, kind: &str) {
+        my_print!(format!(
+            "{} push abandoned after {} ms",
+            kind, self.settings.timeout
+        ));
+        let mut locked_counters = self.counters.lock().await;
+        *locked_counters
+            .abandoned
+            .entry(String::from(kind))
+            .or_insert(0) += 1;
+        drop(locked_counters);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(backoff: Backoff, jitter: f64) -> RetrySettings {
+        RetrySettings {
+            backoff,
+            base: 100,
+            cap: 1000,
+            jitter,
+            timeout: 0,
+        }
+    }
+
+    #[test]
+    fn exponential_delays_are_capped() {
+        let exponential = settings(Backoff::Exponential, 0.0);
+        let delays: Vec<u64> = (0..6)
+            .map(|attempt| exponential.delay(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(exponential.delay(u32::MAX), Duration::from_millis(1000));
+        let constant = settings(Backoff::Constant, 0.0);
+        assert_eq!(constant.delay(5), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jitter_stays_below_the_delay() {
+        let jittered = settings(Backoff::Exponential, 0.5);
+        for attempt in 0..6 {
+            let delay = settings(Backoff::Exponential, 0.0).delay(attempt);
+            for _ in 0..20 {
+                let drawn = jittered.delay(attempt);
+                assert!(drawn <= delay && drawn >= delay / 2);
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use talk::crypto::primitives::sign::Signature;
+use talk::crypto::{KeyCard, KeyChain, Statement};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Registrations of the shards, in the order the KeyCards registered.
+pub type Registrations = BTreeMap<u32, Vec<KeyCard>>;
+
+#[derive(Serialize)]
+pub enum RegistryHeader {
+    Registration,
+}
+
+/// What a Node signs to register its KeyCard in a shard, so that nobody else registers it.
+#[derive(Serialize, Deserialize)]
+pub struct Registration {
+    pub shard: u32,
+}
+
+impl Statement for Registration {
+    type Header = RegistryHeader;
+    const HEADER: RegistryHeader = RegistryHeader::Registration;
+}
+
+/// Request sent to the registry of the Rendez-Vous server, as a JSON object on its own line.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// Register a KeyCard in a shard, with the signature of its Registration by the KeyChain of the
+    /// KeyCard.
+    Register {
+        shard: u32,
+        keycard: KeyCard,
+        signature: Signature,
+    },
+    /// Get the KeyCards of a shard, once it is full.
+    Shard { shard: u32 },
+}
+
+/// Response of the registry, as a JSON object on its own line.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Registered,
+    Shard { keycards: Vec<KeyCard> },
+    Error { message: String },
+}
+
+/// Shards of the Rendez-Vous server. A KeyCard takes a slot of its shard only once it is admitted, so
+/// that a refused registration never fills a shard.
+#[derive(Debug)]
+pub struct Registry {
+    pub sizes: Vec<usize>,
+    pub registrations: Registrations,
+}
+
+impl Registry {
+    pub fn new(sizes: Vec<usize>) -> Self {
+        Registry {
+            sizes,
+            registrations: Registrations::new(),
+        }
+    }
+
+    /// The size of a shard, if it is configured.
+    pub fn size(&self, shard: u32) -> Option<usize> {
+        self.sizes.get(shard as usize).copied()
+    }
+
+    /// Register a KeyCard in a shard, if its KeyChain signed the Registration. Registering again is
+    /// accepted, a full shard refuses any new KeyCard.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard.
+    /// * `keycard` - The KeyCard to register.
+    /// * `signature` - The signature of the Registration by the KeyChain of the KeyCard.
+    ///
+    pub fn register(&mut self, shard: u32, keycard: KeyCard, signature: &Signature) -> Response {
+        if signature.verify(&keycard, &Registration { shard }).is_err() {
+            return Response::Error {
+                message: format!(
+                    "{:?} did not sign its registration in shard {}",
+                    keycard.identity(),
+                    shard
+                ),
+            };
+        }
+        self.admit(shard, keycard)
+    }
+
+    /// Register a KeyCard already authenticated in a shard, such as a KeyCard restored from a previous
+    /// run. Registering again is accepted, a full shard refuses any new KeyCard.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard.
+    /// * `keycard` - The KeyCard to register.
+    ///
+    pub fn admit(&mut self, shard: u32, keycard: KeyCard) -> Response {
+        let size = match self.size(shard) {
+            Some(size) => size,
+            None => {
+                return Response::Error {
+                    message: format!("Shard {} is not configured", shard),
+                };
+            }
+        };
+        let keycards = self.registrations.entry(shard).or_default();
+        if keycards.iter().any(|k| k.identity() == keycard.identity()) {
+            return Response::Registered;
+        }
+        if keycards.len() >= size {
+            return Response::Error {
+                message: format!("Shard {} is full", shard),
+            };
+        }
+        keycards.push(keycard);
+        Response::Registered
+    }
+
+    /// The KeyCards of a shard, if it is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - The shard.
+    ///
+    pub fn shard(&self, shard: u32) -> Response {
+        let registered = self.registrations.get(&shard).map(|k| k.len()).unwrap_or(0);
+        match self.size(shard) {
+            Some(size) if registered >= size => Response::Shard {
+                keycards: self.registrations[&shard].clone(),
+            },
+            Some(size) => Response::Error {
+                message: format!("Shard {} is not complete : {}/{}", shard, registered, size),
+            },
+            None => Response::Error {
+                message: format!("Shard {} is not configured", shard),
+            },
+        }
+    }
+}
+
+/// Send a request to the registry and read its response.
+///
+/// # Arguments
+///
+/// * `addr` - The address of the Rendez-Vous server.
+/// * `port` - The port of its registry.
+/// * `request` - The request.
+///
+async fn call(addr: &str, port: u16, request: &Request) -> Result<Response, String> {
+    let stream = TcpStream::connect((addr, port))
+        .await
+        .map_err(|e| format!("registry {}:{} : {}", addr, port, e))?;
+    let (read_half, mut write_half) = stream.into_split();
+    let mut line = serde_json::to_string(request).unwrap();
+    line.push('\n');
+    write_half
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| format!("registry write : {}", e))?;
+    match BufReader::new(read_half).lines().next_line().await {
+        Ok(Some(line)) => {
+            serde_json::from_str(&line).map_err(|e| format!("registry response : {}", e))
+        }
+        _ => Err(String::from("registry closed the connection")),
+    }
+}
+
+/// Register the KeyCard of a KeyChain in a shard of the registry.
+///
+/// # Arguments
+///
+/// * `addr` - The address of the Rendez-Vous server.
+/// * `port` - The port of its registry.
+/// * `shard` - The shard.
+/// * `keychain` - The KeyChain whose KeyCard is registered, which signs the Registration.
+///
+pub async fn register(
+    addr: &str,
+    port: u16,
+    shard: u32,
+    keychain: &KeyChain,
+) -> Result<(), String> {
+    let request = Request::Register {
+        shard,
+        keycard: keychain.keycard(),
+        signature: keychain.sign(&Registration { shard }).unwrap(),
+    };
+    match call(addr, port, &request).await? {
+        Response::Registered => Ok(()),
+        Response::Error { message } => Err(message),
+        response => Err(format!("unexpected response {:?}", response)),
+    }
+}
+
+/// Get the KeyCards of a full shard of the registry.
+///
+/// # Arguments
+///
+/// * `addr` - The address of the Rendez-Vous server.
+/// * `port` - The port of its registry.
+/// * `shard` - The shard.
+///
+pub async fn get_shard(addr: &str, port: u16, shard: u32) -> Result<Vec<KeyCard>, String> {
+    match call(addr, port, &Request::Shard { shard }).await? {
+        Response::Shard { keycards } => Ok(keycards),
+        Response::Error { message } => Err(message),
+        response => Err(format!("unexpected response {:?}", response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_shards_refuse_new_keycards() {
+        let mut registry = Registry::new(vec![2]);
+        let keycards: Vec<KeyCard> = (0..3).map(|_| KeyChain::random().keycard()).collect();
+        assert!(matches!(
+            registry.admit(0, keycards[0].clone()),
+            Response::Registered
+        ));
+        assert!(matches!(registry.shard(0), Response::Error { .. }));
+        // Registering again does not take another slot.
+        assert!(matches!(
+            registry.admit(0, keycards[0].clone()),
+            Response::Registered
+        ));
+        assert!(matches!(
+            registry.admit(0, keycards[1].clone()),
+            Response::Registered
+        ));
+        assert!(matches!(
+            registry.admit(0, keycards[2].clone()),
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            registry.admit(1, keycards[2].clone()),
+            Response::Error { .. }
+        ));
+        match registry.shard(0) {
+            Response::Shard { keycards: shard } => assert_eq!(shard.len(), 2),
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[test]
+    fn registrations_are_signed_by_their_keychain() {
+        let mut registry = Registry::new(vec![2, 2]);
+        let (keychain, other) = (KeyChain::random(), KeyChain::random());
+        let sign =
+            |keychain: &KeyChain, shard: u32| keychain.sign(&Registration { shard }).unwrap();
+        // Nobody else registers the public KeyCard of a Node, nor in another shard than it signed.
+        assert!(matches!(
+            registry.register(0, keychain.keycard(), &sign(&other, 0)),
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            registry.register(1, keychain.keycard(), &sign(&keychain, 0)),
+            Response::Error { .. }
+        ));
+        assert!(registry.registrations.values().all(|k| k.is_empty()));
+        assert!(matches!(
+            registry.register(0, keychain.keycard(), &sign(&keychain, 0)),
+            Response::Registered
+        ));
+    }
+}
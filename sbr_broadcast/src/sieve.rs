@@ -1,14 +1,14 @@
-use crate::contagion;
+use crate::journal::{record, Journal, Record};
+use crate::layer::BroadcastLayer;
 use crate::message::{Message, SignedMessage};
 use crate::message_headers::{Echo, EchoSubscription};
+use crate::push::Pusher;
 use crate::utils::{check_message_occurrences_sieve, sample_sieve};
+use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use talk::broadcast::{BestEffort, BestEffortSettings};
 use talk::crypto::{Identity, KeyCard, KeyChain};
-use talk::time::sleep_schedules::Constant;
-use talk::unicast::{Acknowledgement, PushSettings, Sender};
+use talk::unicast::Sender;
 use tokio::sync::Mutex;
 
 /// Initialises the Echo set used in the Sieve algorithm. Sample randomly a number of peers from the system
@@ -36,106 +36,170 @@ pub async fn init(
 ///
 /// * `keychain` - KeyChain used to sign the Message.
 /// * `node_sender` - The Node's Sender used to send Messages.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `echo_replies` - The Echo replies used to get the Echo peers.
+/// * `topic` - The topic the peers are sampled for.
+/// * `epoch` - The epoch the peers are sampled in.
+/// * `incarnation` - The number of times the Node restarted, which lets the peers accept the
+///   subscriptions of a restarted Node.
 ///
 pub async fn echo_subscribe(
     keychain: KeyChain,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     echo_replies: HashMap<Identity, Option<Message>>,
+    topic: u32,
+    epoch: u64,
+    incarnation: u64,
 ) {
-    let push_settings = PushSettings {
-        stop_condition: Acknowledgement::Strong,
-        retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-    };
-    let settings: BestEffortSettings = BestEffortSettings { push_settings };
     // Collect Identities to which a Subscription is sent.
     let peers: Vec<Identity> = echo_replies.into_keys().collect();
-    let msg = Message::new(4, String::from("EchoSubscription"));
+    let msg = Message::new(4, String::from("EchoSubscription"))
+        .in_topic(topic)
+        .in_epoch(epoch)
+        .in_incarnation(incarnation);
     let signature = keychain.sign(&EchoSubscription(msg.clone())).unwrap();
     let signed_msg = SignedMessage::new(msg, signature);
-    let best_effort = BestEffort::new(node_sender.clone(), peers, signed_msg, settings);
-    best_effort.complete().await;
+    pusher
+        .best_effort("echo_subscribe", node_sender, peers, signed_msg)
+        .await;
     my_print!("Finished Sieve Subscriptions");
 }
 
-/// Deliver an EchoSubscription type Message. If an Echo Message has already been delivered, send
-/// it to the subscribing Node.
+/// Deliver an EchoSubscription type Message. Send the Echo Messages already delivered, one per
+/// instance, to the subscribing Node.
 ///
 /// # Arguments
 ///
 /// * `keychain` - KeyChain used to sign the Message.
 /// * `node_sender` - The Node's Sender used to send Messages.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
 /// * `from` - The Identity of the Node subscribing.
-/// * `delivered_echo` - The status of the delivered Echo Message.
+/// * `delivered_echoes` - The Echo Messages delivered so far.
 /// * `echo_peers` - The Atomic Reference Counter to the Echo peers to update.
 ///
 pub async fn echo_subscription(
     keychain: KeyChain,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     from: Identity,
-    delivered_echo: Option<Message>,
+    delivered_echoes: Vec<Message>,
     echo_subscribers: Arc<Mutex<Vec<Identity>>>,
 ) {
-    if delivered_echo.is_some() {
-        let echo_message: Message = delivered_echo.unwrap();
-        let msg: Message = Message::new(1, echo_message.content.clone());
+    for echo_message in delivered_echoes.into_iter() {
+        let msg: Message = echo_message.with_type(1);
         let signature = keychain.sign(&Echo(msg.clone())).unwrap();
         let signed_msg = SignedMessage::new(msg, signature);
-        loop {
-            let r = node_sender.send(from, signed_msg.clone()).await;
-            match r {
-                Ok(_) => {
-                    break;
-                }
-                Err(e) => {
-                    println!("ERROR : echo_subscription send : {}", e);
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                }
-            }
-        }
+        pusher
+            .send("echo_subscription", &node_sender, from, signed_msg)
+            .await;
     }
     let mut locked_echo_subscribers = echo_subscribers.lock().await;
-    locked_echo_subscribers.push(from);
+    if !locked_echo_subscribers.contains(&from) {
+        locked_echo_subscribers.push(from);
+    }
     drop(locked_echo_subscribers);
 }
 
-/// Probabilistic Broadcast Deliver. If the Message is verified, send an Echo of the Message to the
-/// Echo peers.
+/// Sieve, the Probabilistic Consistent Broadcast layer of an instance : the Messages delivered by
+/// Murmur are echoed to the Echo subscribers, and a Message echoed by enough Echo peers is delivered to
+/// the layer above.
+pub struct SieveLayer {
+    pub keychain: KeyChain,
+    pub node_sender: Sender<SignedMessage>,
+    pub pusher: Pusher,
+    pub echo_subscribers: Vec<Identity>,
+    pub echo: Arc<Mutex<Option<Message>>>,
+    pub echo_replies: Arc<Mutex<HashMap<Identity, Option<Message>>>>,
+    pub duplicate_echo: HashMap<Identity, usize>,
+    pub delivered_echo: Arc<Mutex<Option<Message>>>,
+    pub e_thr: usize,
+    pub journal: Option<Arc<Journal>>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+impl SieveLayer {
+    /// Handle an Echo received from a peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The verified Echo Message.
+    /// * `from` - The Identity of the Node sending the Echo.
+    ///
+    pub async fn echo_received(&self, message: Message, from: Identity) {
+        deliver_echo(
+            message,
+            from,
+            self.echo.clone(),
+            self.echo_replies.clone(),
+            self.duplicate_echo.clone(),
+            self.delivered_echo.clone(),
+            self.e_thr,
+            self.upper.clone(),
+        )
+        .await;
+    }
+}
+
+#[async_trait]
+impl BroadcastLayer for SieveLayer {
+    /// Echo a Message delivered by Murmur.
+    async fn deliver(&self, message: Message) {
+        deliver(
+            self.keychain.clone(),
+            message,
+            self.node_sender.clone(),
+            self.pusher.clone(),
+            self.echo.clone(),
+            self.echo_subscribers.clone(),
+            self.journal.clone(),
+        )
+        .await;
+    }
+}
+
+/// Probabilistic Broadcast Deliver. If the Message is verified and the Node has not echoed yet, send an
+/// Echo of the Message to the Echo peers. The Echo is journaled before it is set and sent, and neither
+/// if it cannot be journaled.
 ///
 /// # Arguments
 ///
 /// * `keychain` - KeyChain used to sign the Message.
-/// * `signed_msg` - The signed Message to deliver.
+/// * `message` - The Message to deliver.
 /// * `node_sender` - The Node's Sender used to send the Gossip Subscription to the peers.
-/// * `delivered_echo` - The Atomic Reference Counter to the status of the delivered Echo Message.
-/// * `echo_peers` - The Echo peers to which the Echo is sent.
+/// * `pusher` - The pushes of the Node, with their retry schedule and timeout.
+/// * `echo` - The Atomic Reference Counter to the status of the Echo Message.
+/// * `echo_subscribers` - The Echo peers to which the Echo is sent.
+/// * `journal` - The journal of the Node, if it keeps one.
 ///
 pub async fn deliver(
     keychain: KeyChain,
-    signed_msg: SignedMessage,
+    message: Message,
     node_sender: Sender<SignedMessage>,
+    pusher: Pusher,
     echo: Arc<Mutex<Option<Message>>>,
     echo_subscribers: Vec<Identity>,
+    journal: Option<Arc<Journal>>,
 ) {
-    let recv_msg = Some(signed_msg.clone().get_message());
     let mut locked_echo = echo.lock().await;
-    *locked_echo = recv_msg;
+    // A Node echoes a single Message per instance.
+    if locked_echo.is_some() {
+        return;
+    }
+    let msg: Message = message.with_type(1);
+    if msg.instance.is_some() {
+        let message = msg.clone();
+        if !record(&journal, Record::Echo { message }).await {
+            return;
+        }
+    }
+    *locked_echo = Some(message);
     drop(locked_echo);
-    let msg: Message = Message::new(1, signed_msg.clone().get_message().content);
     let signature = keychain.sign(&Echo(msg.clone())).unwrap();
     let signed_echo: SignedMessage = SignedMessage::new(msg, signature);
-    let push_settings = PushSettings {
-        stop_condition: Acknowledgement::Strong,
-        retry_schedule: Arc::new(Constant::new(Duration::from_millis(100))),
-    };
-    let settings: BestEffortSettings = BestEffortSettings { push_settings };
-    let best_effort = BestEffort::new(
-        node_sender.clone(),
-        echo_subscribers,
-        signed_echo.clone(),
-        settings,
-    );
-    best_effort.complete().await;
+    pusher
+        .best_effort("echo", node_sender, echo_subscribers, signed_echo)
+        .await;
 }
 
 /// Deliver an Echo type Message. Save the Echo Message in the Echo replies, used to track when a Message
@@ -143,29 +207,24 @@ pub async fn deliver(
 ///
 /// # Arguments
 ///
-/// * `keychain` - KeyChain used to sign the Message.
 /// * `message` - The Message of the Message (Without the prepending type).
 /// * `from` - The Identity of the Node sending the Echo.
+/// * `echo` - The Atomic Reference Counter to the status of the Echo Message.
 /// * `echo_replies` - The Atomic Reference Counter to the Echo replies received.
 /// * `duplicate_echo` - The HashMap containing information on Echo peers sampled multiple times.
-/// * `node_sender` - The Node's Sender used to send the Gossip Subscription to the peers.
 /// * `delivered_echo` - The Atomic Reference Counter to the status of the delivered Echo Message.
 /// * `e_thr` - The threshold defining if enough Echo replies have been received.
-/// * `ready_peers` - The Ready peers (used by Contagion).
-/// * `ready_messages` - The Atomic Reference Counter to the vector of all Messages which are ready.
+/// * `upper` - The layer above Sieve, Contagion or the application.
 ///
 pub async fn deliver_echo(
-    keychain: KeyChain,
     message: Message,
     from: Identity,
     echo: Arc<Mutex<Option<Message>>>,
     echo_replies: Arc<Mutex<HashMap<Identity, Option<Message>>>>,
     duplicate_echo: HashMap<Identity, usize>,
-    node_sender: Sender<SignedMessage>,
     delivered_echo: Arc<Mutex<Option<Message>>>,
     e_thr: usize,
-    ready_peers: Vec<Identity>,
-    ready_messages: Arc<Mutex<Vec<Message>>>,
+    upper: Arc<dyn BroadcastLayer>,
 ) {
     if echo_replies.lock().await.contains_key(&from) {
         if echo_replies.lock().await.get(&from).unwrap().is_none() {
@@ -176,62 +235,47 @@ pub async fn deliver_echo(
         }
 
         check_echoes(
-            keychain.clone(),
-            node_sender.clone(),
             echo,
             delivered_echo.clone(),
             e_thr,
-            ready_peers.clone(),
             echo_replies,
             duplicate_echo,
-            ready_messages.clone(),
+            upper,
         )
         .await;
     }
 }
 
 /// Check the status of the Echo replies received. If more than the threshold have been
-/// received, Probabilistic Consistent Broadcast deliver the Message.
+/// received, Probabilistic Consistent Broadcast deliver the Message to the layer above.
 ///
 /// # Arguments
 ///
-/// * `keychain` - KeyChain used to sign the Message.
-/// * `node_sender` - The Node's Sender used to send the Gossip Subscription to the peers.
+/// * `echo` - The Atomic Reference Counter to the status of the Echo Message.
 /// * `delivered_echo` - The Atomic Reference Counter to the status of the delivered Echo Message.
 /// * `e_thr` - The threshold defining if enough Echo replies have been received.
-/// * `ready_peers` - The Ready peers (used by Contagion).
 /// * `echo_replies` - The Echo replies received.
 /// * `duplicate_echo` - The HashMap containing information on Echo peers sampled multiple times.
-/// * `ready_messages` - The Atomic Reference Counter to the vector of all Messages which are ready.
+/// * `upper` - The layer above Sieve, Contagion or the application.
 ///
 pub async fn check_echoes(
-    keychain: KeyChain,
-    node_sender: Sender<SignedMessage>,
     echo: Arc<Mutex<Option<Message>>>,
     delivered_echo: Arc<Mutex<Option<Message>>>,
     e_thr: usize,
-    ready_peers: Vec<Identity>,
     echo_replies: Arc<Mutex<HashMap<Identity, Option<Message>>>>,
     duplicate_echo: HashMap<Identity, usize>,
-    ready_messages: Arc<Mutex<Vec<Message>>>,
+    upper: Arc<dyn BroadcastLayer>,
 ) {
     if delivered_echo.lock().await.is_none() && echo.lock().await.is_some() {
         let echo_replies: HashMap<Identity, Option<Message>> = echo_replies.lock().await.clone();
         let occ = check_message_occurrences_sieve(echo_replies, duplicate_echo, echo.clone()).await;
         if occ >= e_thr {
-            let msg = Message::new(1, echo.lock().await.as_ref().unwrap().content.clone());
+            let msg = echo.lock().await.as_ref().unwrap().with_type(1);
             let echo = Some(msg.clone());
             let mut locked_delivered_echo = delivered_echo.lock().await;
             *locked_delivered_echo = echo.clone();
             drop(locked_delivered_echo);
-            contagion::deliver(
-                keychain,
-                msg.clone(),
-                node_sender.clone(),
-                ready_peers,
-                ready_messages,
-            )
-            .await;
+            upper.deliver(msg).await;
             return;
         }
     }
@@ -0,0 +1,390 @@
+use crate::layer::BroadcastLayer;
+use crate::message::Message;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Payload writing a value in the key-value view of the store.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Put {
+    pub key: String,
+    pub value: String,
+}
+
+/// Entry of the log of a source : the content delivered in one of its instances.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LogEntry {
+    pub sequence: u64,
+    pub content: String,
+}
+
+/// How far ahead of the log of its source an entry can be delivered and wait for the missing ones.
+const WINDOW: u64 = 1024;
+
+/// Append-only log of the entries delivered by a Node, per source, and the key-value view derived from
+/// it. The log of a source is contiguous : an entry is appended once every previous sequence of its
+/// source is, and waits until then. The value of a key is written by the Put with the highest position
+/// in the log of its source, sources breaking ties, so that the view only depends on the entries
+/// delivered and not on the order of the deliveries, and no source can claim a position ahead of its
+/// previous entries.
+#[derive(Debug, Default)]
+pub struct Store {
+    log: HashMap<String, Vec<String>>,
+    pending: HashMap<String, BTreeMap<u64, String>>,
+    view: HashMap<String, (u64, String, String)>,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store::default()
+    }
+
+    /// The value of a key in the key-value view.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.view.get(key).map(|(_, _, value)| value.clone())
+    }
+
+    /// The log of every source, ordered by source and sequence.
+    pub fn log(&self) -> BTreeMap<String, Vec<LogEntry>> {
+        self.log
+            .iter()
+            .map(|(source, entries)| {
+                let entries = entries
+                    .iter()
+                    .enumerate()
+                    .map(|(sequence, content)| LogEntry {
+                        sequence: sequence as u64,
+                        content: content.clone(),
+                    })
+                    .collect();
+                (source.clone(), entries)
+            })
+            .collect()
+    }
+
+    /// Append a delivered Message to the log of its source, and apply it to the view if it is a Put,
+    /// with the entries which were waiting for it. Returns whether it was appended or waits : an
+    /// instance already in the log is never replaced, and an entry too far ahead of its log is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the running Node, used for debug purpose.
+    /// * `message` - The delivered Message.
+    ///
+    pub fn push(&mut self, id: usize, message: &Message) -> bool {
+        let instance = match message.instance {
+            Some(instance) => instance,
+            None => {
+                return false;
+            }
+        };
+        let source = format!("{:?}", instance.source);
+        let entries = self.log.entry(source.clone()).or_insert(Vec::new());
+        let next = entries.len() as u64;
+        let pending = self
+            .pending
+            .entry(source.clone())
+            .or_insert(BTreeMap::new());
+        let existing = if instance.sequence < next {
+            Some(&entries[instance.sequence as usize])
+        } else {
+            pending.get(&instance.sequence)
+        };
+        if let Some(content) = existing {
+            if *content != message.content {
+                println!(
+                    "ERROR : <{}> conflicting entry {} from {} kept out of the log",
+                    id, instance.sequence, source
+                );
+            }
+            return false;
+        }
+        if instance.sequence >= next + WINDOW {
+            println!(
+                "ERROR : <{}> entry {} from {} is too far ahead of its log, at {}",
+                id, instance.sequence, source, next
+            );
+            return false;
+        }
+        pending.insert(instance.sequence, message.content.clone());
+
+        let mut next = next;
+        while let Some(content) = pending.remove(&next) {
+            entries.push(content.clone());
+            if let Ok(put) = serde_json::from_str::<Put>(&content) {
+                let version = (next, source.clone());
+                let newer = match self.view.get(&put.key) {
+                    Some((sequence, source, _)) => version > (*sequence, source.clone()),
+                    None => true,
+                };
+                if newer {
+                    self.view.insert(put.key, (version.0, version.1, put.value));
+                }
+            }
+            next += 1;
+        }
+        true
+    }
+}
+
+/// Replicated log and key-value store : appends the Messages delivered below it to the store of the
+/// Node, and passes them on to the application.
+pub struct StoreLayer {
+    pub id: usize,
+    pub store: Arc<Mutex<Store>>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+#[async_trait]
+impl BroadcastLayer for StoreLayer {
+    async fn deliver(&self, message: Message) {
+        self.store.lock().await.push(self.id, &message);
+        self.upper.deliver(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bracha::{BrachaState, Quorums, Step};
+    use crate::message::Instance;
+    use rand::prelude::*;
+    use talk::crypto::{Identity, KeyChain};
+
+    fn put(key: &str, value: &str) -> String {
+        serde_json::to_string(&Put {
+            key: String::from(key),
+            value: String::from(value),
+        })
+        .unwrap()
+    }
+
+    /// Bracha Message in flight between two Nodes of the simulation.
+    struct InFlight {
+        to: usize,
+        from: usize,
+        step: u8,
+        instance: Instance,
+        content: String,
+    }
+
+    /// Run Bracha in memory between n Nodes, the first ones being Byzantine, delivering the Messages
+    /// in a random order, and return the stores of the correct Nodes. Every Node writes the same keys
+    /// in 3 instances. The Byzantine sources send a different Put to every peer, and the Byzantine
+    /// Nodes echo and ready a different content to every peer in every instance.
+    fn simulate(n: usize, byzantine: usize, rng: &mut ThreadRng) -> Vec<Store> {
+        let identities: Vec<Identity> = (0..n)
+            .map(|_| KeyChain::random().keycard().identity())
+            .collect();
+        let quorums = Quorums::new(n);
+        let mut states: Vec<HashMap<Instance, BrachaState>> = vec![HashMap::new(); n];
+        let mut stores: Vec<Store> = (0..n).map(|_| Store::new()).collect();
+        let mut in_flight = Vec::new();
+
+        for (source, identity) in identities.iter().enumerate() {
+            for sequence in 0..3 {
+                let instance = Instance {
+                    source: *identity,
+                    sequence,
+                };
+                for to in 0..n {
+                    let content = if source < byzantine {
+                        put("k", &format!("forged {}", to))
+                    } else {
+                        put(
+                            &format!("k{}", sequence % 2),
+                            &format!("{} {}", source, sequence),
+                        )
+                    };
+                    in_flight.push(InFlight {
+                        to,
+                        from: source,
+                        step: 10,
+                        instance,
+                        content,
+                    });
+                }
+                for from in 0..byzantine {
+                    for to in byzantine..n {
+                        for step in [11, 12] {
+                            in_flight.push(InFlight {
+                                to,
+                                from,
+                                step,
+                                instance,
+                                content: put("k", &format!("forged {}", to)),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        while !in_flight.is_empty() {
+            let message = in_flight.swap_remove(rng.gen_range(0..in_flight.len()));
+            if message.to < byzantine {
+                continue;
+            }
+            let own = identities[message.to];
+            let from = identities[message.from];
+            let state = states[message.to].entry(message.instance).or_default();
+            let steps = match message.step {
+                10 if message.instance.source == from => {
+                    state.on_send(own, &message.content, quorums)
+                }
+                11 => state.on_echo(own, from, &message.content, quorums),
+                12 => state.on_ready(own, from, &message.content, quorums),
+                _ => Vec::new(),
+            };
+            for step in steps.into_iter() {
+                let (step, content) = match step {
+                    Step::Echo(content) => (11, content),
+                    Step::Ready(content) => (12, content),
+                    Step::Deliver(content) => {
+                        let delivered = Message::with_instance(12, message.instance, content);
+                        assert!(stores[message.to].push(message.to, &delivered));
+                        continue;
+                    }
+                };
+                for to in (0..n).filter(|to| *to != message.to) {
+                    in_flight.push(InFlight {
+                        to,
+                        from: message.to,
+                        step,
+                        instance: message.instance,
+                        content: content.clone(),
+                    });
+                }
+            }
+        }
+        stores.split_off(byzantine)
+    }
+
+    #[test]
+    fn correct_nodes_converge_with_byzantine_nodes() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let stores = simulate(7, 2, &mut rng);
+            let log = stores[0].log();
+            // Every instance of the correct sources is in the log.
+            assert_eq!(log.values().filter(|entries| entries.len() == 3).count(), 5);
+            for store in stores.iter() {
+                assert_eq!(store.log(), log);
+                for key in ["k", "k0", "k1"] {
+                    assert_eq!(store.get(key), stores[0].get(key));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn view_does_not_depend_on_the_delivery_order() {
+        let a = KeyChain::random().keycard().identity();
+        let b = KeyChain::random().keycard().identity();
+        let messages = vec![
+            Message::with_instance(
+                2,
+                Instance {
+                    source: a,
+                    sequence: 0,
+                },
+                put("k", "a0"),
+            ),
+            Message::with_instance(
+                2,
+                Instance {
+                    source: a,
+                    sequence: 1,
+                },
+                put("k", "a1"),
+            ),
+            Message::with_instance(
+                2,
+                Instance {
+                    source: b,
+                    sequence: 1,
+                },
+                put("k", "b1"),
+            ),
+            Message::with_instance(
+                2,
+                Instance {
+                    source: b,
+                    sequence: 0,
+                },
+                String::from("hello"),
+            ),
+        ];
+        // a1 and b1 have the same sequence, the highest source wins.
+        let last = if format!("{:?}", a) > format!("{:?}", b) {
+            "a1"
+        } else {
+            "b1"
+        };
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let mut shuffled = messages.clone();
+            shuffled.shuffle(&mut rng);
+            let mut store = Store::new();
+            for message in shuffled.iter() {
+                assert!(store.push(0, message));
+            }
+            assert_eq!(store.get("k"), Some(String::from(last)));
+            assert_eq!(store.log()[&format!("{:?}", b)][0].content, "hello");
+        }
+    }
+
+    #[test]
+    fn entries_are_never_replaced() {
+        let source = KeyChain::random().keycard().identity();
+        let instance = Instance {
+            source,
+            sequence: 0,
+        };
+        let mut store = Store::new();
+        assert!(store.push(0, &Message::with_instance(2, instance, put("k", "v"))));
+        assert!(!store.push(0, &Message::with_instance(2, instance, put("k", "w"))));
+        assert_eq!(store.get("k"), Some(String::from("v")));
+        assert_eq!(store.log()[&format!("{:?}", source)].len(), 1);
+    }
+
+    #[test]
+    fn claimed_sequences_do_not_pin_keys() {
+        let (a, b) = (
+            KeyChain::random().keycard().identity(),
+            KeyChain::random().keycard().identity(),
+        );
+        let instance = |source: Identity, sequence: u64| Instance { source, sequence };
+        let mut store = Store::new();
+        // A Put far ahead of the log of its source is dropped.
+        let pinned = Message::with_instance(2, instance(b, u64::MAX), put("k", "b"));
+        assert!(!store.push(0, &pinned));
+        // A Put ahead of the log waits for the previous entries of its source.
+        assert!(store.push(
+            0,
+            &Message::with_instance(2, instance(b, 2), put("k", "b2"))
+        ));
+        assert_eq!(store.get("k"), None);
+        assert!(store.push(
+            0,
+            &Message::with_instance(2, instance(a, 0), put("k", "a0"))
+        ));
+        assert!(store.push(
+            0,
+            &Message::with_instance(2, instance(a, 1), put("k", "a1"))
+        ));
+        assert_eq!(store.get("k"), Some(String::from("a1")));
+        assert!(store.push(
+            0,
+            &Message::with_instance(2, instance(b, 0), put("j", "b0"))
+        ));
+        assert!(store.push(
+            0,
+            &Message::with_instance(2, instance(b, 1), put("j", "b1"))
+        ));
+        assert_eq!(store.log()[&format!("{:?}", b)].len(), 3);
+        assert_eq!(store.get("k"), Some(String::from("b2")));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+#[derive(Default)]
+struct Running {
+    next: u64,
+    stopped: bool,
+    handles: HashMap<u64, JoinHandle<()>>,
+}
+
+/// The tasks of a Node still running, shared by its topics, its epochs and the layers of its instances,
+/// so that every task spawned on behalf of the Node is cancelled when it stops. A task removes itself
+/// from the set once it finishes. Once cancelled, the set spawns no more tasks.
+#[derive(Clone, Default)]
+pub struct Tasks {
+    running: Arc<Mutex<Running>>,
+}
+
+impl Tasks {
+    /// Spawn a task, tracked until it finishes. The task is dropped if the set is cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The task to spawn.
+    ///
+    pub async fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // The set stays locked until the task is inserted, so that it cannot remove itself before.
+        let mut locked_running = self.running.lock().await;
+        if locked_running.stopped {
+            return;
+        }
+        let id = locked_running.next;
+        locked_running.next += 1;
+        let running = self.running.clone();
+        let handle = tokio::spawn(async move {
+            task.await;
+            running.lock().await.handles.remove(&id);
+        });
+        locked_running.handles.insert(id, handle);
+        drop(locked_running);
+    }
+
+    /// Cancel the tasks still running, such as pushes waiting for an acknowledgement, and stop spawning
+    /// new ones. Returns the number of cancelled tasks.
+    pub async fn cancel(&self) -> usize {
+        let mut locked_running = self.running.lock().await;
+        locked_running.stopped = true;
+        let handles: Vec<JoinHandle<()>> = locked_running
+            .handles
+            .drain()
+            .map(|(_, handle)| handle)
+            .collect();
+        drop(locked_running);
+        let mut cancelled = 0;
+        for handle in handles.into_iter() {
+            if !handle.is_finished() {
+                handle.abort();
+                cancelled += 1;
+            }
+            let _ = handle.await;
+        }
+        cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    async fn running(tasks: &Tasks) -> usize {
+        tasks.running.lock().await.handles.len()
+    }
+
+    #[tokio::test]
+    async fn finished_tasks_leave_the_set() {
+        let tasks = Tasks::default();
+        for _ in 0..8 {
+            tasks.spawn(async {}).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(running(&tasks).await, 0);
+    }
+
+    #[tokio::test]
+    async fn nested_tasks_are_cancelled() {
+        let tasks = Tasks::default();
+        let pushes = Arc::new(AtomicUsize::new(0));
+        let (nested, counter) = (tasks.clone(), pushes.clone());
+        tasks
+            .spawn(async move {
+                nested
+                    .spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .await;
+            })
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(running(&tasks).await, 1);
+        assert_eq!(tasks.cancel().await, 1);
+        // Once cancelled, no task is spawned anymore.
+        let counter = pushes.clone();
+        tasks
+            .spawn(async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(pushes.load(Ordering::SeqCst), 0);
+        assert_eq!(running(&tasks).await, 0);
+    }
+}
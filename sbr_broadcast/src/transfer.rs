@@ -0,0 +1,525 @@
+use crate::layer::BroadcastLayer;
+use crate::message::Message;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use talk::crypto::primitives::sign::Signature as SignSignature;
+use talk::crypto::{KeyCard, KeyChain};
+use tokio::sync::Mutex;
+
+/// The topic in which every transfer is broadcast, so that every Node processes them in the same
+/// order. The accounts are shared by every topic.
+pub const TRANSFER_TOPIC: u32 = 0;
+
+/// A transfer is identified by its account and its sequence number in that account.
+pub type TransferId = (String, u64);
+
+/// Transfer of an amount from the account of a source to another account. Every Node owns the
+/// account named after its Identity, and the sequence number of a transfer is the sequence of the
+/// instance which broadcasts it. The dependencies are the incoming transfers the source claims to
+/// fund it : an incoming transfer can only be spent once it is claimed, and only once.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Transfer {
+    pub from: String,
+    pub sequence: u64,
+    pub to: String,
+    pub amount: u64,
+    pub dependencies: Vec<TransferId>,
+}
+
+/// Transfer signed by the owner of the account it spends from, which is the only form in which a
+/// transfer is broadcast : the KeyCard of the owner names the account.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SignedTransfer {
+    pub transfer: Transfer,
+    pub owner: KeyCard,
+    pub signature: SignSignature,
+}
+
+impl SignedTransfer {
+    /// Sign a transfer as the owner of its account.
+    ///
+    /// # Arguments
+    ///
+    /// * `transfer` - The transfer to sign.
+    /// * `keychain` - The KeyChain of the owner.
+    ///
+    pub fn new(transfer: Transfer, keychain: &KeyChain) -> Self {
+        let signature = keychain.sign(&transfer).unwrap();
+        SignedTransfer {
+            transfer,
+            owner: keychain.keycard(),
+            signature,
+        }
+    }
+
+    /// Check that the transfer is signed by the owner of the account it spends from.
+    pub fn verify(&self) -> bool {
+        format!("{:?}", self.owner.identity()) == self.transfer.from
+            && self.signature.verify(&self.owner, &self.transfer).is_ok()
+    }
+}
+
+/// Balances of an account : the balance counts every transfer applied, the spendable balance only the
+/// incoming transfers claimed by the account.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Account {
+    pub account: String,
+    pub balance: u64,
+    pub spendable: u64,
+}
+
+/// The accounts replicated on a Node. The instances of every source are processed in the order of
+/// their sequence numbers, payloads which are not signed transfers included, so that a source cannot
+/// spend the same funds in two instances. A transfer is applied once its dependencies are processed, and
+/// validated only against its own account and its dependencies, so that every Node accepts and rejects
+/// the same transfers whatever the order of the deliveries.
+#[derive(Debug)]
+pub struct Ledger {
+    own: String,
+    initial: u64,
+    next: HashMap<String, u64>,
+    pending: HashMap<String, BTreeMap<u64, Option<SignedTransfer>>>,
+    applied: HashMap<TransferId, Transfer>,
+    claimed: HashSet<TransferId>,
+    accounts: HashMap<String, Account>,
+    prepared: BTreeMap<u64, Transfer>,
+    pub rejected: usize,
+}
+
+impl Ledger {
+    /// Create the ledger of a Node, in which every account starts with the same balance.
+    ///
+    /// # Arguments
+    ///
+    /// * `own` - The account of the Node.
+    /// * `initial` - The initial balance of every account.
+    ///
+    pub fn new(own: String, initial: u64) -> Self {
+        Ledger {
+            own,
+            initial,
+            next: HashMap::new(),
+            pending: HashMap::new(),
+            applied: HashMap::new(),
+            claimed: HashSet::new(),
+            accounts: HashMap::new(),
+            prepared: BTreeMap::new(),
+            rejected: 0,
+        }
+    }
+
+    /// The balances of an account, which holds the initial balance until a transfer involves it.
+    pub fn account(&self, account: &str) -> Account {
+        self.accounts.get(account).cloned().unwrap_or(Account {
+            account: account.to_string(),
+            balance: self.initial,
+            spendable: self.initial,
+        })
+    }
+
+    /// Every account involved in an applied transfer, ordered by name.
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self.accounts.values().cloned().collect();
+        accounts.sort_by(|a, b| a.account.cmp(&b.account));
+        accounts
+    }
+
+    /// Prepare a transfer from the account of the Node. It claims every incoming transfer not claimed
+    /// yet, by an applied or a reserved transfer, and is checked against the transfers reserved before
+    /// it. Its sequence is set once its instance is allocated.
+    ///
+    /// # Arguments
+    ///
+    /// * `to` - The account credited.
+    /// * `amount` - The amount transferred.
+    ///
+    pub fn prepare(&self, to: String, amount: u64) -> Result<Transfer, String> {
+        let reserved: HashSet<&TransferId> = self
+            .prepared
+            .values()
+            .flat_map(|t| t.dependencies.iter())
+            .collect();
+        let mut dependencies: Vec<TransferId> = self
+            .applied
+            .values()
+            .filter(|t| t.to == self.own)
+            .map(|t| (t.from.clone(), t.sequence))
+            .filter(|id| !self.claimed.contains(id) && !reserved.contains(id))
+            .collect();
+        dependencies.sort();
+        let incoming: u64 = dependencies.iter().map(|id| self.applied[id].amount).sum();
+        let available = self.prepared.values().fold(
+            self.account(&self.own).spendable + incoming,
+            |available, t| {
+                let claimed: u64 = t
+                    .dependencies
+                    .iter()
+                    .map(|id| self.applied[id].amount)
+                    .sum();
+                (available + claimed).saturating_sub(t.amount)
+            },
+        );
+        if amount > available {
+            return Err(format!(
+                "Insufficient balance : {} available, {} requested",
+                available, amount
+            ));
+        }
+        Ok(Transfer {
+            from: self.own.clone(),
+            sequence: 0,
+            to,
+            amount,
+            dependencies,
+        })
+    }
+
+    /// Reserve a prepared transfer until it is processed, so that the next transfers do not claim its
+    /// dependencies again.
+    ///
+    /// # Arguments
+    ///
+    /// * `transfer` - The transfer, with the sequence of the instance which broadcasts it.
+    ///
+    pub fn reserve(&mut self, transfer: Transfer) {
+        self.prepared.insert(transfer.sequence, transfer);
+    }
+
+    /// Release a reserved transfer which could not be broadcast, so that its dependencies can be
+    /// claimed by the next transfers.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The sequence of the instance of the transfer.
+    ///
+    pub fn release(&mut self, sequence: u64) {
+        self.prepared.remove(&sequence);
+    }
+
+    /// Record a delivered Message, and process every instance which can now be processed. Returns the
+    /// transfers processed, with whether they were applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the running Node, used for debug purpose.
+    /// * `message` - The delivered Message.
+    ///
+    pub fn push(&mut self, id: usize, message: &Message) -> Vec<(Transfer, bool)> {
+        let instance = match message.instance {
+            Some(instance) => instance,
+            None => {
+                return Vec::new();
+            }
+        };
+        let source = format!("{:?}", instance.source);
+        if instance.sequence < *self.next.get(&source).unwrap_or(&0) {
+            return Vec::new();
+        }
+        let transfer = serde_json::from_str::<SignedTransfer>(&message.content).ok();
+        self.pending
+            .entry(source)
+            .or_insert(BTreeMap::new())
+            .insert(instance.sequence, transfer);
+
+        let mut processed = Vec::new();
+        let mut progress = true;
+        while progress {
+            progress = false;
+            let sources: Vec<String> = self.pending.keys().cloned().collect();
+            for source in sources.into_iter() {
+                while let Some(outcome) = self.process_next(id, &source) {
+                    progress = true;
+                    processed.extend(outcome);
+                }
+            }
+        }
+        processed
+    }
+
+    /// Process the next instance of a source, if it is delivered and its dependencies are processed.
+    /// Returns None if the source has to wait.
+    fn process_next(&mut self, id: usize, source: &str) -> Option<Option<(Transfer, bool)>> {
+        let next = *self.next.get(source).unwrap_or(&0);
+        let transfer = self.pending.get(source)?.get(&next)?.clone();
+        if let Some(signed) = &transfer {
+            let waiting = signed
+                .transfer
+                .dependencies
+                .iter()
+                .any(|(account, sequence)| *sequence >= *self.next.get(account).unwrap_or(&0));
+            if waiting {
+                return None;
+            }
+        }
+        self.pending.get_mut(source).unwrap().remove(&next);
+        self.next.insert(source.to_string(), next + 1);
+        if source == self.own {
+            self.prepared.remove(&next);
+        }
+        let signed = match transfer {
+            Some(signed) => signed,
+            None => {
+                return Some(None);
+            }
+        };
+        let outcome = match self.validate(source, next, &signed) {
+            Ok(incoming) => {
+                self.apply(&signed.transfer, incoming);
+                true
+            }
+            Err(reason) => {
+                self.rejected += 1;
+                my_print!(format!(
+                    "{} rejected transfer {} from {} : {}",
+                    id, next, source, reason
+                ));
+                false
+            }
+        };
+        Some(Some((signed.transfer, outcome)))
+    }
+
+    /// Check a transfer against the signature of the owner of its account, its account and its
+    /// dependencies, and return the amount it claims.
+    fn validate(
+        &self,
+        source: &str,
+        sequence: u64,
+        signed: &SignedTransfer,
+    ) -> Result<u64, String> {
+        let transfer = &signed.transfer;
+        if !signed.verify() {
+            return Err(String::from("not signed by the owner of the account"));
+        }
+        if transfer.from != source || transfer.sequence != sequence {
+            return Err(String::from(
+                "broadcast by another account or in another instance",
+            ));
+        }
+        let mut incoming = 0;
+        let mut dependencies = HashSet::new();
+        for dependency in transfer.dependencies.iter() {
+            if !dependencies.insert(dependency) || self.claimed.contains(dependency) {
+                return Err(format!("{:?} is already claimed", dependency));
+            }
+            match self.applied.get(dependency) {
+                Some(incoming_transfer) if incoming_transfer.to == transfer.from => {
+                    incoming += incoming_transfer.amount;
+                }
+                _ => {
+                    return Err(format!("{:?} is not a transfer to the account", dependency));
+                }
+            }
+        }
+        let spendable = self.account(source).spendable + incoming;
+        if transfer.amount > spendable {
+            return Err(format!(
+                "insufficient balance : {} spendable, {} transferred",
+                spendable, transfer.amount
+            ));
+        }
+        Ok(incoming)
+    }
+
+    fn apply(&mut self, transfer: &Transfer, incoming: u64) {
+        for dependency in transfer.dependencies.iter() {
+            self.claimed.insert(dependency.clone());
+        }
+        let mut from = self.account(&transfer.from);
+        from.spendable = from.spendable + incoming - transfer.amount;
+        from.balance -= transfer.amount;
+        self.accounts.insert(transfer.from.clone(), from);
+        let mut to = self.account(&transfer.to);
+        to.balance += transfer.amount;
+        self.accounts.insert(transfer.to.clone(), to);
+        self.applied
+            .insert((transfer.from.clone(), transfer.sequence), transfer.clone());
+    }
+}
+
+/// Asset transfer application : applies the transfers delivered below it to the ledger of the Node,
+/// and passes every Message on to the application.
+pub struct TransferLayer {
+    pub id: usize,
+    pub ledger: Arc<Mutex<Ledger>>,
+    pub upper: Arc<dyn BroadcastLayer>,
+}
+
+#[async_trait]
+impl BroadcastLayer for TransferLayer {
+    async fn deliver(&self, message: Message) {
+        for (transfer, applied) in self.ledger.lock().await.push(self.id, &message) {
+            if applied {
+                my_print!(format!(
+                    "{} applied transfer {} of {} from {} to {}",
+                    self.id, transfer.sequence, transfer.amount, transfer.from, transfer.to
+                ));
+            }
+        }
+        self.upper.deliver(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Instance;
+    use rand::prelude::*;
+
+    fn account(keychain: &KeyChain) -> String {
+        format!("{:?}", keychain.keycard().identity())
+    }
+
+    fn delivery(source: &KeyChain, sequence: u64, content: String) -> Message {
+        let source = source.keycard().identity();
+        Message::with_instance(2, Instance { source, sequence }, content)
+    }
+
+    fn transfer(
+        source: &KeyChain,
+        sequence: u64,
+        to: &KeyChain,
+        amount: u64,
+        dependencies: Vec<TransferId>,
+    ) -> Message {
+        let transfer = Transfer {
+            from: account(source),
+            sequence,
+            to: account(to),
+            amount,
+            dependencies,
+        };
+        let signed = SignedTransfer::new(transfer, source);
+        delivery(source, sequence, serde_json::to_string(&signed).unwrap())
+    }
+
+    #[test]
+    fn transfers_wait_for_their_dependencies() {
+        let (a, b) = (KeyChain::random(), KeyChain::random());
+        let mut ledger = Ledger::new(account(&b), 10);
+        // b spends the 5 received from a on top of its own 10.
+        let spend = transfer(&b, 0, &a, 15, vec![(account(&a), 1)]);
+        assert!(ledger.push(0, &spend).is_empty());
+        assert!(ledger.push(0, &transfer(&a, 1, &b, 5, vec![])).is_empty());
+        // Instance 0 of a is not a transfer, but the instances of a are processed in order.
+        let processed = ledger.push(0, &delivery(&a, 0, String::from("hello")));
+        assert_eq!(processed.len(), 2);
+        assert!(processed.iter().all(|(_, applied)| *applied));
+        assert_eq!(ledger.account(&account(&a)).balance, 20);
+        assert_eq!(ledger.account(&account(&b)).balance, 0);
+        assert_eq!(ledger.account(&account(&b)).spendable, 0);
+    }
+
+    #[test]
+    fn double_spends_are_rejected_in_every_delivery_order() {
+        let (a, b, c) = (KeyChain::random(), KeyChain::random(), KeyChain::random());
+        let messages = vec![
+            transfer(&a, 0, &b, 6, vec![]),
+            // Overspends the initial balance of a.
+            transfer(&a, 1, &c, 6, vec![]),
+            transfer(&b, 0, &c, 12, vec![(account(&a), 0)]),
+            // Claims the transfer from a a second time.
+            transfer(&b, 1, &c, 4, vec![(account(&a), 0)]),
+            // Claims a transfer which was rejected.
+            transfer(&c, 0, &a, 16, vec![(account(&a), 1)]),
+            transfer(&c, 1, &a, 20, vec![(account(&b), 0)]),
+        ];
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let mut shuffled = messages.clone();
+            shuffled.shuffle(&mut rng);
+            let mut ledger = Ledger::new(account(&a), 10);
+            let mut outcomes = Vec::new();
+            for message in shuffled.iter() {
+                outcomes.extend(
+                    ledger
+                        .push(0, message)
+                        .into_iter()
+                        .map(|(t, applied)| ((t.from, t.sequence), applied)),
+                );
+            }
+            outcomes.sort();
+            let mut expected = vec![
+                ((account(&a), 0), true),
+                ((account(&a), 1), false),
+                ((account(&b), 0), true),
+                ((account(&b), 1), false),
+                ((account(&c), 0), false),
+                ((account(&c), 1), true),
+            ];
+            expected.sort();
+            assert_eq!(outcomes, expected);
+            assert_eq!(ledger.rejected, 3);
+            assert_eq!(ledger.account(&account(&a)).balance, 24);
+            assert_eq!(ledger.account(&account(&b)).balance, 4);
+            assert_eq!(ledger.account(&account(&c)).balance, 2);
+        }
+    }
+
+    #[test]
+    fn forged_transfers_are_rejected() {
+        let (a, b) = (KeyChain::random(), KeyChain::random());
+        let mut ledger = Ledger::new(account(&a), 10);
+        let forged = Transfer {
+            from: account(&a),
+            sequence: 0,
+            to: account(&b),
+            amount: 10,
+            dependencies: vec![],
+        };
+        // b broadcasts a transfer from the account of a.
+        let signed = SignedTransfer::new(forged.clone(), &a);
+        let message = delivery(&b, 0, serde_json::to_string(&signed).unwrap());
+        assert_eq!(ledger.push(0, &message), vec![(forged.clone(), false)]);
+        // The instance claims a as its source, but b signs the transfer, with its own KeyCard or
+        // with the KeyCard of a.
+        let mut signed = SignedTransfer::new(forged.clone(), &b);
+        let message = delivery(&a, 0, serde_json::to_string(&signed).unwrap());
+        assert_eq!(ledger.push(0, &message), vec![(forged.clone(), false)]);
+        signed.owner = a.keycard();
+        let message = delivery(&a, 1, serde_json::to_string(&signed).unwrap());
+        assert_eq!(ledger.push(0, &message), vec![(forged.clone(), false)]);
+        // An unsigned transfer is not a transfer.
+        let message = delivery(&a, 2, serde_json::to_string(&forged).unwrap());
+        assert!(ledger.push(0, &message).is_empty());
+        assert_eq!(ledger.rejected, 3);
+        assert_eq!(ledger.account(&account(&a)).balance, 10);
+        assert_eq!(ledger.account(&account(&b)).balance, 10);
+    }
+
+    #[test]
+    fn prepared_transfers_claim_incoming_once() {
+        let (a, b) = (KeyChain::random(), KeyChain::random());
+        let mut ledger = Ledger::new(account(&b), 10);
+        ledger.push(0, &transfer(&a, 0, &b, 5, vec![]));
+        let first = ledger.prepare(account(&a), 12).unwrap();
+        assert_eq!(first.dependencies, vec![(account(&a), 0)]);
+        ledger.reserve(first);
+        assert!(ledger.prepare(account(&a), 4).is_err());
+        let mut second = ledger.prepare(account(&a), 3).unwrap();
+        assert!(second.dependencies.is_empty());
+        second.sequence = 1;
+        ledger.reserve(second);
+        // Once processed, the transfers are no longer reserved.
+        ledger.push(0, &transfer(&b, 0, &a, 12, vec![(account(&a), 0)]));
+        ledger.push(0, &transfer(&b, 1, &a, 3, vec![]));
+        assert_eq!(ledger.account(&account(&b)).spendable, 0);
+        assert!(ledger.prepare(account(&a), 1).is_err());
+    }
+
+    #[test]
+    fn released_transfers_free_their_dependencies() {
+        let (a, b) = (KeyChain::random(), KeyChain::random());
+        let mut ledger = Ledger::new(account(&b), 0);
+        ledger.push(0, &transfer(&a, 0, &b, 5, vec![]));
+        let reserved = ledger.prepare(account(&a), 5).unwrap();
+        ledger.reserve(reserved);
+        assert!(ledger.prepare(account(&a), 5).is_err());
+        // A transfer which could not be broadcast claims nothing.
+        ledger.release(0);
+        let retried = ledger.prepare(account(&a), 5).unwrap();
+        assert_eq!(retried.dependencies, vec![(account(&a), 0)]);
+    }
+}
@@ -32,7 +32,7 @@ pub async fn sample_sieve(
         selected.insert(system[n].identity().clone(), None);
         *selected_duplicates
             .entry(system[n].identity().clone())
-            .or_insert(1) += 1;
+            .or_insert(0) += 1;
     }
     let mut locked_psi = replies.lock().await;
     locked_psi.extend(selected);
@@ -66,7 +66,7 @@ pub async fn sample_contagion(
         selected.insert(system[n].identity().clone(), Vec::new());
         *selected_duplicates
             .entry(system[n].identity().clone())
-            .or_insert(1) += 1;
+            .or_insert(0) += 1;
     }
     let mut locked_psi = replies.lock().await;
     locked_psi.extend(selected);
@@ -205,4 +205,16 @@ mod tests {
             HashMap::from([(String::from("Test1"), 6), (String::from("Test2"), 7)]);
         assert_eq!(res, expected);
     }
+
+    #[tokio::test]
+    async fn sample_weights_are_multiplicities() {
+        let system: Vec<KeyCard> = (0..3)
+            .map(|_| talk::crypto::KeyChain::random().keycard())
+            .collect();
+        let replies = Arc::new(Mutex::new(HashMap::new()));
+        let mut duplicates = HashMap::new();
+        sample_contagion(10, system, &replies, &mut duplicates).await;
+        assert_eq!(duplicates.values().sum::<usize>(), 10);
+        assert_eq!(duplicates.len(), replies.lock().await.len());
+    }
 }